@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use clap::{Parser, Subcommand};
 
 use super::config::CONFIG_FILE_DEFAULT;
+use crate::defs;
 
 #[derive(Parser, Debug)]
 #[command(name = "meta-hybrid", version, about = "Hybrid Mount Metamodule")]
@@ -22,10 +23,27 @@ pub struct Cli {
     pub partitions: Vec<String>,
     #[arg(long = "dry-run")]
     pub dry_run: bool,
+    #[arg(long = "data-root")]
+    pub data_root: Option<PathBuf>,
+    #[arg(long = "system-root")]
+    pub system_root: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl Cli {
+    pub fn config_path(&self) -> PathBuf {
+        if let Some(path) = &self.config {
+            return path.clone();
+        }
+
+        match &self.data_root {
+            Some(root) => root.join(defs::CONFIG_FILE_NAME),
+            None => PathBuf::from(CONFIG_FILE_DEFAULT),
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     GenConfig {
@@ -45,10 +63,36 @@ pub enum Commands {
         #[arg(long)]
         payload: String,
     },
-    Storage,
+    Storage {
+        #[arg(long)]
+        probe: bool,
+        #[command(subcommand)]
+        action: Option<StorageAction>,
+    },
+    Status,
+    Unmount {
+        #[arg(long)]
+        module: Option<String>,
+    },
+    Remount {
+        #[arg(long)]
+        partition: String,
+    },
     Modules,
+    Module {
+        #[command(subcommand)]
+        action: ModuleAction,
+    },
     Conflicts,
     Diagnostics,
+    Verify {
+        #[arg(long)]
+        repair: bool,
+    },
+    Contexts {
+        #[arg(long)]
+        module: Option<String>,
+    },
     #[command(name = "system-action")]
     SystemAction {
         #[arg(long)]
@@ -57,3 +101,22 @@ pub enum Commands {
         value: Option<String>,
     },
 }
+
+#[derive(Subcommand, Debug)]
+pub enum StorageAction {
+    Compact,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ModuleAction {
+    Enable {
+        id: String,
+        #[arg(long)]
+        now: bool,
+    },
+    Disable {
+        id: String,
+        #[arg(long)]
+        now: bool,
+    },
+}
@@ -8,10 +8,13 @@ use serde::Serialize;
 
 use crate::{
     conf::{
-        cli::Cli,
-        config::{CONFIG_FILE_DEFAULT, Config},
+        cli::{Cli, ModuleAction, StorageAction},
+        config::Config,
+    },
+    core::{
+        executor, file_contexts, granary, hotplug, inventory, modules, planner, reconcile, remount,
+        storage, teardown, verify, winnow,
     },
-    core::{executor, granary, inventory, modules, planner, storage, winnow},
     utils,
 };
 
@@ -22,35 +25,43 @@ struct DiagnosticIssueJson {
     message: String,
 }
 
-fn load_config(cli: &Cli) -> Result<Config> {
-    if let Some(config_path) = &cli.config {
-        return Config::from_file(config_path).with_context(|| {
+pub fn load_config(cli: &Cli) -> Result<Config> {
+    let mut config = if let Some(config_path) = &cli.config {
+        Config::from_file(config_path).with_context(|| {
             format!(
                 "Failed to load config from custom path: {}",
                 config_path.display()
             )
-        });
-    }
-
-    match Config::load_default() {
-        Ok(config) => Ok(config),
-        Err(e) => {
-            let is_not_found = e
-                .root_cause()
-                .downcast_ref::<std::io::Error>()
-                .map(|io_err| io_err.kind() == std::io::ErrorKind::NotFound)
-                .unwrap_or(false);
-
-            if is_not_found {
-                Ok(Config::default())
-            } else {
-                Err(e).context(format!(
-                    "Failed to load default config from {}",
-                    CONFIG_FILE_DEFAULT
-                ))
+        })?
+    } else {
+        let config_path = cli.config_path();
+
+        match Config::from_file(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                let is_not_found = e
+                    .root_cause()
+                    .downcast_ref::<std::io::Error>()
+                    .map(|io_err| io_err.kind() == std::io::ErrorKind::NotFound)
+                    .unwrap_or(false);
+
+                if is_not_found {
+                    Config::default()
+                } else {
+                    return Err(e).context(format!(
+                        "Failed to load default config from {}",
+                        config_path.display()
+                    ));
+                }
             }
         }
-    }
+    };
+
+    config.apply_roots(cli.data_root.clone(), cli.system_root.clone());
+
+    file_contexts::init(&config.system_root);
+
+    Ok(config)
 }
 
 pub fn handle_gen_config(output: &Path) -> Result<()> {
@@ -71,7 +82,12 @@ pub fn handle_show_config(cli: &Cli) -> Result<()> {
 
 pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
     if let Ok(old_config) = load_config(cli)
-        && let Err(e) = granary::create_silo(&old_config, "Auto-Backup", "Pre-WebUI Save")
+        && let Err(e) = granary::create_silo(
+            &old_config,
+            &cli.config_path(),
+            "Auto-Backup",
+            "Pre-WebUI Save",
+        )
     {
         tracing::warn!("Failed to create Granary backup: {}", e);
     }
@@ -86,7 +102,7 @@ pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
         serde_json::from_slice(&json_bytes).context("Failed to parse config JSON payload")?;
 
     config
-        .save_to_file(CONFIG_FILE_DEFAULT)
+        .save_to_file(cli.config_path())
         .context("Failed to save config file")?;
 
     println!("Configuration saved successfully.");
@@ -94,7 +110,9 @@ pub fn handle_save_config(cli: &Cli, payload: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
+pub fn handle_save_rules(cli: &Cli, module: &str, payload: &str) -> Result<()> {
+    let config = load_config(cli)?;
+
     utils::validate_module_id(module).with_context(|| format!("Invalid module ID: {}", module))?;
 
     let json_bytes = (0..payload.len())
@@ -106,9 +124,9 @@ pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
     let _: inventory::ModuleRules =
         serde_json::from_slice(&json_bytes).context("Invalid rules JSON")?;
 
-    let rules_dir = std::path::Path::new("/data/adb/meta-hybrid/rules");
+    let rules_dir = config.rules_dir();
 
-    std::fs::create_dir_all(rules_dir).context("Failed to create rules directory")?;
+    std::fs::create_dir_all(&rules_dir).context("Failed to create rules directory")?;
 
     let file_path = rules_dir.join(format!("{}.json", module));
 
@@ -120,8 +138,38 @@ pub fn handle_save_rules(module: &str, payload: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn handle_storage() -> Result<()> {
-    storage::print_status().context("Failed to retrieve storage status")
+pub fn handle_storage(cli: &Cli, probe: bool, action: Option<&StorageAction>) -> Result<()> {
+    let config = load_config(cli)?;
+
+    if probe {
+        return storage::print_probe(&config).context("Failed to probe storage backends");
+    }
+
+    match action {
+        None => storage::print_status(&config).context("Failed to retrieve storage status"),
+        Some(StorageAction::Compact) => {
+            storage::print_compact(&config).context("Failed to compact storage image")
+        }
+    }
+}
+
+pub fn handle_status(cli: &Cli) -> Result<()> {
+    let config = load_config(cli)?;
+
+    reconcile::print_report(&config).context("Failed to reconcile mount status")
+}
+
+pub fn handle_unmount(cli: &Cli, module: Option<&str>) -> Result<()> {
+    let config = load_config(cli)?;
+
+    teardown::print_teardown(&config, module).context("Failed to tear down mount session")
+}
+
+pub fn handle_remount(cli: &Cli, partition: &str) -> Result<()> {
+    let config = load_config(cli)?;
+
+    remount::print_remount(&config, partition)
+        .with_context(|| format!("Failed to remount partition {partition}"))
 }
 
 pub fn handle_modules(cli: &Cli) -> Result<()> {
@@ -130,6 +178,18 @@ pub fn handle_modules(cli: &Cli) -> Result<()> {
     modules::print_list(&config).context("Failed to list modules")
 }
 
+pub fn handle_module(cli: &Cli, action: &ModuleAction) -> Result<()> {
+    let config = load_config(cli)?;
+
+    let (id, enable, now) = match action {
+        ModuleAction::Enable { id, now } => (id, true, *now),
+        ModuleAction::Disable { id, now } => (id, false, *now),
+    };
+
+    hotplug::print_toggle(&config, id, enable, now)
+        .with_context(|| format!("Failed to toggle module {id}"))
+}
+
 pub fn handle_conflicts(cli: &Cli) -> Result<()> {
     let config = load_config(cli)?;
 
@@ -159,7 +219,9 @@ pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     let plan = planner::generate(&config, &module_list, &config.moduledir)
         .context("Failed to generate plan for diagnostics")?;
 
-    let issues = executor::diagnose_plan(&plan);
+    let mut issues = executor::diagnose_modules(&module_list);
+
+    issues.extend(executor::diagnose_plan(&plan));
 
     let json_issues: Vec<DiagnosticIssueJson> = issues
         .into_iter()
@@ -182,12 +244,24 @@ pub fn handle_diagnostics(cli: &Cli) -> Result<()> {
     Ok(())
 }
 
+pub fn handle_verify(cli: &Cli, repair: bool) -> Result<()> {
+    let config = load_config(cli)?;
+
+    verify::print_verify(&config, repair).context("Failed to verify synced modules")
+}
+
+pub fn handle_contexts(cli: &Cli, module: Option<&str>) -> Result<()> {
+    let config = load_config(cli)?;
+
+    file_contexts::print_labels(&config, module).context("Failed to plan SELinux labels")
+}
+
 pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Result<()> {
     let mut config = load_config(cli)?;
 
     match action {
         "granary-list" => {
-            let silos = granary::list_silos()?;
+            let silos = granary::list_silos(&config)?;
 
             let json = serde_json::to_string(&silos)?;
 
@@ -196,13 +270,13 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
         "granary-create" => {
             let reason = value.unwrap_or("Manual Backup");
 
-            granary::create_silo(&config, "Manual Snapshot", reason)?;
+            granary::create_silo(&config, &cli.config_path(), "Manual Snapshot", reason)?;
 
             println!("Silo created.");
         }
         "granary-delete" => {
             if let Some(id) = value {
-                granary::delete_silo(id)?;
+                granary::delete_silo(&config, id)?;
 
                 println!("Silo {} deleted.", id);
             } else {
@@ -211,7 +285,7 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
         }
         "granary-restore" => {
             if let Some(id) = value {
-                granary::restore_silo(id)?;
+                granary::restore_silo(&config, &cli.config_path(), id)?;
 
                 println!("Silo {} restored. Please reboot.", id);
             } else {
@@ -224,11 +298,24 @@ pub fn handle_system_action(cli: &Cli, action: &str, value: Option<&str>) -> Res
             {
                 config.winnowing.set_rule(path, id);
 
-                config.save_to_file(CONFIG_FILE_DEFAULT)?;
+                config.save_to_file(cli.config_path())?;
 
                 println!("Winnowing rule set: {} -> {}", path, id);
             }
         }
+        "winnow-remove" => {
+            if let Some(path) = value {
+                if !config.winnowing.remove_rule(path) {
+                    bail!("No winnowing rule for {}", path);
+                }
+
+                config.save_to_file(cli.config_path())?;
+
+                println!("Winnowing rule removed: {}", path);
+            } else {
+                bail!("Missing winnowing path");
+            }
+        }
         _ => bail!("Unknown action: {}", action),
     }
 
@@ -11,7 +11,7 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 pub const CONFIG_FILE_DEFAULT: &str = "/data/adb/meta-hybrid/config.toml";
-use crate::defs::DEFAULT_HYBRID_MNT_DIR;
+use crate::defs::{self, DEFAULT_HYBRID_MNT_DIR};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct WinnowingTable {
@@ -23,7 +23,21 @@ impl WinnowingTable {
     pub fn get_preferred_module(&self, file_path: &Path) -> Option<String> {
         let path_str = file_path.to_string_lossy().to_string();
 
-        self.rules.get(&path_str).cloned()
+        if let Some(module_id) = self.rules.get(&path_str) {
+            return Some(module_id.clone());
+        }
+
+        let path_parts: Vec<&str> = path_str.split('/').filter(|p| !p.is_empty()).collect();
+
+        self.rules
+            .iter()
+            .filter(|(pattern, _)| pattern_matches(pattern, &path_parts))
+            .max_by(|(a, _), (b, _)| {
+                pattern_specificity(a)
+                    .cmp(&pattern_specificity(b))
+                    .then_with(|| b.cmp(a))
+            })
+            .map(|(_, module_id)| module_id.clone())
     }
 
     pub fn set_rule(&mut self, file_path: &str, module_id: &str) {
@@ -31,12 +45,48 @@ impl WinnowingTable {
             .insert(file_path.to_string(), module_id.to_string());
     }
 
-    #[allow(dead_code)]
-    pub fn remove_rule(&mut self, file_path: &str) {
-        self.rules.remove(file_path);
+    pub fn remove_rule(&mut self, file_path: &str) -> bool {
+        self.rules.remove(file_path).is_some()
+    }
+}
+
+fn pattern_matches(pattern: &str, path_parts: &[&str]) -> bool {
+    let mut pattern_parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+
+    if pattern.ends_with('/') {
+        pattern_parts.push("**");
+    }
+
+    match_parts(&pattern_parts, path_parts)
+}
+
+fn match_parts(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_parts(rest, &path[skip..])),
+        Some((part, rest)) => path.split_first().is_some_and(|(name, path_rest)| {
+            match_component(part.as_bytes(), name.as_bytes()) && match_parts(rest, path_rest)
+        }),
     }
 }
 
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
+
+fn pattern_specificity(pattern: &str) -> usize {
+    pattern
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != "**")
+        .map(|part| part.bytes().filter(|c| *c != b'*' && *c != b'?').count() + 1)
+        .sum()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GranaryConfig {
     #[serde(default = "default_max_backups")]
@@ -69,6 +119,16 @@ pub enum OverlayMode {
     Tmpfs,
     Ext4,
     Erofs,
+    Squashfs,
+    Auto,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErofsCompression {
+    None,
+    #[default]
+    Lz4,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -83,6 +143,10 @@ pub struct Config {
     #[serde(default)]
     pub overlay_mode: OverlayMode,
     #[serde(default)]
+    pub erofs_compression: ErofsCompression,
+    #[serde(default = "default_tmpfs_ram_ratio")]
+    pub tmpfs_ram_ratio: f64,
+    #[serde(default)]
     pub enable_nuke: bool,
     #[serde(default)]
     pub disable_umount: bool,
@@ -96,12 +160,28 @@ pub struct Config {
     pub granary: GranaryConfig,
     #[serde(default = "default_hybrid_mnt_dir")]
     pub hybrid_mnt_dir: String,
+    #[serde(default = "default_data_root")]
+    pub data_root: PathBuf,
+    #[serde(default = "default_system_root")]
+    pub system_root: PathBuf,
 }
 
 fn default_hybrid_mnt_dir() -> String {
     DEFAULT_HYBRID_MNT_DIR.to_string()
 }
 
+fn default_tmpfs_ram_ratio() -> f64 {
+    0.25
+}
+
+fn default_data_root() -> PathBuf {
+    PathBuf::from(defs::DEFAULT_DATA_ROOT)
+}
+
+fn default_system_root() -> PathBuf {
+    PathBuf::from(defs::DEFAULT_SYSTEM_ROOT)
+}
+
 fn default_moduledir() -> PathBuf {
     PathBuf::from("/data/adb/modules/")
 }
@@ -139,6 +219,8 @@ impl Default for Config {
             verbose: false,
             partitions: Vec::new(),
             overlay_mode: OverlayMode::default(),
+            erofs_compression: ErofsCompression::default(),
+            tmpfs_ram_ratio: default_tmpfs_ram_ratio(),
             enable_nuke: false,
             disable_umount: false,
             allow_umount_coexistence: false,
@@ -146,6 +228,8 @@ impl Default for Config {
             winnowing: WinnowingTable::default(),
             granary: GranaryConfig::default(),
             hybrid_mnt_dir: default_hybrid_mnt_dir(),
+            data_root: default_data_root(),
+            system_root: default_system_root(),
         }
     }
 }
@@ -159,10 +243,6 @@ impl Config {
         Ok(config)
     }
 
-    pub fn load_default() -> Result<Self> {
-        Self::from_file(CONFIG_FILE_DEFAULT)
-    }
-
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let content = toml::to_string_pretty(self).context("failed to serialize config")?;
 
@@ -175,6 +255,57 @@ impl Config {
         Ok(())
     }
 
+    pub fn run_dir(&self) -> PathBuf {
+        self.data_root.join(defs::RUN_DIR_NAME)
+    }
+
+    pub fn state_file(&self) -> PathBuf {
+        self.run_dir().join(defs::STATE_FILE_NAME)
+    }
+
+    pub fn mount_journal_file(&self) -> PathBuf {
+        self.run_dir().join(defs::MOUNT_JOURNAL_FILE_NAME)
+    }
+
+    pub fn daemon_log_file(&self) -> PathBuf {
+        self.data_root.join(defs::DAEMON_LOG_FILE_NAME)
+    }
+
+    pub fn modules_img_file(&self) -> PathBuf {
+        self.data_root.join(defs::MODULES_IMG_FILE_NAME)
+    }
+
+    pub fn system_rw_dir(&self) -> PathBuf {
+        self.data_root.join(defs::SYSTEM_RW_DIR_NAME)
+    }
+
+    pub fn rules_dir(&self) -> PathBuf {
+        self.data_root.join(defs::RULES_DIR_NAME)
+    }
+
+    pub fn module_prop_file(&self) -> PathBuf {
+        self.moduledir
+            .join(defs::SELF_MODULE_ID)
+            .join(defs::MODULE_PROP_FILE_NAME)
+    }
+
+    pub fn system_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+
+        self.system_root
+            .join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    pub fn apply_roots(&mut self, data_root: Option<PathBuf>, system_root: Option<PathBuf>) {
+        if let Some(root) = data_root {
+            self.data_root = root;
+        }
+
+        if let Some(root) = system_root {
+            self.system_root = root;
+        }
+    }
+
     pub fn merge_with_cli(
         &mut self,
         moduledir: Option<PathBuf>,
@@ -204,3 +335,83 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(rules: &[(&str, &str)]) -> WinnowingTable {
+        WinnowingTable {
+            rules: rules
+                .iter()
+                .map(|(pattern, id)| (pattern.to_string(), id.to_string()))
+                .collect(),
+        }
+    }
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+
+        pattern_matches(pattern, &parts)
+    }
+
+    #[test]
+    fn components_match_star_and_question_mark() {
+        assert!(match_component(b"hosts", b"hosts"));
+        assert!(match_component(b"h*", b"hosts"));
+        assert!(match_component(b"*.conf", b"alpha.conf"));
+        assert!(match_component(b"*", b""));
+        assert!(match_component(b"host?", b"hosts"));
+        assert!(!match_component(b"host?", b"host"));
+        assert!(!match_component(b"*.conf", b"alpha.xml"));
+    }
+
+    #[test]
+    fn double_star_spans_any_number_of_directories() {
+        assert!(matches("/system/**", "/system"));
+        assert!(matches("/system/**", "/system/etc/hosts"));
+        assert!(matches("/system/**/hosts", "/system/hosts"));
+        assert!(matches("/system/**/hosts", "/system/etc/net/hosts"));
+        assert!(matches("/system/", "/system/etc/hosts"));
+        assert!(!matches("/system/*", "/system/etc/hosts"));
+        assert!(!matches("/system/etc/h*", "/vendor/etc/hosts"));
+    }
+
+    #[test]
+    fn preferred_module_follows_the_most_specific_rule() {
+        let rules = table(&[
+            ("/system/**", "epsilon"),
+            ("/system/etc/h*", "alpha"),
+            ("/system/etc/hosts", "beta"),
+            ("/vendor/", "gamma"),
+        ]);
+
+        assert_eq!(
+            rules.get_preferred_module(Path::new("/system/etc/hosts")),
+            Some("beta".to_string())
+        );
+        assert_eq!(
+            rules.get_preferred_module(Path::new("/system/etc/hosts.deny")),
+            Some("alpha".to_string())
+        );
+        assert_eq!(
+            rules.get_preferred_module(Path::new("/system/app/A.apk")),
+            Some("epsilon".to_string())
+        );
+        assert_eq!(
+            rules.get_preferred_module(Path::new("/vendor/etc/beta.xml")),
+            Some("gamma".to_string())
+        );
+        assert_eq!(rules.get_preferred_module(Path::new("/odm/etc/x")), None);
+    }
+
+    #[test]
+    fn equally_specific_rules_break_ties_by_pattern() {
+        let rules = table(&[("/system/etc/a*", "later"), ("/system/etc/*s", "earlier")]);
+
+        assert_eq!(
+            rules.get_preferred_module(Path::new("/system/etc/aliases")),
+            Some("earlier".to_string())
+        );
+    }
+}
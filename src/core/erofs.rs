@@ -0,0 +1,992 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    conf::config::ErofsCompression,
+    core::image_tree::{self, Content, Entry, FileKind},
+};
+
+const SUPER_OFFSET: u64 = 1024;
+const SUPER_MAGIC: u32 = 0xE0F5_E1E2;
+const SUPER_SIZE: usize = 128;
+const BLOCK_BITS: u8 = 12;
+const BLOCK_SIZE: usize = 1 << BLOCK_BITS;
+const META_BLKADDR: u32 = 1;
+const INODE_SLOT_SIZE: usize = 32;
+const INODE_SIZE: usize = 64;
+const XATTR_HEADER_SIZE: usize = 12;
+const DIRENT_SIZE: usize = 12;
+const MAP_HEADER_SIZE: usize = 16;
+const LCLUSTER_INDEX_SIZE: usize = 8;
+const MAX_PCLUSTER_LCLUSTERS: usize = 16;
+
+const FEATURE_INCOMPAT_ZERO_PADDING: u32 = 0x1;
+
+const LAYOUT_FLAT_PLAIN: u16 = 0;
+const LAYOUT_COMPRESSED_FULL: u16 = 1;
+const LAYOUT_FLAT_INLINE: u16 = 2;
+
+const LCLUSTER_PLAIN: u16 = 0;
+const LCLUSTER_HEAD: u16 = 1;
+const LCLUSTER_NONHEAD: u16 = 2;
+
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_FIFO: u8 = 5;
+const FT_SOCK: u8 = 6;
+const FT_SYMLINK: u8 = 7;
+
+const XATTR_PREFIXES: &[(&str, u8)] = &[
+    ("user.", 1),
+    ("system.posix_acl_access", 2),
+    ("system.posix_acl_default", 3),
+    ("trusted.", 4),
+    ("security.", 6),
+];
+
+#[derive(Clone, Copy)]
+enum Layout {
+    Plain,
+    Inline,
+    Compressed,
+}
+
+struct Node {
+    entry: Entry,
+    file_type: u8,
+    xattrs: Vec<u8>,
+    layout: Layout,
+    nid: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    data_blocks: u64,
+    compressed_files: usize,
+    saved_blocks: u64,
+    skipped_xattrs: usize,
+}
+
+struct Builder {
+    nodes: Vec<Node>,
+    compression: ErofsCompression,
+    stats: Stats,
+}
+
+struct DataWriter {
+    out: BufWriter<File>,
+    next_block: u64,
+}
+
+impl DataWriter {
+    fn pad_block(&mut self, written: u64) -> Result<()> {
+        let padding = written.next_multiple_of(BLOCK_SIZE as u64) - written;
+
+        self.out.write_all(&[0u8; BLOCK_SIZE][..padding as usize])?;
+
+        self.next_block += written.div_ceil(BLOCK_SIZE as u64);
+
+        Ok(())
+    }
+}
+
+fn block_addr(block: u64) -> Result<u32> {
+    u32::try_from(block).context("EROFS image exceeds 32-bit block addressing")
+}
+
+fn file_type_of(kind: FileKind) -> u8 {
+    match kind {
+        FileKind::Regular => FT_REG_FILE,
+        FileKind::Directory => FT_DIR,
+        FileKind::CharDevice => FT_CHRDEV,
+        FileKind::BlockDevice => FT_BLKDEV,
+        FileKind::Fifo => FT_FIFO,
+        FileKind::Socket => FT_SOCK,
+        FileKind::Symlink => FT_SYMLINK,
+    }
+}
+
+fn encode_xattrs(entry: &Entry, skipped: &mut usize) -> Vec<u8> {
+    let mut entries = Vec::new();
+
+    for (name, value) in &entry.xattrs {
+        let Some((prefix, index)) = XATTR_PREFIXES
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_bytes()))
+        else {
+            tracing::debug!(
+                "Skipping unsupported xattr {} on {}",
+                String::from_utf8_lossy(name),
+                entry.path.display()
+            );
+            *skipped += 1;
+            continue;
+        };
+
+        let suffix = &name[prefix.len()..];
+
+        if suffix.len() > u8::MAX as usize || value.len() > u16::MAX as usize {
+            *skipped += 1;
+            continue;
+        }
+
+        entries.push((*index, suffix.to_vec(), value.clone()));
+    }
+
+    if entries.is_empty() {
+        return Vec::new();
+    }
+
+    entries.sort();
+
+    let mut body = vec![0u8; XATTR_HEADER_SIZE];
+
+    for (index, suffix, value) in entries {
+        body.push(suffix.len() as u8);
+        body.push(index);
+        body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        body.extend_from_slice(&suffix);
+        body.extend_from_slice(&value);
+        body.resize(body.len().next_multiple_of(4), 0);
+    }
+
+    body
+}
+
+fn dirent_blocks(names: &[&[u8]]) -> Vec<(usize, usize)> {
+    let mut blocks = Vec::new();
+
+    let (mut count, mut used) = (0, 0);
+
+    for name in names {
+        let needed = DIRENT_SIZE + name.len();
+
+        if count > 0 && used + needed > BLOCK_SIZE {
+            blocks.push((count, used));
+            (count, used) = (0, 0);
+        }
+
+        count += 1;
+        used += needed;
+    }
+
+    blocks.push((count, used));
+
+    blocks
+}
+
+fn pack_dirents(entries: &[(&[u8], u64, u8)]) -> Vec<u8> {
+    let names: Vec<&[u8]> = entries.iter().map(|(name, _, _)| *name).collect();
+
+    let mut data = Vec::new();
+
+    let mut start = 0;
+
+    for (block, (count, _)) in dirent_blocks(&names).into_iter().enumerate() {
+        data.resize(block * BLOCK_SIZE, 0);
+
+        let chunk = &entries[start..start + count];
+
+        let mut nameoff = count * DIRENT_SIZE;
+
+        for (name, nid, file_type) in chunk {
+            data.extend_from_slice(&nid.to_le_bytes());
+            data.extend_from_slice(&(nameoff as u16).to_le_bytes());
+            data.push(*file_type);
+            data.push(0);
+            nameoff += name.len();
+        }
+
+        for (name, _, _) in chunk {
+            data.extend_from_slice(name);
+        }
+
+        start += count;
+    }
+
+    data
+}
+
+fn compress_block(input: &[u8]) -> Option<Vec<u8>> {
+    let compressed = lz4_flex::block::compress(input);
+
+    (compressed.len() <= BLOCK_SIZE).then_some(compressed)
+}
+
+fn compress_pcluster(window: &[u8]) -> Option<(usize, Vec<u8>)> {
+    let lclusters = window.len().div_ceil(BLOCK_SIZE);
+
+    if lclusters < 2 {
+        return None;
+    }
+
+    let take = |count: usize| &window[..(count * BLOCK_SIZE).min(window.len())];
+
+    let mut best = (2, compress_block(take(2))?);
+
+    let mut high = lclusters + 1;
+
+    while best.0 < lclusters {
+        let next = (best.0 * 2).min(lclusters);
+
+        match compress_block(take(next)) {
+            Some(compressed) => best = (next, compressed),
+            None => {
+                high = next;
+                break;
+            }
+        }
+    }
+
+    while high - best.0 > 1 {
+        let mid = (best.0 + high) / 2;
+
+        match compress_block(take(mid)) {
+            Some(compressed) => best = (mid, compressed),
+            None => high = mid,
+        }
+    }
+
+    Some(best)
+}
+
+fn lcluster_index(kind: u16, payload: [u8; 4]) -> [u8; LCLUSTER_INDEX_SIZE] {
+    let mut index = [0u8; LCLUSTER_INDEX_SIZE];
+
+    index[0..2].copy_from_slice(&kind.to_le_bytes());
+    index[4..8].copy_from_slice(&payload);
+
+    index
+}
+
+fn delta_payload(back: usize, ahead: usize) -> [u8; 4] {
+    let mut payload = [0u8; 4];
+
+    payload[0..2].copy_from_slice(&(back as u16).to_le_bytes());
+    payload[2..4].copy_from_slice(&(ahead as u16).to_le_bytes());
+
+    payload
+}
+
+impl Builder {
+    fn dirents(&self, index: usize) -> Vec<(&[u8], u64, u8)> {
+        let Content::Dir { parent, children } = &self.nodes[index].entry.content else {
+            return Vec::new();
+        };
+
+        let mut entries: Vec<(&[u8], u64, u8)> = vec![
+            (b".", self.nodes[index].nid, FT_DIR),
+            (b"..", self.nodes[*parent].nid, FT_DIR),
+        ];
+
+        entries.extend(children.iter().map(|(name, child)| {
+            let node = &self.nodes[*child];
+
+            (name.as_slice(), node.nid, node.file_type)
+        }));
+
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        entries
+    }
+
+    fn plan_record(&mut self, index: usize) -> usize {
+        if let Content::Dir { .. } = self.nodes[index].entry.content {
+            let entries = self.dirents(index);
+
+            let names: Vec<&[u8]> = entries.iter().map(|(name, _, _)| *name).collect();
+
+            let blocks = dirent_blocks(&names);
+
+            let (_, last_used) = blocks[blocks.len() - 1];
+
+            self.nodes[index].entry.size = ((blocks.len() - 1) * BLOCK_SIZE + last_used) as u64;
+        }
+
+        let compress = self.compression == ErofsCompression::Lz4;
+
+        let node = &mut self.nodes[index];
+
+        let base = INODE_SIZE + node.xattrs.len();
+
+        if compress
+            && matches!(node.entry.content, Content::File)
+            && node.entry.size > BLOCK_SIZE as u64
+        {
+            node.layout = Layout::Compressed;
+
+            let lclusters = node.entry.size.div_ceil(BLOCK_SIZE as u64) as usize;
+
+            return base.next_multiple_of(8) + MAP_HEADER_SIZE + lclusters * LCLUSTER_INDEX_SIZE;
+        }
+
+        let tail = (node.entry.size % BLOCK_SIZE as u64) as usize;
+
+        if tail > 0 && base + tail <= BLOCK_SIZE {
+            node.layout = Layout::Inline;
+
+            base + tail
+        } else {
+            node.layout = Layout::Plain;
+
+            base
+        }
+    }
+
+    fn assign_nids(&mut self) -> usize {
+        let mut cursor = 0;
+
+        for index in 0..self.nodes.len() {
+            let size = self.plan_record(index);
+
+            if cursor % BLOCK_SIZE + size.min(BLOCK_SIZE) > BLOCK_SIZE {
+                cursor = cursor.next_multiple_of(BLOCK_SIZE);
+            }
+
+            self.nodes[index].nid = (cursor / INODE_SLOT_SIZE) as u64;
+
+            cursor += size.next_multiple_of(INODE_SLOT_SIZE);
+        }
+
+        cursor
+    }
+
+    fn open_data(&self, index: usize) -> Result<Box<dyn Read>> {
+        let node = &self.nodes[index];
+
+        Ok(match &node.entry.content {
+            Content::File => Box::new(File::open(&node.entry.path)?),
+            Content::Dir { .. } => Box::new(Cursor::new(pack_dirents(&self.dirents(index)))),
+            Content::Symlink(target) => Box::new(Cursor::new(target.clone())),
+            Content::Special(_) => Box::new(io::empty()),
+        })
+    }
+
+    fn write_flat(&self, index: usize, record: &mut [u8], data: &mut DataWriter) -> Result<u32> {
+        let node = &self.nodes[index];
+
+        let tail = match node.layout {
+            Layout::Inline => node.entry.size % BLOCK_SIZE as u64,
+            _ => 0,
+        };
+
+        let body = node.entry.size - tail;
+
+        let start = block_addr(data.next_block)?;
+
+        let mut reader = self.open_data(index)?;
+
+        if io::copy(&mut reader.by_ref().take(body), &mut data.out)? != body {
+            bail!("Content changed while building image");
+        }
+
+        data.pad_block(body)?;
+
+        let inline_at = INODE_SIZE + node.xattrs.len();
+
+        reader.read_exact(&mut record[inline_at..inline_at + tail as usize])?;
+
+        Ok(if body > 0 { start } else { 0 })
+    }
+
+    fn write_compressed(
+        &mut self,
+        index: usize,
+        record: &mut [u8],
+        data: &mut DataWriter,
+    ) -> Result<u32> {
+        let node = &self.nodes[index];
+
+        let size = node.entry.size as usize;
+
+        let lclusters = size.div_ceil(BLOCK_SIZE);
+
+        let table_at = (INODE_SIZE + node.xattrs.len()).next_multiple_of(8) + MAP_HEADER_SIZE;
+
+        let mut reader = self.open_data(index)?;
+
+        let mut window = Vec::with_capacity(MAX_PCLUSTER_LCLUSTERS * BLOCK_SIZE);
+
+        let mut blocks = 0u32;
+
+        let mut lcn = 0;
+
+        while lcn < lclusters {
+            let wanted = (size - lcn * BLOCK_SIZE).min(MAX_PCLUSTER_LCLUSTERS * BLOCK_SIZE);
+
+            let missing = (wanted - window.len()) as u64;
+
+            if reader.by_ref().take(missing).read_to_end(&mut window)? as u64 != missing {
+                bail!("Content changed while building image");
+            }
+
+            let blkaddr = block_addr(data.next_block)?;
+
+            let count = match compress_pcluster(&window) {
+                Some((count, compressed)) => {
+                    data.out
+                        .write_all(&[0u8; BLOCK_SIZE][..BLOCK_SIZE - compressed.len()])?;
+                    data.out.write_all(&compressed)?;
+                    data.pad_block(BLOCK_SIZE as u64)?;
+
+                    count
+                }
+                None => {
+                    let raw = &window[..BLOCK_SIZE.min(window.len())];
+
+                    data.out.write_all(raw)?;
+                    data.pad_block(raw.len() as u64)?;
+
+                    1
+                }
+            };
+
+            blocks += 1;
+
+            for offset in 0..count {
+                let entry = match (offset, count) {
+                    (0, 1) => lcluster_index(LCLUSTER_PLAIN, blkaddr.to_le_bytes()),
+                    (0, _) => lcluster_index(LCLUSTER_HEAD, blkaddr.to_le_bytes()),
+                    _ => lcluster_index(LCLUSTER_NONHEAD, delta_payload(offset, count - offset)),
+                };
+
+                let at = table_at + (lcn + offset) * LCLUSTER_INDEX_SIZE;
+
+                record[at..at + LCLUSTER_INDEX_SIZE].copy_from_slice(&entry);
+            }
+
+            window.drain(..(count * BLOCK_SIZE).min(window.len()));
+
+            lcn += count;
+        }
+
+        self.stats.compressed_files += 1;
+
+        self.stats.saved_blocks += (lclusters - blocks as usize) as u64;
+
+        Ok(blocks)
+    }
+
+    fn write_node(&mut self, index: usize, meta: &mut [u8], data: &mut DataWriter) -> Result<()> {
+        let record = &mut meta[self.nodes[index].nid as usize * INODE_SLOT_SIZE..];
+
+        let first_block = data.next_block;
+
+        let (layout, i_u) = match (&self.nodes[index].entry.content, self.nodes[index].layout) {
+            (Content::Special(rdev), _) => (LAYOUT_FLAT_PLAIN, *rdev),
+            (_, Layout::Compressed) => (
+                LAYOUT_COMPRESSED_FULL,
+                self.write_compressed(index, record, data)?,
+            ),
+            (_, Layout::Inline) => (LAYOUT_FLAT_INLINE, self.write_flat(index, record, data)?),
+            (_, Layout::Plain) => (LAYOUT_FLAT_PLAIN, self.write_flat(index, record, data)?),
+        };
+
+        self.stats.data_blocks += data.next_block - first_block;
+
+        let node = &self.nodes[index];
+
+        let xattr_icount = match node.xattrs.len() {
+            0 => 0,
+            len => u16::try_from((len - XATTR_HEADER_SIZE) / 4 + 1).context("Too many xattrs")?,
+        };
+
+        record[0..2].copy_from_slice(&((layout << 1) | 1).to_le_bytes());
+        record[2..4].copy_from_slice(&xattr_icount.to_le_bytes());
+        let entry = &node.entry;
+
+        record[4..6].copy_from_slice(&(entry.mode as u16).to_le_bytes());
+        record[8..16].copy_from_slice(&entry.size.to_le_bytes());
+        record[16..20].copy_from_slice(&i_u.to_le_bytes());
+        record[20..24].copy_from_slice(&(index as u32 + 1).to_le_bytes());
+        record[24..28].copy_from_slice(&entry.uid.to_le_bytes());
+        record[28..32].copy_from_slice(&entry.gid.to_le_bytes());
+        record[32..40].copy_from_slice(&(entry.mtime.max(0) as u64).to_le_bytes());
+        record[40..44].copy_from_slice(&entry.mtime_nsec.to_le_bytes());
+        record[44..48].copy_from_slice(&entry.nlink.to_le_bytes());
+        record[INODE_SIZE..INODE_SIZE + node.xattrs.len()].copy_from_slice(&node.xattrs);
+
+        Ok(())
+    }
+}
+
+fn superblock(inodes: u64, blocks: u32, compressed: bool) -> [u8; SUPER_SIZE] {
+    let mut sb = [0u8; SUPER_SIZE];
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    sb[0..4].copy_from_slice(&SUPER_MAGIC.to_le_bytes());
+    sb[12] = BLOCK_BITS;
+    sb[16..24].copy_from_slice(&inodes.to_le_bytes());
+    sb[24..32].copy_from_slice(&now.as_secs().to_le_bytes());
+    sb[32..36].copy_from_slice(&now.subsec_nanos().to_le_bytes());
+    sb[36..40].copy_from_slice(&blocks.to_le_bytes());
+    sb[40..44].copy_from_slice(&META_BLKADDR.to_le_bytes());
+    sb[64..75].copy_from_slice(b"meta-hybrid");
+
+    if compressed {
+        sb[80..84].copy_from_slice(&FEATURE_INCOMPAT_ZERO_PADDING.to_le_bytes());
+    }
+
+    sb
+}
+
+pub fn build_image(src_dir: &Path, image_path: &Path, compression: ErofsCompression) -> Result<()> {
+    tracing::info!(
+        "Packing EROFS image: {} ({:?})",
+        image_path.display(),
+        compression
+    );
+
+    let mut stats = Stats::default();
+
+    let nodes = image_tree::scan(src_dir)?
+        .entries
+        .into_iter()
+        .map(|entry| Node {
+            file_type: file_type_of(entry.kind),
+            xattrs: encode_xattrs(&entry, &mut stats.skipped_xattrs),
+            layout: Layout::Plain,
+            nid: 0,
+            entry,
+        })
+        .collect();
+
+    let mut builder = Builder {
+        nodes,
+        compression,
+        stats,
+    };
+
+    let meta_blocks = builder.assign_nids().div_ceil(BLOCK_SIZE);
+
+    let mut meta = vec![0u8; meta_blocks * BLOCK_SIZE];
+
+    let data_start = (META_BLKADDR as usize + meta_blocks) as u64;
+
+    let file = File::create(image_path)
+        .with_context(|| format!("Failed to create {}", image_path.display()))?;
+
+    let mut data = DataWriter {
+        out: BufWriter::new(file),
+        next_block: data_start,
+    };
+
+    data.out
+        .seek(SeekFrom::Start(data_start * BLOCK_SIZE as u64))?;
+
+    for index in 0..builder.nodes.len() {
+        builder
+            .write_node(index, &mut meta, &mut data)
+            .with_context(|| {
+                format!(
+                    "Failed to pack {}",
+                    builder.nodes[index].entry.path.display()
+                )
+            })?;
+    }
+
+    let total_blocks = block_addr(data.next_block)?;
+
+    let mut file = data.out.into_inner().map_err(|e| e.into_error())?;
+
+    file.seek(SeekFrom::Start(META_BLKADDR as u64 * BLOCK_SIZE as u64))?;
+    file.write_all(&meta)?;
+
+    file.seek(SeekFrom::Start(SUPER_OFFSET))?;
+    file.write_all(&superblock(
+        builder.nodes.len() as u64,
+        total_blocks,
+        builder.stats.compressed_files > 0,
+    ))?;
+
+    file.set_len(total_blocks as u64 * BLOCK_SIZE as u64)?;
+    file.sync_all()?;
+
+    let stats = &builder.stats;
+
+    tracing::info!(
+        "EROFS image built: {} inodes, {} blocks ({} metadata, {} data), {} compressed files \
+         saved {} blocks",
+        builder.nodes.len(),
+        total_blocks,
+        meta_blocks,
+        stats.data_blocks,
+        stats.compressed_files,
+        stats.saved_blocks
+    );
+
+    if stats.skipped_xattrs > 0 {
+        tracing::warn!(
+            "Skipped {} xattrs with unsupported namespaces or sizes",
+            stats.skipped_xattrs
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::CString, fs, os::unix::ffi::OsStrExt};
+
+    use super::*;
+
+    struct Image(Vec<u8>);
+
+    impl Image {
+        fn build(src: &Path, compression: ErofsCompression) -> Self {
+            let image = src.with_extension("erofs");
+
+            build_image(src, &image, compression).unwrap();
+
+            Self(fs::read(image).unwrap())
+        }
+
+        fn u16_at(&self, at: usize) -> u16 {
+            u16::from_le_bytes(self.0[at..at + 2].try_into().unwrap())
+        }
+
+        fn u32_at(&self, at: usize) -> u32 {
+            u32::from_le_bytes(self.0[at..at + 4].try_into().unwrap())
+        }
+
+        fn u64_at(&self, at: usize) -> u64 {
+            u64::from_le_bytes(self.0[at..at + 8].try_into().unwrap())
+        }
+
+        fn inode(&self, nid: u64) -> usize {
+            META_BLKADDR as usize * BLOCK_SIZE + nid as usize * INODE_SLOT_SIZE
+        }
+
+        fn layout(&self, nid: u64) -> u16 {
+            (self.u16_at(self.inode(nid)) >> 1) & 0x7
+        }
+
+        fn mode(&self, nid: u64) -> u32 {
+            self.u16_at(self.inode(nid) + 4) as u32
+        }
+
+        fn nlink(&self, nid: u64) -> u32 {
+            self.u32_at(self.inode(nid) + 44)
+        }
+
+        fn xattr_size(&self, nid: u64) -> usize {
+            match self.u16_at(self.inode(nid) + 2) as usize {
+                0 => 0,
+                count => XATTR_HEADER_SIZE + (count - 1) * 4,
+            }
+        }
+
+        fn xattrs(&self, nid: u64) -> Vec<(u8, Vec<u8>, Vec<u8>)> {
+            let start = self.inode(nid) + INODE_SIZE;
+            let end = start + self.xattr_size(nid);
+
+            let mut at = start + XATTR_HEADER_SIZE;
+            let mut entries = Vec::new();
+
+            while at < end {
+                let name_len = self.0[at] as usize;
+                let value_len = self.u16_at(at + 2) as usize;
+                let name = self.0[at + 4..at + 4 + name_len].to_vec();
+                let value = self.0[at + 4 + name_len..at + 4 + name_len + value_len].to_vec();
+
+                entries.push((self.0[at + 1], name, value));
+
+                at = (at + 4 + name_len + value_len).next_multiple_of(4);
+            }
+
+            entries
+        }
+
+        fn data(&self, nid: u64) -> Vec<u8> {
+            let inode = self.inode(nid);
+            let size = self.u64_at(inode + 8) as usize;
+            let raw = self.u32_at(inode + 16) as usize * BLOCK_SIZE;
+
+            match self.layout(nid) {
+                LAYOUT_FLAT_PLAIN => self.0[raw..raw + size].to_vec(),
+                LAYOUT_FLAT_INLINE => {
+                    let body = size - size % BLOCK_SIZE;
+                    let inline_at = inode + INODE_SIZE + self.xattr_size(nid);
+
+                    let mut data = self.0[raw..raw + body].to_vec();
+                    data.extend_from_slice(&self.0[inline_at..inline_at + size % BLOCK_SIZE]);
+                    data
+                }
+                LAYOUT_COMPRESSED_FULL => self.decompress(nid, size),
+                layout => panic!("unexpected layout {layout}"),
+            }
+        }
+
+        fn decompress(&self, nid: u64, size: usize) -> Vec<u8> {
+            let table = (self.inode(nid) + INODE_SIZE + self.xattr_size(nid)).next_multiple_of(8)
+                + MAP_HEADER_SIZE;
+            let index = |lcn: usize| table + lcn * LCLUSTER_INDEX_SIZE;
+            let lclusters = size.div_ceil(BLOCK_SIZE);
+
+            let mut data = Vec::new();
+            let mut lcn = 0;
+
+            while lcn < lclusters {
+                let blkaddr = self.u32_at(index(lcn) + 4) as usize * BLOCK_SIZE;
+                let block = &self.0[blkaddr..blkaddr + BLOCK_SIZE];
+
+                if self.u16_at(index(lcn)) == LCLUSTER_PLAIN {
+                    data.extend_from_slice(&block[..BLOCK_SIZE.min(size - data.len())]);
+                    lcn += 1;
+                    continue;
+                }
+
+                assert_eq!(self.u16_at(index(lcn)), LCLUSTER_HEAD);
+
+                let count = 1
+                    + (lcn + 1..lclusters)
+                        .take_while(|next| self.u16_at(index(*next)) == LCLUSTER_NONHEAD)
+                        .count();
+                let start = block.iter().position(|b| *b != 0).unwrap();
+                let expected = (count * BLOCK_SIZE).min(size - data.len());
+
+                data.extend(lz4_flex::block::decompress(&block[start..], expected).unwrap());
+                lcn += count;
+            }
+
+            data
+        }
+
+        fn dirents(&self, nid: u64) -> Vec<(Vec<u8>, u64, u8)> {
+            let data = self.data(nid);
+            let mut entries = Vec::new();
+
+            for block in data.chunks(BLOCK_SIZE) {
+                let nameoff = |i: usize| {
+                    u16::from_le_bytes([block[i * DIRENT_SIZE + 8], block[i * DIRENT_SIZE + 9]])
+                        as usize
+                };
+                let count = nameoff(0) / DIRENT_SIZE;
+
+                for i in 0..count {
+                    let end = if i + 1 < count {
+                        nameoff(i + 1)
+                    } else {
+                        block.len()
+                    };
+                    let mut name = block[nameoff(i)..end].to_vec();
+
+                    while name.last() == Some(&0) {
+                        name.pop();
+                    }
+
+                    let dirent = &block[i * DIRENT_SIZE..];
+                    let child = u64::from_le_bytes(dirent[..8].try_into().unwrap());
+
+                    entries.push((name, child, dirent[10]));
+                }
+            }
+
+            entries
+        }
+
+        fn lookup(&self, dir: u64, name: &str) -> (u64, u8) {
+            self.dirents(dir)
+                .into_iter()
+                .find(|(entry, _, _)| entry == name.as_bytes())
+                .map(|(_, nid, file_type)| (nid, file_type))
+                .unwrap_or_else(|| panic!("{name} not found"))
+        }
+    }
+
+    fn root_nid(image: &Image) -> u64 {
+        image.u16_at(SUPER_OFFSET as usize + 14) as u64
+    }
+
+    #[test]
+    fn superblock_and_root_inode() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+
+        fs::create_dir_all(src.join("system/etc")).unwrap();
+        fs::write(src.join("system/etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+
+        let image = Image::build(&src, ErofsCompression::None);
+        let sb = SUPER_OFFSET as usize;
+
+        assert_eq!(image.u32_at(sb), SUPER_MAGIC);
+        assert_eq!(image.0[sb + 12], BLOCK_BITS);
+        assert_eq!(image.u64_at(sb + 16), 4);
+        assert_eq!(image.u32_at(sb + 36) as usize * BLOCK_SIZE, image.0.len());
+        assert_eq!(image.u32_at(sb + 40), META_BLKADDR);
+        assert_eq!(image.u32_at(sb + 80), 0);
+
+        let root = root_nid(&image);
+
+        assert_eq!(image.mode(root) & libc::S_IFMT, libc::S_IFDIR);
+        assert_eq!(image.nlink(root), 3);
+        assert_eq!(
+            image.dirents(root),
+            [
+                (b".".to_vec(), root, FT_DIR),
+                (b"..".to_vec(), root, FT_DIR),
+                (b"system".to_vec(), image.lookup(root, "system").0, FT_DIR),
+            ]
+        );
+
+        let (system, _) = image.lookup(root, "system");
+        let (etc, _) = image.lookup(system, "etc");
+
+        assert_eq!(image.lookup(system, "..").0, root);
+        assert_eq!(
+            image.data(image.lookup(etc, "hosts").0),
+            b"127.0.0.1 localhost\n"
+        );
+    }
+
+    #[test]
+    fn dirents_stay_sorted_across_block_boundaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+
+        fs::create_dir_all(&src).unwrap();
+
+        let mut names: Vec<String> = (0..300)
+            .rev()
+            .map(|i| format!("entry-{i:03}-with-a-longer-name"))
+            .collect();
+
+        for name in &names {
+            fs::write(src.join(name), name).unwrap();
+        }
+
+        let image = Image::build(&src, ErofsCompression::None);
+        let root = root_nid(&image);
+
+        assert!(image.u64_at(image.inode(root) + 8) > 2 * BLOCK_SIZE as u64);
+
+        names.sort();
+
+        let listed: Vec<Vec<u8>> = image
+            .dirents(root)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        let expected: Vec<Vec<u8>> = [".", ".."]
+            .into_iter()
+            .chain(names.iter().map(String::as_str))
+            .map(|name| name.as_bytes().to_vec())
+            .collect();
+
+        assert_eq!(listed, expected);
+
+        for name in &names {
+            assert_eq!(image.data(image.lookup(root, name).0), name.as_bytes());
+        }
+    }
+
+    #[test]
+    fn inline_tail_follows_the_inode() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let content: Vec<u8> = (0..BLOCK_SIZE + 904).map(|i| (i % 251) as u8).collect();
+
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("tail.bin"), &content).unwrap();
+        fs::write(src.join("small.txt"), "small\n").unwrap();
+
+        let image = Image::build(&src, ErofsCompression::None);
+        let root = root_nid(&image);
+
+        let (tail, _) = image.lookup(root, "tail.bin");
+        let (small, _) = image.lookup(root, "small.txt");
+
+        assert_eq!(image.layout(tail), LAYOUT_FLAT_INLINE);
+        assert_eq!(image.data(tail), content);
+        assert_eq!(image.layout(small), LAYOUT_FLAT_INLINE);
+        assert_eq!(image.u32_at(image.inode(small) + 16), 0);
+        assert_eq!(image.data(small), b"small\n");
+    }
+
+    #[test]
+    fn lz4_pclusters_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let content = b"meta-hybrid compresses this line\n".repeat(1000);
+
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("big.txt"), &content).unwrap();
+
+        let image = Image::build(&src, ErofsCompression::Lz4);
+        let (big, _) = image.lookup(root_nid(&image), "big.txt");
+
+        assert_eq!(image.layout(big), LAYOUT_COMPRESSED_FULL);
+        assert_eq!(
+            image.u32_at(SUPER_OFFSET as usize + 80),
+            FEATURE_INCOMPAT_ZERO_PADDING
+        );
+        assert_eq!(image.data(big), content);
+        assert!(image.0.len() < content.len());
+    }
+
+    #[test]
+    fn whiteouts_opaque_dirs_and_hardlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let opaque = src.join("opaque");
+        let whiteout = src.join("removed");
+
+        fs::create_dir_all(&opaque).unwrap();
+        fs::write(src.join("first"), "linked\n").unwrap();
+        fs::hard_link(src.join("first"), src.join("second")).unwrap();
+
+        let c_whiteout = CString::new(whiteout.as_os_str().as_bytes()).unwrap();
+        let has_whiteout = unsafe { libc::mknod(c_whiteout.as_ptr(), libc::S_IFCHR, 0) } == 0;
+        let has_opaque = extattr::lsetxattr(
+            &opaque,
+            "trusted.overlay.opaque",
+            b"y",
+            extattr::Flags::empty(),
+        )
+        .is_ok();
+
+        let image = Image::build(&src, ErofsCompression::None);
+        let root = root_nid(&image);
+
+        let (first, first_type) = image.lookup(root, "first");
+        let (second, _) = image.lookup(root, "second");
+
+        assert_eq!(first, second);
+        assert_eq!(first_type, FT_REG_FILE);
+        assert_eq!(image.nlink(first), 2);
+        assert_eq!(image.data(first), b"linked\n");
+
+        if has_whiteout {
+            let (removed, file_type) = image.lookup(root, "removed");
+
+            assert_eq!(file_type, FT_CHRDEV);
+            assert_eq!(image.mode(removed) & libc::S_IFMT, libc::S_IFCHR);
+            assert_eq!(image.u32_at(image.inode(removed) + 16), 0);
+        }
+
+        if has_opaque {
+            let (opaque, _) = image.lookup(root, "opaque");
+
+            assert!(
+                image
+                    .xattrs(opaque)
+                    .contains(&(4, b"overlay.opaque".to_vec(), b"y".to_vec()))
+            );
+        }
+    }
+}
@@ -10,8 +10,11 @@ use walkdir::WalkDir;
 use crate::{
     conf::config,
     core::{
+        inventory::{self, Module},
+        merge,
         planner::MountPlan,
-        storage::{OverlayLayout, StorageHandle},
+        storage::StorageHandle,
+        winnow,
     },
     mount::{magic_mount, overlayfs},
     utils,
@@ -35,16 +38,29 @@ pub struct DiagnosticIssue {
     pub message: String,
 }
 
-fn extract_module_root(partition_path: &Path) -> Option<PathBuf> {
-    partition_path.parent().map(|p| p.to_path_buf())
-}
-
 struct OverlayResult {
     magic_roots: Vec<PathBuf>,
-    fallback_ids: Vec<String>,
+    fallback_layers: Vec<(String, PathBuf)>,
     success_records: Vec<(PathBuf, String)>,
 }
 
+pub fn diagnose_modules(modules: &[Module]) -> Vec<DiagnosticIssue> {
+    let mut issues = Vec::new();
+
+    if let Some(cycle) = inventory::ordering_cycle(modules) {
+        issues.push(DiagnosticIssue {
+            level: DiagnosticLevel::Warning,
+            context: "ordering".to_string(),
+            message: format!(
+                "Module ordering cycle: {} (falling back to priority order)",
+                cycle.join(" -> ")
+            ),
+        });
+    }
+
+    issues
+}
+
 pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
     let mut issues = Vec::new();
 
@@ -65,7 +81,7 @@ pub fn diagnose_plan(plan: &MountPlan) -> Vec<DiagnosticIssue> {
         .iter()
         .flat_map(|op| {
             op.lowerdirs.iter().map(move |path| {
-                let mod_id = utils::extract_module_id(path).unwrap_or_else(|| "unknown".into());
+                let mod_id = plan.layer_owner(path).unwrap_or_else(|| "unknown".into());
 
                 (mod_id, path)
             })
@@ -106,6 +122,8 @@ pub fn execute(
 ) -> Result<ExecutionResult> {
     let mut magic_queue = plan.magic_module_paths.clone();
 
+    let mut magic_scopes = plan.magic_scopes.clone();
+
     let mut global_success_map: HashMap<PathBuf, HashSet<String>> = HashMap::new();
 
     let mut final_overlay_ids = HashSet::new();
@@ -116,57 +134,42 @@ pub fn execute(
 
     tracing::info!(">> Phase 1: OverlayFS Execution...");
 
+    let merge_root = merge::merge_root(config, storage.mode(), &storage.mount_point);
+
+    merge::clear(&merge_root);
+
     let overlay_results: Vec<OverlayResult> = plan
         .overlay_ops
         .iter()
         .map(|op| {
-            let lowerdir_strings: Vec<String> = op
-                .lowerdirs
-                .iter()
-                .map(|p: &PathBuf| p.display().to_string())
-                .collect();
-
-            let (upper_opt, work_opt) = match &storage.layout {
-                OverlayLayout::Contained => {
-                    let rw_root = storage.mount_point.join("overlay_rw");
-                    let part_rw = rw_root.join(&op.partition_name);
-                    let upper = part_rw.join("upperdir");
-                    let work = part_rw.join("workdir");
-
-                    if std::fs::create_dir_all(&work).is_ok()
-                        && std::fs::create_dir_all(&upper).is_ok()
-                    {
-                        (Some(upper), Some(work))
-                    } else {
-                        tracing::warn!("Failed to create overlay directories in contained storage");
-                        (None, None)
-                    }
-                }
-                OverlayLayout::Split { rw_base } | OverlayLayout::Direct { rw_base } => {
-                    let part_rw = rw_base.join(&op.partition_name);
-                    let upper = part_rw.join("upperdir");
-                    let work = part_rw.join("workdir");
-                    if upper.exists() && work.exists() {
-                        (Some(upper), Some(work))
-                    } else {
-                        (None, None)
-                    }
-                }
-            };
+            let (upper_opt, work_opt) = storage
+                .layout()
+                .rw_dirs(&storage.mount_point, &op.partition_name);
 
             tracing::info!(
                 "Mounting {} [OVERLAY] (Layers: {})",
                 op.target,
-                lowerdir_strings.len()
+                op.lowerdirs.len()
             );
 
-            if let Err(e) = overlayfs::overlayfs::mount_overlay(
-                &op.target,
-                &lowerdir_strings,
-                work_opt,
-                upper_opt,
-                &config.mountsource,
-            ) {
+            let mounted = merge::fit_lowerdirs(
+                plan,
+                op,
+                (upper_opt.as_deref(), work_opt.as_deref()),
+                &merge_root,
+            )
+            .and_then(|lowerdir_strings| {
+                overlayfs::overlayfs::mount_overlay(
+                    &op.target,
+                    &lowerdir_strings,
+                    &op.data_layers(),
+                    work_opt,
+                    upper_opt,
+                    &config.mountsource,
+                )
+            });
+
+            if let Err(e) = mounted {
                 tracing::warn!(
                     "OverlayFS failed for {}: {}. Triggering fallback.",
                     op.target,
@@ -175,21 +178,27 @@ pub fn execute(
 
                 let mut local_magic = Vec::new();
 
-                let mut local_fallback_ids = Vec::new();
+                let mut local_fallback_layers = Vec::new();
 
                 for layer_path in &op.lowerdirs {
-                    if let Some(root) = extract_module_root(layer_path) {
-                        local_magic.push(root.clone());
+                    if winnow::is_override_layer(layer_path) {
+                        continue;
+                    }
+
+                    if let Some(id) = plan.layer_owner(layer_path) {
+                        let root = plan.storage_root.join(&id);
 
-                        if let Some(id) = utils::extract_module_id(layer_path) {
-                            local_fallback_ids.push(id);
+                        if let Ok(relative) = layer_path.strip_prefix(&root) {
+                            local_fallback_layers.push((id, relative.to_path_buf()));
                         }
+
+                        local_magic.push(root);
                     }
                 }
 
                 return OverlayResult {
                     magic_roots: local_magic,
-                    fallback_ids: local_fallback_ids,
+                    fallback_layers: local_fallback_layers,
                     success_records: Vec::new(),
                 };
             }
@@ -205,14 +214,18 @@ pub fn execute(
             let mut successes = Vec::new();
 
             for layer_path in &op.lowerdirs {
-                if let Some(root) = extract_module_root(layer_path) {
-                    successes.push((root, op.partition_name.clone()));
+                if winnow::is_override_layer(layer_path) {
+                    continue;
+                }
+
+                if let Some(id) = plan.layer_owner(layer_path) {
+                    successes.push((plan.storage_root.join(id), op.partition_name.clone()));
                 }
             }
 
             OverlayResult {
                 magic_roots: Vec::new(),
-                fallback_ids: Vec::new(),
+                fallback_layers: Vec::new(),
                 success_records: successes,
             }
         })
@@ -221,8 +234,10 @@ pub fn execute(
     for res in overlay_results {
         magic_queue.extend(res.magic_roots);
 
-        for id in res.fallback_ids {
+        for (id, relative) in res.fallback_layers {
             final_overlay_ids.remove(&id);
+
+            magic_scopes.entry(id).or_default().push(relative);
         }
 
         for (root, partition) in res.success_records {
@@ -249,7 +264,7 @@ pub fn execute(
     }
 
     if !magic_queue.is_empty() {
-        let tempdir = utils::select_temp_dir()?;
+        let tempdir = utils::select_temp_dir(&config.system_root, &config.run_dir())?;
         let _ = crate::try_umount::TMPFS.set(tempdir.to_string_lossy().to_string());
 
         tracing::info!(
@@ -266,9 +281,11 @@ pub fn execute(
         if let Err(e) = magic_mount::magic_mount(
             &tempdir,
             module_dir,
+            &config.system_root,
             &config.mountsource,
             &config.partitions,
             magic_need_ids,
+            &magic_scopes,
             !config.disable_umount,
         ) {
             tracing::error!("Magic Mount critical failure: {:#}", e);
@@ -0,0 +1,508 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::{Result, bail};
+use regex_lite::Regex;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::Config,
+    core::{inventory, state::RuntimeState, sync},
+    defs, utils,
+};
+
+static POLICY: OnceLock<FileContexts> = OnceLock::new();
+
+const CONTEXT_NONE: &str = "<<none>>";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelSource {
+    Policy,
+    Live,
+    Parent,
+    Default,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Label {
+    pub context: String,
+    pub source: LabelSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+}
+
+struct Spec {
+    pattern: String,
+    prefix: String,
+    regex: Regex,
+    file_type: Option<char>,
+    context: String,
+    origin: String,
+}
+
+pub struct FileContexts {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    specs: Vec<Spec>,
+}
+
+pub fn init(system_root: &Path) {
+    let _ = POLICY.set(FileContexts::load(system_root));
+}
+
+pub fn policy() -> &'static FileContexts {
+    POLICY.get_or_init(|| FileContexts::load(Path::new("/")))
+}
+
+pub fn file_type(metadata: &fs::Metadata) -> char {
+    let ft = metadata.file_type();
+
+    if ft.is_dir() {
+        'd'
+    } else if ft.is_symlink() {
+        'l'
+    } else if ft.is_char_device() {
+        'c'
+    } else if ft.is_block_device() {
+        'b'
+    } else if ft.is_fifo() {
+        'p'
+    } else if ft.is_socket() {
+        's'
+    } else {
+        '-'
+    }
+}
+
+fn is_meta(c: char) -> bool {
+    matches!(c, '.' | '^' | '$' | '?' | '*' | '+' | '|' | '[' | '(' | '{')
+}
+
+fn has_meta(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if is_meta(c) {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn literal_prefix(pattern: &str) -> String {
+    let mut depth = 0;
+
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => return String::new(),
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+
+    let mut prefix = String::new();
+
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '\\' => match chars.next() {
+                Some(escaped) if !escaped.is_ascii_alphanumeric() => escaped,
+                _ => break,
+            },
+            c if is_meta(c) => break,
+            c => c,
+        };
+
+        if chars
+            .peek()
+            .is_some_and(|next| matches!(next, '?' | '*' | '{'))
+        {
+            break;
+        }
+
+        prefix.push(literal);
+    }
+
+    prefix
+}
+
+fn parse_file_type(field: &str) -> Option<Option<char>> {
+    match field {
+        "--" => Some(Some('-')),
+        "-d" | "-l" | "-c" | "-b" | "-p" | "-s" => Some(field.chars().nth(1)),
+        _ => None,
+    }
+}
+
+impl FileContexts {
+    pub fn load(root: &Path) -> Self {
+        let mut policy = FileContexts {
+            root: root.to_path_buf(),
+            files: Vec::new(),
+            specs: Vec::new(),
+        };
+
+        for candidates in defs::FILE_CONTEXTS_CANDIDATES {
+            let Some(path) = candidates
+                .iter()
+                .map(|c| root.join(c))
+                .find(|p| p.is_file())
+            else {
+                continue;
+            };
+
+            match fs::read_to_string(&path) {
+                Ok(content) => policy.parse(&path, &content),
+                Err(e) => tracing::warn!("Failed to read {}: {}", path.display(), e),
+            }
+
+            policy.files.push(path);
+        }
+
+        let (exact, regex): (Vec<Spec>, Vec<Spec>) = policy
+            .specs
+            .drain(..)
+            .partition(|spec| !has_meta(&spec.pattern));
+
+        policy.specs = regex.into_iter().chain(exact).collect();
+
+        tracing::debug!(
+            "Loaded {} file_contexts specs from {} file(s)",
+            policy.specs.len(),
+            policy.files.len()
+        );
+
+        policy
+    }
+
+    fn parse(&mut self, path: &Path, content: &str) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let mut skipped = 0;
+
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let (pattern, file_type, context) = match fields.as_slice() {
+                [pattern, context] => (*pattern, None, *context),
+                [pattern, kind, context] => match parse_file_type(kind) {
+                    Some(file_type) => (*pattern, file_type, *context),
+                    None => {
+                        skipped += 1;
+                        continue;
+                    }
+                },
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            let Ok(regex) = Regex::new(&format!("^(?:{})$", pattern)) else {
+                skipped += 1;
+                continue;
+            };
+
+            self.specs.push(Spec {
+                pattern: pattern.to_string(),
+                prefix: literal_prefix(pattern),
+                regex,
+                file_type,
+                context: context.to_string(),
+                origin: format!("{}:{}", name, index + 1),
+            });
+        }
+
+        if skipped > 0 {
+            tracing::debug!("Skipped {} unparsable lines in {}", skipped, path.display());
+        }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    pub fn spec_count(&self) -> usize {
+        self.specs.len()
+    }
+
+    pub fn lookup(&self, system_path: &str, file_type: char) -> Option<Label> {
+        let spec = self.specs.iter().rev().find(|spec| {
+            system_path.starts_with(&spec.prefix)
+                && spec.file_type.is_none_or(|t| t == file_type)
+                && spec.regex.is_match(system_path)
+        })?;
+
+        if spec.context == CONTEXT_NONE {
+            return None;
+        }
+
+        Some(Label {
+            context: spec.context.clone(),
+            source: LabelSource::Policy,
+            rule: Some(format!("{} {}", spec.origin, spec.pattern)),
+        })
+    }
+}
+
+pub fn label_for(relative: &Path, file_type: char) -> Label {
+    let policy = policy();
+
+    let system_path = Path::new("/").join(relative);
+
+    if let Some(label) = policy.lookup(&system_path.to_string_lossy(), file_type) {
+        return label;
+    }
+
+    let live_path = policy.root.join(relative);
+
+    if fs::symlink_metadata(&live_path).is_ok() {
+        return Label {
+            context: utils::live_context(&live_path),
+            source: LabelSource::Live,
+            rule: None,
+        };
+    }
+
+    if let Some(parent) = live_path.parent()
+        && parent.exists()
+    {
+        return Label {
+            context: utils::live_context(parent),
+            source: LabelSource::Parent,
+            rule: None,
+        };
+    }
+
+    Label {
+        context: utils::get_context_for_path(&system_path).to_string(),
+        source: LabelSource::Default,
+        rule: None,
+    }
+}
+
+pub fn context_for(path: &Path, relative: &Path) -> String {
+    let file_type = fs::symlink_metadata(path)
+        .map(|m| file_type(&m))
+        .unwrap_or('-');
+
+    label_for(relative, file_type).context
+}
+
+#[derive(Serialize)]
+pub struct LabelDecision {
+    pub module: String,
+    pub path: PathBuf,
+    pub file_type: char,
+    #[serde(flatten)]
+    pub label: Label,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct LabelReport {
+    pub policy_files: Vec<PathBuf>,
+    pub specs: usize,
+    pub decisions: Vec<LabelDecision>,
+}
+
+pub fn plan_labels(config: &Config, module_id: Option<&str>) -> Result<LabelReport> {
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    if let Some(id) = module_id
+        && !modules.iter().any(|m| m.id == id)
+    {
+        bail!("Module not found or not enabled: {}", id);
+    }
+
+    let storage_root = RuntimeState::load(&config.state_file())
+        .ok()
+        .map(|s| s.mount_point)
+        .filter(|p| !p.as_os_str().is_empty() && utils::is_mounted(p));
+
+    let policy = policy();
+
+    let mut report = LabelReport {
+        policy_files: policy.files.clone(),
+        specs: policy.spec_count(),
+        decisions: Vec::new(),
+    };
+
+    for module in modules
+        .iter()
+        .filter(|m| module_id.is_none_or(|id| m.id == id))
+        .filter(|m| m.rules.uses_overlay() && sync::has_content(m))
+    {
+        for entry in WalkDir::new(&module.source_path)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .flatten()
+        {
+            let Ok(relative) = entry.path().strip_prefix(&module.source_path) else {
+                continue;
+            };
+
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+
+            let file_type = file_type(&metadata);
+
+            let current = storage_root
+                .as_ref()
+                .and_then(|root| utils::lgetfilecon(root.join(&module.id).join(relative)).ok());
+
+            report.decisions.push(LabelDecision {
+                module: module.id.clone(),
+                path: Path::new("/").join(relative),
+                file_type,
+                label: label_for(relative, file_type),
+                current,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+pub fn print_labels(config: &Config, module_id: Option<&str>) -> Result<()> {
+    let report = plan_labels(config, module_id)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(content: &str) -> FileContexts {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system/etc/selinux/plat_file_contexts");
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+
+        FileContexts::load(dir.path())
+    }
+
+    fn context(policy: &FileContexts, path: &str, file_type: char) -> Option<String> {
+        policy.lookup(path, file_type).map(|label| label.context)
+    }
+
+    #[test]
+    fn literal_prefix_stops_at_regex_syntax() {
+        assert_eq!(
+            literal_prefix("/system/etc/alpha\\.conf"),
+            "/system/etc/alpha.conf"
+        );
+        assert_eq!(literal_prefix("/system(/.*)?"), "/system");
+        assert_eq!(literal_prefix("/system/bin/sh?"), "/system/bin/s");
+        assert_eq!(literal_prefix("/system|/vendor"), "");
+    }
+
+    #[test]
+    fn parse_skips_comments_and_malformed_lines() {
+        let policy = load(
+            "# comment\n\
+             /system(/.*)?  u:object_r:system_file:s0  # trailing\n\
+             /system/bad  -x  u:object_r:bad_file:s0\n\
+             /system/too many fields here\n\
+             /system/[  u:object_r:broken_file:s0\n\
+             \n",
+        );
+
+        assert_eq!(policy.spec_count(), 1);
+        assert_eq!(policy.files().len(), 1);
+    }
+
+    #[test]
+    fn later_and_exact_specs_win() {
+        let policy = load(
+            "/system(/.*)?               u:object_r:system_file:s0\n\
+             /system/etc/hosts           u:object_r:hosts_file:s0\n\
+             /system/etc(/.*)?           u:object_r:etc_file:s0\n\
+             /system/etc/none\\.conf     <<none>>\n",
+        );
+
+        assert_eq!(
+            context(&policy, "/system/etc/hosts", '-').as_deref(),
+            Some("u:object_r:hosts_file:s0")
+        );
+        assert_eq!(
+            context(&policy, "/system/etc/alpha.conf", '-').as_deref(),
+            Some("u:object_r:etc_file:s0")
+        );
+        assert_eq!(
+            context(&policy, "/system/bin/sh", '-').as_deref(),
+            Some("u:object_r:system_file:s0")
+        );
+        assert_eq!(context(&policy, "/system/etc/none.conf", '-'), None);
+        assert_eq!(context(&policy, "/vendor/etc/hosts", '-'), None);
+    }
+
+    #[test]
+    fn file_type_fields_restrict_matches() {
+        let policy = load(
+            "/system(/.*)?         u:object_r:system_file:s0\n\
+             /system/etc(/.*)?  -d u:object_r:etc_dir:s0\n\
+             /system/bin/sh     -- u:object_r:shell_exec:s0\n",
+        );
+
+        assert_eq!(
+            context(&policy, "/system/etc/init", 'd').as_deref(),
+            Some("u:object_r:etc_dir:s0")
+        );
+        assert_eq!(
+            context(&policy, "/system/etc/init", '-').as_deref(),
+            Some("u:object_r:system_file:s0")
+        );
+        assert_eq!(
+            context(&policy, "/system/bin/sh", '-').as_deref(),
+            Some("u:object_r:shell_exec:s0")
+        );
+        assert_eq!(
+            context(&policy, "/system/bin/sh", 'l').as_deref(),
+            Some("u:object_r:system_file:s0")
+        );
+
+        let label = policy.lookup("/system/bin/sh", '-').unwrap();
+
+        assert_eq!(label.source, LabelSource::Policy);
+        assert_eq!(
+            label.rule.as_deref(),
+            Some("plat_file_contexts:3 /system/bin/sh")
+        );
+    }
+}
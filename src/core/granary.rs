@@ -4,14 +4,14 @@
 use std::{
     fs,
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 
-use crate::{conf::config::Config, defs, utils};
+use crate::{conf::config::Config, utils};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Silo {
@@ -26,14 +26,20 @@ pub struct Silo {
     pub raw_state: Option<String>,
 }
 
-const RATOON_COUNTER_FILE: &str = "/data/adb/meta-hybrid/ratoon_counter";
+const RATOON_COUNTER_FILE_NAME: &str = "ratoon_counter";
 
-const RATOON_RESCUE_NOTICE: &str = "/data/adb/meta-hybrid/rescue_notice";
+const RATOON_RESCUE_NOTICE_NAME: &str = "rescue_notice";
 
-const GRANARY_DIR: &str = "/data/adb/meta-hybrid/granary";
+const GRANARY_DIR_NAME: &str = "granary";
 
-pub fn engage_ratoon_protocol() -> Result<()> {
-    let path = Path::new(RATOON_COUNTER_FILE);
+fn granary_dir(config: &Config) -> PathBuf {
+    config.data_root.join(GRANARY_DIR_NAME)
+}
+
+pub fn engage_ratoon_protocol(config: &Config, config_path: &Path) -> Result<()> {
+    let counter_file = config.data_root.join(RATOON_COUNTER_FILE_NAME);
+
+    let path = counter_file.as_path();
 
     let mut count = 0;
 
@@ -63,7 +69,7 @@ pub fn engage_ratoon_protocol() -> Result<()> {
 
         tracing::warn!(">> Executing emergency rollback from Granary...");
 
-        match restore_latest_silo() {
+        match restore_latest_silo(config, config_path) {
             Ok(silo_id) => {
                 tracing::info!(">> Rollback successful. Resetting counter.");
 
@@ -75,7 +81,8 @@ pub fn engage_ratoon_protocol() -> Result<()> {
                     silo_id
                 );
 
-                if let Err(e) = fs::write(RATOON_RESCUE_NOTICE, notice) {
+                if let Err(e) = fs::write(config.data_root.join(RATOON_RESCUE_NOTICE_NAME), notice)
+                {
                     tracing::warn!("Failed to write rescue notice: {}", e);
                 }
             }
@@ -85,7 +92,7 @@ pub fn engage_ratoon_protocol() -> Result<()> {
                     e
                 );
 
-                disable_all_modules()?;
+                disable_all_modules(config)?;
 
                 // Also reset counter to avoid infinite loop of failing restores
                 let _ = fs::remove_file(path);
@@ -96,8 +103,10 @@ pub fn engage_ratoon_protocol() -> Result<()> {
     Ok(())
 }
 
-pub fn disengage_ratoon_protocol() {
-    let path = Path::new(RATOON_COUNTER_FILE);
+pub fn disengage_ratoon_protocol(config: &Config) {
+    let counter_file = config.data_root.join(RATOON_COUNTER_FILE_NAME);
+
+    let path = counter_file.as_path();
 
     if path.exists() {
         if let Err(e) = fs::remove_file(path) {
@@ -108,8 +117,15 @@ pub fn disengage_ratoon_protocol() {
     }
 }
 
-pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String> {
-    if let Err(e) = fs::create_dir_all(GRANARY_DIR) {
+pub fn create_silo(
+    config: &Config,
+    config_path: &Path,
+    label: &str,
+    reason: &str,
+) -> Result<String> {
+    let granary_dir = granary_dir(config);
+
+    if let Err(e) = fs::create_dir_all(&granary_dir) {
         tracing::warn!("Failed to create granary dir: {}", e);
     }
 
@@ -117,9 +133,9 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
 
     let id = format!("silo_{}", now);
 
-    let raw_config = fs::read_to_string(crate::conf::config::CONFIG_FILE_DEFAULT).ok();
+    let raw_config = fs::read_to_string(config_path).ok();
 
-    let raw_state = fs::read_to_string(crate::defs::STATE_FILE).ok();
+    let raw_state = fs::read_to_string(config.state_file()).ok();
 
     let silo = Silo {
         id: id.clone(),
@@ -131,7 +147,7 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
         raw_state,
     };
 
-    let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
+    let file_path = granary_dir.join(format!("{}.json", id));
 
     let json = serde_json::to_string_pretty(&silo)?;
 
@@ -144,14 +160,16 @@ pub fn create_silo(config: &Config, label: &str, reason: &str) -> Result<String>
     Ok(id)
 }
 
-pub fn list_silos() -> Result<Vec<Silo>> {
+pub fn list_silos(config: &Config) -> Result<Vec<Silo>> {
     let mut silos = Vec::new();
 
-    if !Path::new(GRANARY_DIR).exists() {
+    let granary_dir = granary_dir(config);
+
+    if !granary_dir.exists() {
         return Ok(silos);
     }
 
-    for entry in fs::read_dir(GRANARY_DIR)? {
+    for entry in fs::read_dir(&granary_dir)? {
         let entry = entry?;
 
         let path = entry.path();
@@ -170,8 +188,8 @@ pub fn list_silos() -> Result<Vec<Silo>> {
     Ok(silos)
 }
 
-pub fn delete_silo(id: &str) -> Result<()> {
-    let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
+pub fn delete_silo(config: &Config, id: &str) -> Result<()> {
+    let file_path = granary_dir(config).join(format!("{}.json", id));
 
     if file_path.exists() {
         fs::remove_file(&file_path)?;
@@ -184,8 +202,8 @@ pub fn delete_silo(id: &str) -> Result<()> {
     }
 }
 
-pub fn restore_silo(id: &str) -> Result<()> {
-    let file_path = Path::new(GRANARY_DIR).join(format!("{}.json", id));
+pub fn restore_silo(config: &Config, config_path: &Path, id: &str) -> Result<()> {
+    let file_path = granary_dir(config).join(format!("{}.json", id));
 
     if !file_path.exists() {
         bail!("Silo {} not found", id);
@@ -200,19 +218,19 @@ pub fn restore_silo(id: &str) -> Result<()> {
     if let Some(raw) = &silo.raw_config {
         tracing::info!(">> Restoring config from RAW content (preserving comments)...");
 
-        utils::atomic_write(crate::conf::config::CONFIG_FILE_DEFAULT, raw)?;
+        utils::atomic_write(config_path, raw)?;
     } else {
         tracing::info!(">> Raw config missing, restoring from struct snapshot...");
 
         let toml_str = toml::to_string(&silo.config_snapshot)?;
 
-        utils::atomic_write(crate::conf::config::CONFIG_FILE_DEFAULT, toml_str)?;
+        utils::atomic_write(config_path, toml_str)?;
     }
 
     if let Some(state) = &silo.raw_state {
         tracing::info!(">> Restoring state from snapshot...");
 
-        utils::atomic_write(crate::defs::STATE_FILE, state)?;
+        utils::atomic_write(config.state_file(), state)?;
     } else {
         tracing::warn!(">> No state snapshot found in this Silo. Skipping state restore.");
     }
@@ -220,11 +238,11 @@ pub fn restore_silo(id: &str) -> Result<()> {
     Ok(())
 }
 
-fn restore_latest_silo() -> Result<String> {
-    let silos = list_silos()?;
+fn restore_latest_silo(config: &Config, config_path: &Path) -> Result<String> {
+    let silos = list_silos(config)?;
 
     if let Some(latest) = silos.first() {
-        restore_silo(&latest.id)?;
+        restore_silo(config, config_path, &latest.id)?;
 
         Ok(latest.id.clone())
     } else {
@@ -233,7 +251,7 @@ fn restore_latest_silo() -> Result<String> {
 }
 
 fn prune_silos(config: &Config) -> Result<()> {
-    let silos = list_silos()?;
+    let silos = list_silos(config)?;
 
     let max_count = config.granary.max_backups;
 
@@ -261,7 +279,7 @@ fn prune_silos(config: &Config) -> Result<()> {
         }
 
         if should_delete {
-            let path = Path::new(GRANARY_DIR).join(format!("{}.json", silo.id));
+            let path = granary_dir(config).join(format!("{}.json", silo.id));
 
             if let Err(e) = fs::remove_file(&path) {
                 tracing::warn!("Failed to delete old silo {}: {}", silo.id, e);
@@ -278,8 +296,8 @@ fn prune_silos(config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn disable_all_modules() -> Result<()> {
-    let modules_dir = Path::new(defs::MODULES_DIR);
+fn disable_all_modules(config: &Config) -> Result<()> {
+    let modules_dir = config.moduledir.as_path();
 
     if modules_dir.exists() {
         for entry in fs::read_dir(modules_dir)? {
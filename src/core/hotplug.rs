@@ -0,0 +1,285 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        inventory::{Module, ModuleRules},
+        planner,
+        remount::{self, RemountReport},
+        state::RuntimeState,
+        storage,
+        sync::{self, SyncReport},
+        teardown,
+    },
+    defs,
+    mount::{journal, magic_mount, record},
+    utils,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyStatus {
+    Applied,
+    PendingReboot,
+}
+
+#[derive(Serialize)]
+pub struct ToggleReport {
+    pub id: String,
+    pub enabled: bool,
+    pub status: ApplyStatus,
+    pub reasons: Vec<String>,
+    pub partitions: Vec<RemountReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub synced: Vec<SyncReport>,
+}
+
+struct Contributions {
+    overlay: Vec<String>,
+    magic_scope: Vec<PathBuf>,
+}
+
+fn contributions(config: &Config, module: &Module) -> Contributions {
+    let mut partitions: Vec<&str> = defs::BUILTIN_PARTITIONS.to_vec();
+    partitions.extend(config.partitions.iter().map(String::as_str));
+
+    let Some(contrib) = planner::contribution(module, &module.source_path, &partitions) else {
+        return Contributions {
+            overlay: Vec::new(),
+            magic_scope: Vec::new(),
+        };
+    };
+
+    let mut overlay: Vec<String> = contrib
+        .overlays
+        .iter()
+        .filter_map(|(key, _)| key.split('/').next().map(str::to_string))
+        .collect();
+    overlay.sort();
+    overlay.dedup();
+
+    Contributions {
+        overlay,
+        magic_scope: contrib.magic_scope,
+    }
+}
+
+fn set_disable_flag(module_path: &Path, enable: bool) -> Result<()> {
+    let flag = module_path.join(defs::DISABLE_FILE_NAME);
+
+    if enable {
+        if flag.exists() {
+            fs::remove_file(&flag)
+                .with_context(|| format!("Failed to remove {}", flag.display()))?;
+        }
+    } else if !flag.exists() {
+        fs::File::create(&flag).with_context(|| format!("Failed to create {}", flag.display()))?;
+    }
+
+    Ok(())
+}
+
+fn mount_magic(config: &Config, storage_root: &Path, id: &str, scope: Vec<PathBuf>) -> Result<()> {
+    let tempdir = utils::select_temp_dir(&config.system_root, &config.run_dir())?;
+
+    if !tempdir.exists() {
+        fs::create_dir_all(&tempdir)?;
+    }
+
+    magic_mount::magic_mount(
+        &tempdir,
+        storage_root,
+        &config.system_root,
+        &config.mountsource,
+        &config.partitions,
+        HashSet::from([id.to_string()]),
+        &HashMap::from([(id.to_string(), scope)]),
+        !config.disable_umount,
+    )
+}
+
+fn apply_live(
+    config: &Config,
+    module_path: &Path,
+    id: &str,
+    enable: bool,
+    report: &mut ToggleReport,
+) -> Result<()> {
+    let state = RuntimeState::load(&config.state_file())?;
+
+    let storage_root = state.mount_point.clone();
+
+    if storage_root.as_os_str().is_empty() || !utils::is_mounted(&storage_root) {
+        report
+            .reasons
+            .push("storage is not mounted in this session".to_string());
+        return Ok(());
+    }
+
+    if enable
+        && [defs::REMOVE_FILE_NAME, defs::SKIP_MOUNT_FILE_NAME]
+            .iter()
+            .any(|f| module_path.join(f).exists())
+    {
+        report
+            .reasons
+            .push("module is marked for removal or skip_mount".to_string());
+        return Ok(());
+    }
+
+    let module = Module {
+        id: id.to_string(),
+        source_path: module_path.to_path_buf(),
+        rules: ModuleRules::load(module_path, id, &config.rules_dir()),
+    };
+
+    let contrib = contributions(config, &module);
+
+    let read_only = state.storage_mode.is_read_only();
+
+    if enable && read_only && !contrib.overlay.is_empty() {
+        report.reasons.push(format!(
+            "{} storage is read-only, module content cannot be added live",
+            state.storage_mode
+        ));
+        return Ok(());
+    }
+
+    let _ = record::STORAGE_ROOT.set(storage_root.clone());
+
+    if let Err(e) = journal::resume(&config.mount_journal_file()) {
+        tracing::warn!("Failed to open mount journal: {:#}", e);
+    }
+
+    if enable && !read_only {
+        if let Err(e) = storage::ensure_live_capacity(config, &state) {
+            tracing::warn!("Failed to grow {} storage: {:#}", state.storage_mode, e);
+        }
+
+        report.synced = sync::sync_modules(std::slice::from_ref(&module), &storage_root);
+
+        report.synced.retain(|r| !r.changes.is_empty());
+    }
+
+    for part in &contrib.overlay {
+        match remount::remount_partition(config, part) {
+            Ok(r) => report.partitions.push(r),
+            Err(e) => report
+                .reasons
+                .push(format!("remount of {part} failed: {e:#}")),
+        }
+    }
+
+    let has_magic = !contrib.magic_scope.is_empty();
+
+    if has_magic {
+        if enable {
+            if let Err(e) = mount_magic(config, &storage_root, id, contrib.magic_scope) {
+                report.reasons.push(format!("magic mount failed: {e:#}"));
+            }
+        } else {
+            let torn = teardown::teardown(config, Some(id))?;
+
+            report.reasons.extend(
+                torn.skipped
+                    .iter()
+                    .filter(|s| s.reason != "not mounted")
+                    .map(|s| format!("{}: {}", s.target.display(), s.reason)),
+            );
+            report.reasons.extend(
+                torn.failed
+                    .iter()
+                    .map(|f| format!("failed to unmount {}: {}", f.target.display(), f.error)),
+            );
+        }
+    }
+
+    let mut state = RuntimeState::load(&config.state_file())?;
+
+    state.magic_modules.retain(|m| m != id);
+
+    if enable && has_magic && report.reasons.is_empty() {
+        state.magic_modules.push(id.to_string());
+        state.magic_modules.sort();
+    }
+
+    state.save(&config.state_file())?;
+
+    if !enable && !read_only && report.reasons.is_empty() {
+        let still_used: BTreeSet<String> = record::load(&config.mount_journal_file())
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|r| r.modules)
+            .collect();
+
+        let stored = storage_root.join(id);
+
+        if !still_used.contains(id)
+            && stored.exists()
+            && let Err(e) = fs::remove_dir_all(&stored)
+        {
+            tracing::warn!("Failed to prune storage for {}: {}", id, e);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn set_module_enabled(
+    config: &Config,
+    id: &str,
+    enable: bool,
+    now: bool,
+) -> Result<ToggleReport> {
+    utils::validate_module_id(id)?;
+
+    let module_path = config.moduledir.join(id);
+
+    if !module_path.is_dir() {
+        bail!("Module not found: {}", id);
+    }
+
+    set_disable_flag(&module_path, enable)?;
+
+    let mut report = ToggleReport {
+        id: id.to_string(),
+        enabled: enable,
+        status: ApplyStatus::PendingReboot,
+        reasons: Vec::new(),
+        partitions: Vec::new(),
+        synced: Vec::new(),
+    };
+
+    if !now {
+        report
+            .reasons
+            .push("live apply not requested (use --now)".to_string());
+        return Ok(report);
+    }
+
+    apply_live(config, &module_path, id, enable, &mut report)?;
+
+    if report.reasons.is_empty() {
+        report.status = ApplyStatus::Applied;
+    }
+
+    Ok(report)
+}
+
+pub fn print_toggle(config: &Config, id: &str, enable: bool, now: bool) -> Result<()> {
+    let report = set_module_enabled(config, id, enable, now)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs, io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use extattr::{lgetxattr, llistxattr};
+
+const PROBED_XATTRS: &[&str] = &["security.selinux", "security.capability"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+pub enum Content {
+    Dir {
+        parent: usize,
+        children: Vec<(Vec<u8>, usize)>,
+    },
+    File,
+    Symlink(Vec<u8>),
+    Special(u32),
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub content: Content,
+    pub kind: FileKind,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub size: u64,
+    pub nlink: u32,
+    pub xattrs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Default)]
+pub struct SourceTree {
+    pub entries: Vec<Entry>,
+    hardlinks: HashMap<(u64, u64), usize>,
+}
+
+pub fn scan(root: &Path) -> Result<SourceTree> {
+    let mut tree = SourceTree::default();
+
+    tree.scan(root, 0)?;
+
+    Ok(tree)
+}
+
+fn encode_rdev(rdev: u64) -> u32 {
+    let major = libc::major(rdev as libc::dev_t);
+    let minor = libc::minor(rdev as libc::dev_t);
+
+    (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12)
+}
+
+fn kind_of(ft: &fs::FileType) -> FileKind {
+    if ft.is_dir() {
+        FileKind::Directory
+    } else if ft.is_symlink() {
+        FileKind::Symlink
+    } else if ft.is_char_device() {
+        FileKind::CharDevice
+    } else if ft.is_block_device() {
+        FileKind::BlockDevice
+    } else if ft.is_fifo() {
+        FileKind::Fifo
+    } else if ft.is_socket() {
+        FileKind::Socket
+    } else {
+        FileKind::Regular
+    }
+}
+
+fn read_xattrs(path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let Ok(mut names) = llistxattr(path) else {
+        return Vec::new();
+    };
+
+    for probe in PROBED_XATTRS {
+        if !names.iter().any(|name| name == probe) {
+            names.push(OsString::from(probe));
+        }
+    }
+
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let value = lgetxattr(path, &name).ok()?;
+
+            Some((name.as_bytes().to_vec(), value))
+        })
+        .collect()
+}
+
+impl SourceTree {
+    fn scan(&mut self, path: &Path, parent: usize) -> Result<usize> {
+        let meta = fs::symlink_metadata(path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?;
+
+        let ft = meta.file_type();
+
+        if !ft.is_dir()
+            && meta.nlink() > 1
+            && let Some(&index) = self.hardlinks.get(&(meta.dev(), meta.ino()))
+        {
+            self.entries[index].nlink += 1;
+
+            return Ok(index);
+        }
+
+        let content = if ft.is_dir() {
+            Content::Dir {
+                parent,
+                children: Vec::new(),
+            }
+        } else if ft.is_symlink() {
+            Content::Symlink(fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+        } else if ft.is_file() {
+            Content::File
+        } else {
+            Content::Special(encode_rdev(meta.rdev()))
+        };
+
+        let size = match &content {
+            Content::File => meta.len(),
+            Content::Symlink(target) => target.len() as u64,
+            _ => 0,
+        };
+
+        let index = self.entries.len();
+
+        self.entries.push(Entry {
+            path: path.to_path_buf(),
+            content,
+            kind: kind_of(&ft),
+            mode: meta.mode(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec() as u32,
+            size,
+            nlink: 1,
+            xattrs: read_xattrs(path),
+        });
+
+        if !ft.is_dir() {
+            if meta.nlink() > 1 {
+                self.hardlinks.insert((meta.dev(), meta.ino()), index);
+            }
+
+            return Ok(index);
+        }
+
+        let mut names: Vec<_> = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<io::Result<_>>()?;
+
+        names.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+        let mut children = Vec::with_capacity(names.len());
+
+        for name in names {
+            let child = self.scan(&path.join(&name), index)?;
+
+            children.push((name.as_bytes().to_vec(), child));
+        }
+
+        let subdirs = children
+            .iter()
+            .filter(|(_, child)| self.entries[*child].kind == FileKind::Directory)
+            .count();
+
+        self.entries[index].nlink = 2 + subdirs as u32;
+
+        if let Content::Dir { children: slot, .. } = &mut self.entries[index].content {
+            *slot = children;
+        }
+
+        Ok(index)
+    }
+}
@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
@@ -28,10 +29,16 @@ pub struct ModuleRules {
     pub default_mode: MountMode,
     #[serde(default)]
     pub paths: HashMap<String, MountMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub before: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
 }
 
 impl ModuleRules {
-    pub fn load(module_dir: &Path, module_id: &str) -> Self {
+    pub fn load(module_dir: &Path, module_id: &str, user_rules_dir: &Path) -> Self {
         let mut rules = ModuleRules::default();
 
         let internal_config = module_dir.join("hybrid_rules.json");
@@ -48,8 +55,6 @@ impl ModuleRules {
             }
         }
 
-        let user_rules_dir = Path::new("/data/adb/meta-hybrid/rules");
-
         let user_config = user_rules_dir.join(format!("{}.json", module_id));
 
         if user_config.exists() {
@@ -59,6 +64,14 @@ impl ModuleRules {
                         rules.default_mode = user_rules.default_mode;
 
                         rules.paths.extend(user_rules.paths);
+
+                        if user_rules.priority.is_some() {
+                            rules.priority = user_rules.priority;
+                        }
+
+                        rules.before.extend(user_rules.before);
+
+                        rules.after.extend(user_rules.after);
                     }
                     Err(e) => {
                         tracing::warn!("Failed to parse user rules for '{}': {}", module_id, e)
@@ -78,7 +91,34 @@ impl ModuleRules {
             return mode.clone();
         }
 
-        self.default_mode.clone()
+        Path::new(relative_path.trim_matches('/'))
+            .ancestors()
+            .find_map(|prefix| {
+                self.paths
+                    .iter()
+                    .find(|(key, _)| Path::new(key.trim_matches('/')) == prefix)
+                    .map(|(_, mode)| mode.clone())
+            })
+            .unwrap_or_else(|| self.default_mode.clone())
+    }
+
+    pub fn has_rules_below(&self, relative_path: &str) -> bool {
+        let base = Path::new(relative_path.trim_matches('/'));
+
+        self.paths.keys().any(|key| {
+            let key = Path::new(key.trim_matches('/'));
+
+            key != base && key.starts_with(base)
+        })
+    }
+
+    pub fn uses_overlay(&self) -> bool {
+        self.default_mode == MountMode::Overlay
+            || self.paths.values().any(|mode| *mode == MountMode::Overlay)
+    }
+
+    pub fn effective_priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
     }
 }
 
@@ -89,14 +129,16 @@ pub struct Module {
     pub rules: ModuleRules,
 }
 
-pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>> {
+pub fn scan(source_dir: &Path, config: &config::Config) -> Result<Vec<Module>> {
     if !source_dir.exists() {
         return Ok(Vec::new());
     }
 
+    let user_rules_dir = config.rules_dir();
+
     let dir_entries = fs::read_dir(source_dir)?.collect::<std::io::Result<Vec<_>>>()?;
 
-    let mut modules: Vec<Module> = dir_entries
+    let modules: Vec<Module> = dir_entries
         .into_par_iter()
         .filter_map(|entry| {
             let path = entry.path();
@@ -121,7 +163,7 @@ pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>>
                 return None;
             }
 
-            let rules = ModuleRules::load(&path, &id);
+            let rules = ModuleRules::load(&path, &id, &user_rules_dir);
 
             Some(Module {
                 id,
@@ -131,7 +173,194 @@ pub fn scan(source_dir: &Path, _config: &config::Config) -> Result<Vec<Module>>
         })
         .collect();
 
-    modules.sort_by(|a, b| b.id.cmp(&a.id));
+    let (order, cycle) = resolve_order(&modules);
+
+    if let Some(cycle) = cycle {
+        tracing::error!(
+            "Module ordering cycle detected: {}. Falling back to priority order.",
+            cycle.join(" -> ")
+        );
+    }
+
+    let mut slots: Vec<Option<Module>> = modules.into_iter().map(Some).collect();
+
+    Ok(order.into_iter().filter_map(|i| slots[i].take()).collect())
+}
+
+pub fn ordering_cycle(modules: &[Module]) -> Option<Vec<String>> {
+    resolve_order(modules).1
+}
+
+fn resolve_order(modules: &[Module]) -> (Vec<usize>, Option<Vec<String>>) {
+    let index: HashMap<&str, usize> = modules
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.id.as_str(), i))
+        .collect();
+
+    let mut successors: Vec<Vec<usize>> = vec![Vec::new(); modules.len()];
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); modules.len()];
+
+    for (i, module) in modules.iter().enumerate() {
+        let edges = module
+            .rules
+            .after
+            .iter()
+            .filter_map(|id| index.get(id.as_str()).map(|&j| (j, i)))
+            .chain(
+                module
+                    .rules
+                    .before
+                    .iter()
+                    .filter_map(|id| index.get(id.as_str()).map(|&j| (i, j))),
+            );
+
+        for (from, to) in edges {
+            if from != to && !successors[from].contains(&to) {
+                successors[from].push(to);
+                predecessors[to].push(from);
+            }
+        }
+    }
+
+    let key = |i: usize| {
+        Reverse((
+            modules[i].rules.effective_priority(),
+            modules[i].id.as_str(),
+        ))
+    };
+
+    let mut indegree: Vec<usize> = predecessors.iter().map(Vec::len).collect();
+
+    let mut ready: BinaryHeap<_> = (0..modules.len())
+        .filter(|&i| indegree[i] == 0)
+        .map(|i| (key(i), i))
+        .collect();
+
+    let mut load_order = Vec::with_capacity(modules.len());
+
+    while let Some((_, i)) = ready.pop() {
+        load_order.push(i);
+
+        for &next in &successors[i] {
+            indegree[next] -= 1;
+
+            if indegree[next] == 0 {
+                ready.push((key(next), next));
+            }
+        }
+    }
+
+    let mut cycle = None;
+
+    if load_order.len() < modules.len() {
+        let placed: HashSet<usize> = load_order.iter().copied().collect();
+
+        let mut remaining: Vec<usize> =
+            (0..modules.len()).filter(|i| !placed.contains(i)).collect();
+
+        cycle = find_cycle(&remaining, &predecessors, &placed)
+            .map(|c| c.into_iter().map(|i| modules[i].id.clone()).collect());
+
+        remaining.sort_by_key(|&i| (modules[i].rules.effective_priority(), &modules[i].id));
+
+        load_order.extend(remaining);
+    }
+
+    load_order.reverse();
+
+    (load_order, cycle)
+}
+
+fn find_cycle(
+    remaining: &[usize],
+    predecessors: &[Vec<usize>],
+    placed: &HashSet<usize>,
+) -> Option<Vec<usize>> {
+    let mut current = *remaining.first()?;
+
+    let mut path = Vec::new();
+
+    loop {
+        if let Some(pos) = path.iter().position(|&i| i == current) {
+            let mut cycle: Vec<usize> = path[pos..].to_vec();
+
+            cycle.reverse();
+
+            cycle.push(cycle[0]);
+
+            return Some(cycle);
+        }
+
+        path.push(current);
+
+        current = *predecessors[current].iter().find(|p| !placed.contains(p))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(id: &str, priority: Option<i32>, before: &[&str], after: &[&str]) -> Module {
+        Module {
+            id: id.to_string(),
+            source_path: PathBuf::from("/data/adb/modules").join(id),
+            rules: ModuleRules {
+                priority,
+                before: before.iter().map(|s| s.to_string()).collect(),
+                after: after.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn order(modules: &[Module]) -> (Vec<&str>, Option<Vec<String>>) {
+        let (order, cycle) = resolve_order(modules);
+
+        (
+            order.into_iter().map(|i| modules[i].id.as_str()).collect(),
+            cycle,
+        )
+    }
+
+    #[test]
+    fn higher_priority_modules_take_precedence() {
+        let modules = [
+            module("alpha", None, &[], &[]),
+            module("beta", Some(5), &[], &[]),
+            module("gamma", None, &[], &[]),
+        ];
 
-    Ok(modules)
+        assert_eq!(order(&modules), (vec!["beta", "gamma", "alpha"], None));
+    }
+
+    #[test]
+    fn before_and_after_override_priority() {
+        let modules = [
+            module("alpha", None, &[], &["beta"]),
+            module("beta", Some(10), &[], &[]),
+            module("gamma", Some(20), &["alpha"], &["missing"]),
+        ];
+
+        assert_eq!(order(&modules), (vec!["alpha", "gamma", "beta"], None));
+    }
+
+    #[test]
+    fn cycles_are_reported_and_fall_back_to_priority() {
+        let modules = [
+            module("alpha", None, &[], &["beta"]),
+            module("beta", Some(1), &[], &["alpha"]),
+            module("gamma", None, &[], &[]),
+        ];
+
+        let (order, cycle) = order(&modules);
+
+        assert_eq!(order, ["beta", "alpha", "gamma"]);
+
+        let cycle = cycle.unwrap();
+
+        assert_eq!(cycle.first(), cycle.last());
+        assert!(cycle.contains(&"alpha".to_string()) && cycle.contains(&"beta".to_string()));
+    }
 }
@@ -0,0 +1,297 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{defs, utils};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Device,
+}
+
+impl EntryKind {
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        let ft = metadata.file_type();
+
+        if ft.is_dir() {
+            EntryKind::Dir
+        } else if ft.is_symlink() {
+            EntryKind::Symlink
+        } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
+            EntryKind::Device
+        } else {
+            EntryKind::File
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileEntry {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mtime: i64,
+    pub mode: u32,
+    #[serde(default)]
+    pub uid: u32,
+    #[serde(default)]
+    pub gid: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub capability: String,
+}
+
+impl FileEntry {
+    fn same_content(&self, other: &FileEntry) -> bool {
+        self.kind == other.kind
+            && self.mode == other.mode
+            && self.uid == other.uid
+            && self.gid == other.gid
+            && self.hash == other.hash
+            && self.capability == other.capability
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ManifestDiff {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+
+        match serde_json::from_str(&content) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                tracing::warn!("Ignoring corrupt sync manifest {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self).context("Failed to serialize sync manifest")?;
+
+        utils::atomic_write(path, json)
+    }
+
+    pub fn build(root: &Path, previous: Option<&Manifest>) -> Result<Self> {
+        let mut files = BTreeMap::new();
+
+        for entry in WalkDir::new(root).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+
+            if entry.depth() == 1 && entry.file_name() == defs::SYNC_MANIFEST_FILE_NAME {
+                continue;
+            }
+
+            let relative = entry
+                .path()
+                .strip_prefix(root)?
+                .to_string_lossy()
+                .to_string();
+
+            let metadata = entry.metadata()?;
+
+            let kind = EntryKind::of(&metadata);
+
+            let mut file_entry = FileEntry {
+                kind,
+                size: metadata.len(),
+                mtime: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+                mode: metadata.mode(),
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                hash: String::new(),
+                capability: utils::file_capability(entry.path())
+                    .map(|capability| hash_bytes(&capability))
+                    .unwrap_or_default(),
+            };
+
+            let cached = previous.and_then(|p| p.files.get(&relative)).filter(|old| {
+                old.kind == file_entry.kind
+                    && old.size == file_entry.size
+                    && old.mtime == file_entry.mtime
+            });
+
+            file_entry.hash = match (kind, cached) {
+                (EntryKind::Dir, _) => String::new(),
+                (_, Some(old)) => old.hash.clone(),
+                (EntryKind::File, None) => hash_file(entry.path())
+                    .with_context(|| format!("Failed to hash {}", entry.path().display()))?,
+                (EntryKind::Symlink, None) => {
+                    let target = fs::read_link(entry.path())?;
+                    hash_bytes(target.as_os_str().as_encoded_bytes())
+                }
+                (EntryKind::Device, None) => format!("rdev:{}", metadata.rdev()),
+            };
+
+            files.insert(relative, file_entry);
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn diff(&self, newer: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+
+        for (path, entry) in &newer.files {
+            match self.files.get(path) {
+                None => diff.added.push(path.clone()),
+                Some(old) if !old.same_content(entry) => diff.modified.push(path.clone()),
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = self
+            .files
+            .keys()
+            .filter(|path| !newer.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: EntryKind, hash: &str) -> FileEntry {
+        FileEntry {
+            kind,
+            size: 0,
+            mtime: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            hash: hash.to_string(),
+            capability: String::new(),
+        }
+    }
+
+    fn manifest(entries: &[(&str, FileEntry)]) -> Manifest {
+        Manifest {
+            files: entries
+                .iter()
+                .map(|(path, entry)| (path.to_string(), entry.clone()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_reports_owner_and_capability_changes() {
+        let file = entry(EntryKind::File, "a");
+
+        let old = manifest(&[
+            ("system/bin/owned", file.clone()),
+            ("system/bin/capable", file.clone()),
+            ("system/bin/same", file.clone()),
+        ]);
+
+        let new = manifest(&[
+            (
+                "system/bin/owned",
+                FileEntry {
+                    uid: 1000,
+                    gid: 2000,
+                    ..file.clone()
+                },
+            ),
+            (
+                "system/bin/capable",
+                FileEntry {
+                    capability: "cap".to_string(),
+                    ..file.clone()
+                },
+            ),
+            ("system/bin/same", file),
+        ]);
+
+        let diff = old.diff(&new);
+
+        assert_eq!(diff.modified, ["system/bin/capable", "system/bin/owned"]);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+
+    #[test]
+    fn diff_tracks_content_not_timestamps() {
+        let file = entry(EntryKind::File, "a");
+
+        let old = manifest(&[
+            ("system/etc", entry(EntryKind::Dir, "")),
+            ("system/etc/hosts", file.clone()),
+            ("system/etc/gone.conf", file.clone()),
+            ("system/etc/link", entry(EntryKind::Symlink, "a")),
+            ("system/etc/touched", file.clone()),
+        ]);
+
+        let new = manifest(&[
+            ("system/etc", entry(EntryKind::Dir, "")),
+            ("system/etc/hosts", entry(EntryKind::File, "b")),
+            ("system/etc/link", file.clone()),
+            ("system/etc/new.conf", file.clone()),
+            (
+                "system/etc/touched",
+                FileEntry {
+                    size: 1,
+                    mtime: 1,
+                    ..file
+                },
+            ),
+        ]);
+
+        let diff = old.diff(&new);
+
+        assert_eq!(diff.added, ["system/etc/new.conf"]);
+        assert_eq!(diff.modified, ["system/etc/hosts", "system/etc/link"]);
+        assert_eq!(diff.removed, ["system/etc/gone.conf"]);
+        assert!(!diff.is_empty());
+        assert!(new.diff(&new).is_empty());
+    }
+}
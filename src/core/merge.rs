@@ -0,0 +1,153 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        planner::{MountPlan, OverlayOperation},
+        storage::StorageMode,
+    },
+    defs,
+    mount::{overlayfs, record},
+    utils,
+};
+
+pub fn merge_root(config: &Config, mode: StorageMode, storage_root: &Path) -> PathBuf {
+    if mode.is_read_only() {
+        config.run_dir().join(defs::MERGED_LAYER_DIR_NAME)
+    } else {
+        storage_root.join(defs::MERGED_LAYER_DIR_NAME)
+    }
+}
+
+pub fn clear(merge_root: &Path) {
+    if merge_root.exists()
+        && let Err(e) = fs::remove_dir_all(merge_root)
+    {
+        tracing::warn!(
+            "Failed to clear merged layers in {}: {}",
+            merge_root.display(),
+            e
+        );
+    }
+}
+
+pub fn fit_lowerdirs(
+    plan: &MountPlan,
+    op: &OverlayOperation,
+    rw_dirs: (Option<&Path>, Option<&Path>),
+    merge_root: &Path,
+) -> Result<Vec<String>> {
+    let layers: Vec<String> = op
+        .lowerdirs
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect();
+
+    let data_layers = op.data_layers();
+
+    let (upper, work) = rw_dirs;
+
+    if overlayfs::overlayfs::overlay_fits(&op.target, &layers, &data_layers, upper, work) {
+        return Ok(layers);
+    }
+
+    let merged = merge_root.join(&op.partition_name);
+
+    let merged_str = merged.display().to_string();
+
+    for keep in (0..layers.len().saturating_sub(1)).rev() {
+        let mut candidate = layers[..keep].to_vec();
+
+        candidate.push(merged_str.clone());
+
+        if !overlayfs::overlayfs::overlay_fits(&op.target, &candidate, &data_layers, upper, work) {
+            continue;
+        }
+
+        tracing::warn!(
+            "Lowerdir options for {} exceed the mount limit, merging {} layers into {}",
+            op.target,
+            layers.len() - keep,
+            merged.display()
+        );
+
+        merge_layers(&op.lowerdirs[keep..], &merged)?;
+
+        let owners: BTreeSet<String> = op.lowerdirs[keep..]
+            .iter()
+            .filter_map(|layer| plan.layer_owner(layer))
+            .collect();
+
+        record::register_merged_layer(&merged, owners);
+
+        return Ok(candidate);
+    }
+
+    bail!(
+        "{} layers for {} cannot fit the mount options even when merged",
+        layers.len(),
+        op.target
+    )
+}
+
+fn merge_layers(layers: &[PathBuf], dest: &Path) -> Result<()> {
+    if dest.exists() {
+        fs::remove_dir_all(dest)
+            .with_context(|| format!("Failed to clear merged layer {}", dest.display()))?;
+    }
+
+    fs::create_dir_all(dest)?;
+
+    for layer in layers.iter().rev() {
+        for entry in WalkDir::new(layer).min_depth(1).into_iter() {
+            let entry = entry?;
+
+            let relative = entry.path().strip_prefix(layer)?;
+
+            merge_entry(layer, dest, relative, entry.file_type().is_dir())
+                .with_context(|| format!("Failed to merge {}", entry.path().display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_entry(layer: &Path, dest: &Path, relative: &Path, is_dir: bool) -> Result<()> {
+    let src = layer.join(relative);
+
+    let dst = dest.join(relative);
+
+    let existing = fs::symlink_metadata(&dst).ok();
+
+    if is_dir {
+        if existing.as_ref().is_some_and(|m| m.is_dir()) && utils::is_overlay_opaque(&src) {
+            fs::remove_dir_all(&dst)?;
+        }
+
+        utils::sync_entry(layer, dest, relative, None)?;
+
+        return Ok(());
+    }
+
+    match existing {
+        Some(m) if m.is_dir() => fs::remove_dir_all(&dst)?,
+        Some(_) => fs::remove_file(&dst)?,
+        None => {}
+    }
+
+    if fs::hard_link(&src, &dst).is_err() {
+        utils::sync_entry(layer, dest, relative, None)?;
+    }
+
+    Ok(())
+}
@@ -1,18 +1,33 @@
+pub mod erofs;
 pub mod executor;
+pub mod file_contexts;
 pub mod granary;
+pub mod hotplug;
+pub mod image_tree;
 pub mod inventory;
+pub mod manifest;
+pub mod merge;
 pub mod modules;
 pub mod planner;
+pub mod reconcile;
+pub mod remount;
+pub mod squashfs;
 pub mod state;
 pub mod storage;
 pub mod sync;
+pub mod teardown;
+pub mod verify;
 pub mod winnow;
 
-use std::path::Path;
+use std::{collections::BTreeSet, path::Path};
 
 use anyhow::Result;
 
-use crate::{conf::config::Config, try_umount};
+use crate::{
+    conf::config::Config,
+    mount::record::{self, MountRecord, RecordKind},
+    try_umount,
+};
 
 pub struct Init;
 
@@ -57,23 +72,19 @@ impl OryzaEngine<Init> {
         mnt_base: &Path,
         img_path: &Path,
     ) -> Result<OryzaEngine<StorageReady>> {
-        let handle = storage::setup(
-            mnt_base,
-            img_path,
-            &self.config.moduledir,
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Ext4
-            ),
-            matches!(
-                self.config.overlay_mode,
-                crate::conf::config::OverlayMode::Erofs
-            ),
-            &self.config.mountsource,
-            self.config.disable_umount,
-        )?;
+        let handle = storage::setup(&self.config, mnt_base, img_path)?;
+
+        tracing::info!(
+            ">> Storage Backend: [{}]",
+            handle.mode().as_str().to_uppercase()
+        );
 
-        tracing::info!(">> Storage Backend: [{}]", handle.mode.to_uppercase());
+        let _ = record::STORAGE_ROOT.set(handle.mount_point.clone());
+        record::push(MountRecord::new(
+            RecordKind::Storage,
+            &handle.mount_point,
+            BTreeSet::new(),
+        ));
 
         Ok(OryzaEngine {
             config: self.config,
@@ -82,6 +93,30 @@ impl OryzaEngine<Init> {
     }
 }
 
+fn populate_storage(
+    config: &Config,
+    modules: &[inventory::Module],
+    handle: &mut storage::StorageHandle,
+) -> Result<()> {
+    let reports = sync::perform_sync(modules, &handle.mount_point)?;
+
+    let changed = reports.iter().filter(|r| !r.changes.is_empty()).count();
+
+    tracing::info!(
+        ">> Module Sync: {} changed, {} up to date.",
+        changed,
+        reports.len() - changed
+    );
+
+    match winnow::build_override_layer(config, modules, &handle.mount_point, None) {
+        Ok(0) => {}
+        Ok(count) => tracing::info!(">> Winnowing: {} override(s) enforced.", count),
+        Err(e) => tracing::warn!("Failed to build winnowing override layer: {:#}", e),
+    }
+
+    handle.commit(config, modules)
+}
+
 impl OryzaEngine<StorageReady> {
     pub fn scan_and_sync(mut self) -> Result<OryzaEngine<ModulesReady>> {
         let modules = inventory::scan(&self.config.moduledir, &self.config)?;
@@ -91,9 +126,25 @@ impl OryzaEngine<StorageReady> {
             modules.len()
         );
 
-        sync::perform_sync(&modules, &self.state.handle.mount_point)?;
+        if let Err(e) = self.state.handle.ensure_capacity(&self.config) {
+            tracing::warn!("Failed to grow storage image: {:#}", e);
+        }
+
+        if !self.state.handle.reuse_image(&self.config, &modules)? {
+            while let Err(e) = populate_storage(&self.config, &modules, &mut self.state.handle) {
+                tracing::error!(
+                    "Failed to populate {} storage: {:#}",
+                    self.state.handle.mode(),
+                    e
+                );
 
-        self.state.handle.commit(self.config.disable_umount)?;
+                self.state.handle.fall_back(&self.config)?;
+
+                if let Err(e) = self.state.handle.ensure_capacity(&self.config) {
+                    tracing::warn!("Failed to grow storage image: {:#}", e);
+                }
+            }
+        }
 
         Ok(OryzaEngine {
             config: self.config,
@@ -130,7 +181,9 @@ impl OryzaEngine<Planned> {
     pub fn execute(self) -> Result<OryzaEngine<Executed>> {
         tracing::info!(">> Link Start! Executing mount plan...");
 
-        let result = executor::execute(&self.state.plan, &self.config, &self.state.handle)?;
+        let result = executor::execute(&self.state.plan, &self.config, &self.state.handle);
+
+        let result = result?;
 
         Ok(OryzaEngine {
             config: self.config,
@@ -148,7 +201,7 @@ impl OryzaEngine<Executed> {
     pub fn finalize(self) -> Result<()> {
         let mut nuke_active = false;
 
-        if self.state.handle.mode == "ext4" && self.config.enable_nuke {
+        if self.state.handle.mode() == storage::StorageMode::Ext4 && self.config.enable_nuke {
             tracing::info!(">> Engaging Paw Pad Protocol (Stealth)...");
 
             match try_umount::ksu_nuke_sysfs(
@@ -166,13 +219,14 @@ impl OryzaEngine<Executed> {
         }
 
         modules::update_description(
-            &self.state.handle.mode,
+            &self.config.module_prop_file(),
+            self.state.handle.mode(),
             nuke_active,
             self.state.result.overlay_module_ids.len(),
             self.state.result.magic_module_ids.len(),
         );
 
-        let storage_stats = storage::get_usage(&self.state.handle.mount_point);
+        let storage_stats = self.state.handle.usage();
 
         let active_mounts: Vec<String> = self
             .state
@@ -183,20 +237,21 @@ impl OryzaEngine<Executed> {
             .collect();
 
         let state = state::RuntimeState::new(
-            self.state.handle.mode,
+            self.state.handle.mode(),
             self.state.handle.mount_point,
             self.state.result.overlay_module_ids,
             self.state.result.magic_module_ids,
             nuke_active,
             active_mounts,
             storage_stats,
+            self.state.handle.probe,
         );
 
-        if let Err(e) = state.save() {
+        if let Err(e) = state.save(&self.config.state_file()) {
             tracing::error!("Failed to save runtime state: {:#}", e);
         }
 
-        granary::disengage_ratoon_protocol();
+        granary::disengage_ratoon_protocol(&self.config);
 
         tracing::info!(">> System operational. Mount sequence complete.");
 
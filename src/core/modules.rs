@@ -16,6 +16,7 @@ use crate::{
     core::{
         inventory::{self, MountMode},
         state::RuntimeState,
+        storage::StorageMode,
     },
     defs, utils,
 };
@@ -143,7 +144,7 @@ impl ModuleFile {
 pub fn print_list(config: &Config) -> Result<()> {
     let modules = inventory::scan(&config.moduledir, config)?;
 
-    let state = RuntimeState::load().unwrap_or_default();
+    let state = RuntimeState::load(&config.state_file()).unwrap_or_default();
 
     let mounted_ids: HashSet<&str> = state
         .overlay_modules
@@ -163,27 +164,28 @@ pub fn print_list(config: &Config) -> Result<()> {
 }
 
 pub fn update_description(
-    storage_mode: &str,
+    prop_path: &Path,
+    storage_mode: StorageMode,
     nuke_active: bool,
     overlay_count: usize,
     magic_count: usize,
 ) {
-    let prop_path = Path::new(defs::MODULE_PROP_FILE);
-
     if !prop_path.exists() {
         return;
     }
 
     let mode_str = match storage_mode {
-        "tmpfs" => "Tmpfs",
-        "erofs" => "EROFS",
-        _ => "Ext4",
+        StorageMode::Tmpfs => "Tmpfs",
+        StorageMode::Erofs => "EROFS",
+        StorageMode::Squashfs => "SquashFS",
+        StorageMode::Ext4 => "Ext4",
     };
 
     let status_emoji = match storage_mode {
-        "tmpfs" => "🐾",
-        "erofs" => "🚀",
-        _ => "💿",
+        StorageMode::Tmpfs => "🐾",
+        StorageMode::Erofs => "🚀",
+        StorageMode::Squashfs => "📦",
+        StorageMode::Ext4 => "💿",
     };
 
     let nuke_str = if nuke_active {
@@ -11,7 +11,10 @@ use walkdir::WalkDir;
 
 use crate::{
     conf::config,
-    core::inventory::{Module, MountMode},
+    core::{
+        inventory::{Module, MountMode},
+        winnow,
+    },
     defs,
 };
 
@@ -20,12 +23,15 @@ pub struct OverlayOperation {
     pub partition_name: String,
     pub target: String,
     pub lowerdirs: Vec<PathBuf>,
+    pub datadirs: Vec<PathBuf>,
 }
 
 #[derive(Debug, Default)]
 pub struct MountPlan {
+    pub storage_root: PathBuf,
     pub overlay_ops: Vec<OverlayOperation>,
     pub magic_module_paths: Vec<PathBuf>,
+    pub magic_scopes: HashMap<String, Vec<PathBuf>>,
     pub overlay_module_ids: Vec<String>,
     pub magic_module_ids: Vec<String>,
 }
@@ -42,7 +48,25 @@ pub struct ConflictReport {
     pub details: Vec<ConflictEntry>,
 }
 
+impl OverlayOperation {
+    pub fn data_layers(&self) -> Vec<String> {
+        self.datadirs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect()
+    }
+}
+
 impl MountPlan {
+    pub fn layer_owner(&self, layer_path: &Path) -> Option<String> {
+        layer_path
+            .strip_prefix(&self.storage_root)
+            .ok()?
+            .components()
+            .next()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+    }
+
     pub fn analyze_conflicts(&self) -> ConflictReport {
         let mut conflicts: Vec<ConflictEntry> = self
             .overlay_ops
@@ -53,7 +77,8 @@ impl MountPlan {
                 let mut file_map: HashMap<String, Vec<String>> = HashMap::new();
 
                 for layer_path in &op.lowerdirs {
-                    let module_id = crate::utils::extract_module_id(layer_path)
+                    let module_id = self
+                        .layer_owner(layer_path)
                         .unwrap_or_else(|| "UNKNOWN".into());
 
                     for entry in WalkDir::new(layer_path).min_depth(1).into_iter().flatten() {
@@ -121,9 +146,7 @@ impl MountPlan {
                         "├──"
                     };
 
-                    let mod_name = crate::utils::extract_module_id(layer)
-                        .map(|n| n.to_string())
-                        .unwrap_or_else(|| "UNKNOWN".into());
+                    let mod_name = self.layer_owner(layer).unwrap_or_else(|| "UNKNOWN".into());
 
                     tracing::info!("{}{} [Layer] {}", prefix, sub_branch, mod_name);
                 }
@@ -149,84 +172,141 @@ impl MountPlan {
     }
 }
 
-struct ModuleContribution {
-    id: String,
-    overlays: Vec<(String, PathBuf)>,
-    magic_path: Option<PathBuf>,
+pub struct ModuleContribution {
+    pub id: String,
+    pub overlays: Vec<(String, PathBuf)>,
+    pub magic_scope: Vec<PathBuf>,
 }
 
-pub fn generate(
-    config: &config::Config,
-    modules: &[Module],
-    storage_root: &Path,
-) -> Result<MountPlan> {
-    let mut plan = MountPlan::default();
+pub fn contribution(
+    module: &Module,
+    content_path: &Path,
+    partitions: &[&str],
+) -> Option<ModuleContribution> {
+    if !content_path.exists() {
+        return None;
+    }
 
-    let mut target_partitions = defs::BUILTIN_PARTITIONS.to_vec();
+    let mut contrib = ModuleContribution {
+        id: module.id.clone(),
+        overlays: Vec::new(),
+        magic_scope: Vec::new(),
+    };
 
-    target_partitions.extend(config.partitions.iter().map(|s| s.as_str()));
+    if let Ok(entries) = fs::read_dir(content_path) {
+        for entry in entries.flatten() {
+            let path = entry.path();
 
-    let contributions: Vec<Option<ModuleContribution>> = modules
-        .par_iter()
-        .map(|module| {
-            let content_path = storage_root.join(&module.id);
+            if !path.is_dir() {
+                continue;
+            }
+
+            let dir_name = entry.file_name().to_string_lossy().to_string();
 
-            if !content_path.exists() {
-                return None;
+            if !partitions.contains(&dir_name.as_str()) {
+                continue;
             }
 
-            let mut contrib = ModuleContribution {
-                id: module.id.clone(),
-                overlays: Vec::new(),
-                magic_path: None,
-            };
+            if !has_files(&path) {
+                continue;
+            }
 
-            let mut has_any_action = false;
+            if module.rules.has_rules_below(&dir_name) {
+                split_contribution(module, content_path, Path::new(&dir_name), &mut contrib);
 
-            if let Ok(entries) = fs::read_dir(&content_path) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
+                continue;
+            }
 
-                    if !path.is_dir() {
-                        continue;
-                    }
+            match module.rules.get_mode(&dir_name) {
+                MountMode::Overlay => contrib.overlays.push((dir_name, path)),
+                MountMode::Magic => contrib.magic_scope.push(PathBuf::from(dir_name)),
+                MountMode::Ignore => {
+                    tracing::debug!("Ignoring {}/{} per rule", module.id, dir_name);
+                }
+            }
+        }
+    }
 
-                    let dir_name = entry.file_name().to_string_lossy().to_string();
+    if contrib.overlays.is_empty() && contrib.magic_scope.is_empty() {
+        None
+    } else {
+        Some(contrib)
+    }
+}
 
-                    if !target_partitions.contains(&dir_name.as_str()) {
-                        continue;
-                    }
+fn split_contribution(
+    module: &Module,
+    content_path: &Path,
+    relative: &Path,
+    contrib: &mut ModuleContribution,
+) {
+    let Ok(entries) = fs::read_dir(content_path.join(relative)) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if entry.file_name() == defs::REPLACE_DIR_FILE_NAME {
+            continue;
+        }
 
-                    if !has_files(&path) {
-                        continue;
-                    }
+        let child = relative.join(entry.file_name());
 
-                    let mode = module.rules.get_mode(&dir_name);
+        let child_str = child.to_string_lossy().to_string();
 
-                    match mode {
-                        MountMode::Overlay => {
-                            contrib.overlays.push((dir_name, path));
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
 
-                            has_any_action = true;
-                        }
-                        MountMode::Magic => {
-                            contrib.magic_path = Some(content_path.clone());
+        if is_dir && module.rules.has_rules_below(&child_str) {
+            split_contribution(module, content_path, &child, contrib);
 
-                            has_any_action = true;
-                        }
-                        MountMode::Ignore => {
-                            tracing::debug!("Ignoring {}/{} per rule", module.id, dir_name);
-                        }
-                    }
+            continue;
+        }
+
+        match module.rules.get_mode(&child_str) {
+            MountMode::Overlay if is_dir => {
+                if has_files(&entry.path()) {
+                    contrib.overlays.push((child_str, entry.path()));
                 }
             }
+            MountMode::Overlay => {
+                tracing::debug!(
+                    "{}/{} sits beside a split rule, mounting it via magic mount",
+                    module.id,
+                    child_str
+                );
+
+                contrib.magic_scope.push(child);
+            }
+            MountMode::Magic => contrib.magic_scope.push(child),
+            MountMode::Ignore => {
+                tracing::debug!("Ignoring {}/{} per rule", module.id, child_str);
+            }
+        }
+    }
+}
+
+pub fn generate(
+    config: &config::Config,
+    modules: &[Module],
+    storage_root: &Path,
+) -> Result<MountPlan> {
+    let mut plan = MountPlan {
+        storage_root: storage_root.to_path_buf(),
+        ..Default::default()
+    };
+
+    let mut target_partitions = defs::BUILTIN_PARTITIONS.to_vec();
+
+    target_partitions.extend(config.partitions.iter().map(|s| s.as_str()));
 
-            if has_any_action { Some(contrib) } else { None }
-        })
+    let contributions: Vec<Option<ModuleContribution>> = modules
+        .par_iter()
+        .map(|module| contribution(module, &storage_root.join(&module.id), &target_partitions))
         .collect();
 
     let mut overlay_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
 
+    let mut data_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+
     let mut magic_paths = HashSet::new();
 
     let mut overlay_ids = HashSet::new();
@@ -234,23 +314,38 @@ pub fn generate(
     let mut magic_ids = HashSet::new();
 
     for contrib in contributions.into_iter().flatten() {
-        if let Some(path) = contrib.magic_path {
-            magic_paths.insert(path);
+        if !contrib.magic_scope.is_empty() {
+            magic_paths.insert(storage_root.join(&contrib.id));
 
             magic_ids.insert(contrib.id.clone());
+
+            plan.magic_scopes
+                .insert(contrib.id.clone(), contrib.magic_scope);
         }
 
         for (part, path) in contrib.overlays {
+            let data_layer = storage_root
+                .join(&contrib.id)
+                .join(defs::DATA_LAYER_DIR_NAME)
+                .join(&part);
+
+            if has_files(&data_layer) {
+                data_groups
+                    .entry(part.clone())
+                    .or_default()
+                    .push(data_layer);
+            }
+
             overlay_groups.entry(part).or_default().push(path);
 
             overlay_ids.insert(contrib.id.clone());
         }
     }
 
-    for (part, layers) in overlay_groups {
-        let initial_target_path = format!("/{}", part);
+    for (part, mut layers) in overlay_groups {
+        let initial_target_path = config.system_path(&part);
 
-        let target_path_obj = Path::new(&initial_target_path);
+        let target_path_obj = initial_target_path.as_path();
 
         if fs::symlink_metadata(target_path_obj)
             .map(|m| m.file_type().is_symlink())
@@ -258,7 +353,7 @@ pub fn generate(
         {
             tracing::warn!(
                 "Skipping overlay on symlink partition: {}",
-                initial_target_path
+                initial_target_path.display()
             );
 
             continue;
@@ -277,13 +372,25 @@ pub fn generate(
             continue;
         }
 
+        if let Some(layer) = winnow::override_layer(storage_root, &part)
+            && has_files(&layer)
+        {
+            layers.insert(0, layer);
+        }
+
+        let datadirs = data_groups.remove(&part).unwrap_or_default();
+
         plan.overlay_ops.push(OverlayOperation {
             partition_name: part,
             target: resolved_target.to_string_lossy().to_string(),
             lowerdirs: layers,
+            datadirs,
         });
     }
 
+    plan.overlay_ops
+        .sort_by(|a, b| a.partition_name.cmp(&b.partition_name));
+
     plan.magic_module_paths = magic_paths.into_iter().collect();
 
     plan.overlay_module_ids = overlay_ids.into_iter().collect();
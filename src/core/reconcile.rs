@@ -0,0 +1,570 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsString,
+    fs,
+    os::unix::ffi::OsStringExt,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use procfs::process::MountInfo;
+use serde::Serialize;
+
+use crate::{conf::config::Config, core::state::RuntimeState, defs};
+
+const MAX_PARENT_DEPTH: u8 = 32;
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountStatus {
+    Mounted,
+    Missing,
+    Shadowed,
+    Unexpected,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountKind {
+    Overlay,
+    Bind,
+}
+
+#[derive(Serialize)]
+pub struct StorageReport {
+    pub mount_point: PathBuf,
+    pub status: MountStatus,
+}
+
+#[derive(Serialize)]
+pub struct PartitionReport {
+    pub partition: String,
+    pub target: PathBuf,
+    pub status: MountStatus,
+    pub modules: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ModuleReport {
+    pub id: String,
+    pub mode: String,
+    pub status: MountStatus,
+    pub mounts: Vec<PathBuf>,
+    pub shadowed_mounts: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+pub struct ExtraMount {
+    pub mount_point: PathBuf,
+    pub kind: MountKind,
+    pub modules: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct StatusReport {
+    pub storage: StorageReport,
+    pub partitions: Vec<PartitionReport>,
+    pub modules: Vec<ModuleReport>,
+    pub unexpected_mounts: Vec<ExtraMount>,
+}
+
+struct LiveMount {
+    mount_point: PathBuf,
+    kind: MountKind,
+    modules: BTreeSet<String>,
+    visible: bool,
+}
+
+fn octal_escape(bytes: &[u8]) -> Option<u8> {
+    let digits = bytes.get(..3)?;
+
+    if !digits.iter().all(|b| (b'0'..=b'7').contains(b)) {
+        return None;
+    }
+
+    u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok()
+}
+
+fn unescape_option(value: &str) -> OsString {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(b) = octal_escape(&bytes[i + 1..])
+        {
+            unescaped.push(b);
+            i += 4;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    OsString::from_vec(unescaped)
+}
+
+fn option_path(value: &str) -> String {
+    unescape_option(value).to_string_lossy().into_owned()
+}
+
+pub fn split_lowerdir(value: &str) -> (Vec<String>, Vec<String>) {
+    let unescaped = unescape_option(value).into_vec();
+    let mut bytes = unescaped.into_iter().peekable();
+    let mut layers = Vec::new();
+    let mut data_layers = Vec::new();
+    let mut in_data = false;
+    let mut current = Vec::new();
+
+    let mut finish = |current: Vec<u8>, in_data: bool| {
+        let layer = OsString::from_vec(current).to_string_lossy().into_owned();
+        if in_data {
+            data_layers.push(layer);
+        } else {
+            layers.push(layer);
+        }
+    };
+
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => current.extend(bytes.next()),
+            b':' => {
+                if !current.is_empty() {
+                    finish(std::mem::take(&mut current), in_data);
+                }
+                if bytes.next_if_eq(&b':').is_some() {
+                    in_data = true;
+                }
+            }
+            _ => current.push(b),
+        }
+    }
+
+    if !current.is_empty() {
+        finish(current, in_data);
+    }
+
+    (layers, data_layers)
+}
+
+fn overlay_lowerdirs(super_options: &str) -> Option<Vec<String>> {
+    let mut appended = Vec::new();
+
+    for option in super_options.split(',') {
+        if let Some(value) = option.strip_prefix("lowerdir=") {
+            return Some(split_lowerdir(value).0);
+        }
+
+        if let Some(value) = option.strip_prefix("lowerdir+=") {
+            appended.push(option_path(value));
+        }
+    }
+
+    (!appended.is_empty()).then_some(appended)
+}
+
+fn overlay_datadirs(super_options: &str) -> Vec<String> {
+    let mut appended = Vec::new();
+
+    for option in super_options.split(',') {
+        if let Some(value) = option.strip_prefix("lowerdir=") {
+            return split_lowerdir(value).1;
+        }
+
+        if let Some(value) = option.strip_prefix("datadir+=") {
+            appended.push(option_path(value));
+        }
+    }
+
+    appended
+}
+
+pub struct MountTable {
+    pub mounts: Vec<MountInfo>,
+    super_options: HashMap<i32, String>,
+}
+
+impl MountTable {
+    pub fn read() -> Result<Self> {
+        let content = fs::read_to_string(MOUNTINFO).context("Failed to read mountinfo")?;
+
+        let mut mounts = Vec::new();
+
+        let mut super_options = HashMap::new();
+
+        for line in content.lines() {
+            let info = MountInfo::from_line(line)
+                .with_context(|| format!("Failed to parse mountinfo line: {line}"))?;
+
+            if let Some((_, tail)) = line.split_once(" - ")
+                && let Some(options) = tail.split_whitespace().nth(2)
+            {
+                super_options.insert(info.mnt_id, options.to_string());
+            }
+
+            mounts.push(info);
+        }
+
+        Ok(Self {
+            mounts,
+            super_options,
+        })
+    }
+
+    pub fn overlay_lowerdirs(&self, m: &MountInfo) -> Option<Vec<String>> {
+        overlay_lowerdirs(self.super_options.get(&m.mnt_id)?)
+    }
+
+    pub fn overlay_datadirs(&self, m: &MountInfo) -> Vec<String> {
+        self.super_options
+            .get(&m.mnt_id)
+            .map(|options| overlay_datadirs(options))
+            .unwrap_or_default()
+    }
+}
+
+fn first_component(path: &Path) -> Option<String> {
+    path.components().find_map(|c| match c {
+        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+        _ => None,
+    })
+}
+
+fn is_hidden(index: usize, mounts: &[MountInfo], by_id: &HashMap<i32, usize>, depth: u8) -> bool {
+    let entry = &mounts[index];
+
+    let covered = mounts.iter().any(|other| {
+        if other.mnt_id == entry.mnt_id {
+            return false;
+        }
+
+        let stacked = other.pid == entry.mnt_id && other.mount_point == entry.mount_point;
+
+        let over_ancestor = other.pid == entry.pid
+            && other.mount_point != entry.mount_point
+            && entry.mount_point.starts_with(&other.mount_point);
+
+        stacked || over_ancestor
+    });
+
+    if covered {
+        return true;
+    }
+
+    if depth == 0 || entry.pid == entry.mnt_id {
+        return false;
+    }
+
+    by_id
+        .get(&entry.pid)
+        .is_some_and(|&parent| is_hidden(parent, mounts, by_id, depth - 1))
+}
+
+fn collect_live_mounts(
+    table: &MountTable,
+    by_id: &HashMap<i32, usize>,
+    storage_root: &Path,
+) -> Vec<LiveMount> {
+    let mounts = &table.mounts;
+
+    let storage_dev = mounts
+        .iter()
+        .rev()
+        .find(|m| m.mount_point == storage_root && m.root == "/")
+        .map(|m| m.majmin.clone());
+
+    let mut live = Vec::new();
+
+    for (i, m) in mounts.iter().enumerate() {
+        let (kind, modules) = if m.fs_type == "overlay" && m.root == "/" {
+            let Some(layers) = table.overlay_lowerdirs(m) else {
+                continue;
+            };
+
+            let modules: BTreeSet<String> = layers
+                .iter()
+                .filter_map(|layer| Path::new(layer).strip_prefix(storage_root).ok())
+                .filter_map(first_component)
+                .filter(|id| id != defs::WINNOW_LAYER_DIR_NAME && id != defs::MERGED_LAYER_DIR_NAME)
+                .collect();
+
+            (MountKind::Overlay, modules)
+        } else if storage_dev.as_deref() == Some(m.majmin.as_str())
+            && m.root != "/"
+            && m.mount_point != storage_root
+        {
+            let modules = first_component(Path::new(&m.root)).into_iter().collect();
+
+            (MountKind::Bind, modules)
+        } else {
+            continue;
+        };
+
+        if modules.is_empty() {
+            continue;
+        }
+
+        live.push(LiveMount {
+            mount_point: m.mount_point.clone(),
+            kind,
+            modules,
+            visible: !is_hidden(i, mounts, by_id, MAX_PARENT_DEPTH),
+        });
+    }
+
+    live
+}
+
+fn status_of(visible: bool, hidden: bool) -> MountStatus {
+    if visible {
+        MountStatus::Mounted
+    } else if hidden {
+        MountStatus::Shadowed
+    } else {
+        MountStatus::Missing
+    }
+}
+
+pub fn reconcile(config: &Config, state: &RuntimeState) -> Result<StatusReport> {
+    let table = MountTable::read()?;
+
+    let mounts = &table.mounts;
+
+    let storage_root = state.mount_point.as_path();
+
+    let storage_index = mounts
+        .iter()
+        .rposition(|m| m.mount_point == storage_root && m.root == "/");
+
+    let by_id: HashMap<i32, usize> = mounts
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.mnt_id, i))
+        .collect();
+
+    let storage = StorageReport {
+        mount_point: storage_root.to_path_buf(),
+        status: match storage_index {
+            Some(i) if is_hidden(i, mounts, &by_id, MAX_PARENT_DEPTH) => MountStatus::Shadowed,
+            Some(_) => MountStatus::Mounted,
+            None => MountStatus::Missing,
+        },
+    };
+
+    let live = if storage_root.as_os_str().is_empty() {
+        Vec::new()
+    } else {
+        collect_live_mounts(&table, &by_id, storage_root)
+    };
+
+    let targets: Vec<(String, PathBuf)> = state
+        .active_mounts
+        .iter()
+        .map(|partition| {
+            let target = config.system_path(partition);
+            let target = target.canonicalize().unwrap_or(target);
+
+            (partition.clone(), target)
+        })
+        .collect();
+
+    let partitions = targets
+        .iter()
+        .map(|(partition, target)| {
+            let on_target: Vec<&LiveMount> = live
+                .iter()
+                .filter(|l| l.kind == MountKind::Overlay && &l.mount_point == target)
+                .collect();
+
+            let modules = on_target
+                .iter()
+                .flat_map(|l| l.modules.iter().cloned())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+
+            PartitionReport {
+                partition: partition.clone(),
+                target: target.clone(),
+                status: status_of(on_target.iter().any(|l| l.visible), !on_target.is_empty()),
+                modules,
+            }
+        })
+        .collect();
+
+    let mut recorded: BTreeMap<String, (bool, bool)> = BTreeMap::new();
+
+    for id in &state.overlay_modules {
+        recorded.entry(id.clone()).or_default().0 = true;
+    }
+
+    for id in &state.magic_modules {
+        recorded.entry(id.clone()).or_default().1 = true;
+    }
+
+    let mut modules: Vec<ModuleReport> = recorded
+        .iter()
+        .map(|(id, (overlay, magic))| {
+            let mode = match (overlay, magic) {
+                (true, true) => "hybrid",
+                (true, false) => "overlay",
+                _ => "magic",
+            };
+
+            let (mounts, shadowed_mounts): (Vec<&LiveMount>, Vec<&LiveMount>) = live
+                .iter()
+                .filter(|l| l.modules.contains(id))
+                .partition(|l| l.visible);
+
+            ModuleReport {
+                id: id.clone(),
+                mode: mode.to_string(),
+                status: status_of(!mounts.is_empty(), !shadowed_mounts.is_empty()),
+                mounts: mounts.iter().map(|l| l.mount_point.clone()).collect(),
+                shadowed_mounts: shadowed_mounts
+                    .iter()
+                    .map(|l| l.mount_point.clone())
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let mut unexpected_modules: BTreeMap<String, (MountKind, Vec<PathBuf>)> = BTreeMap::new();
+
+    let mut unexpected_mounts = Vec::new();
+
+    for l in &live {
+        for id in &l.modules {
+            if !recorded.contains_key(id) {
+                unexpected_modules
+                    .entry(id.clone())
+                    .or_insert_with(|| (l.kind, Vec::new()))
+                    .1
+                    .push(l.mount_point.clone());
+            }
+        }
+
+        let expected = match l.kind {
+            MountKind::Overlay => targets
+                .iter()
+                .any(|(_, target)| l.mount_point.starts_with(target)),
+            MountKind::Bind => l
+                .modules
+                .iter()
+                .all(|id| recorded.get(id).is_some_and(|(_, magic)| *magic)),
+        };
+
+        if !expected {
+            unexpected_mounts.push(ExtraMount {
+                mount_point: l.mount_point.clone(),
+                kind: l.kind,
+                modules: l.modules.iter().cloned().collect(),
+            });
+        }
+    }
+
+    modules.extend(
+        unexpected_modules
+            .into_iter()
+            .map(|(id, (kind, mounts))| ModuleReport {
+                id,
+                mode: match kind {
+                    MountKind::Overlay => "overlay".to_string(),
+                    MountKind::Bind => "magic".to_string(),
+                },
+                status: MountStatus::Unexpected,
+                mounts,
+                shadowed_mounts: Vec::new(),
+            }),
+    );
+
+    Ok(StatusReport {
+        storage,
+        partitions,
+        modules,
+        unexpected_mounts,
+    })
+}
+
+pub fn print_report(config: &Config) -> Result<()> {
+    let state = RuntimeState::load(&config.state_file()).context("Failed to load runtime state")?;
+
+    let report = reconcile(config, &state)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lowerdir_plain() {
+        assert_eq!(
+            split_lowerdir("/a:/b::/c::/d"),
+            (
+                vec!["/a".into(), "/b".into()],
+                vec!["/c".into(), "/d".into()]
+            )
+        );
+    }
+
+    #[test]
+    fn split_lowerdir_decodes_mountinfo_before_splitting() {
+        assert_eq!(split_lowerdir("/a\\134:b:/c").0, vec!["/a:b", "/c"]);
+        assert_eq!(split_lowerdir("/a\\134\\054b:/c").0, vec!["/a,b", "/c"]);
+        assert_eq!(
+            split_lowerdir("/a\\040b\\134\\134:/c").0,
+            vec!["/a b\\", "/c"]
+        );
+        assert_eq!(
+            split_lowerdir("/a\\134:::/c"),
+            (vec!["/a:".into()], vec!["/c".into()])
+        );
+    }
+
+    #[test]
+    fn split_lowerdir_keeps_escaped_multibyte_paths() {
+        assert_eq!(
+            split_lowerdir("/mod/caf\\303\\251:/b").0,
+            vec!["/mod/café", "/b"]
+        );
+        assert_eq!(
+            overlay_lowerdirs("rw,lowerdir+=/\\346\\250\\241\\345\\235\\227"),
+            Some(vec!["/模块".to_string()])
+        );
+    }
+
+    #[test]
+    fn overlay_lowerdirs_reads_appended_layers() {
+        assert_eq!(
+            overlay_lowerdirs("rw,lowerdir+=/a\\054b,lowerdir+=/c:d,redirect_dir=on"),
+            Some(vec!["/a,b".to_string(), "/c:d".to_string()])
+        );
+        assert_eq!(
+            overlay_lowerdirs("rw,lowerdir=/a:/b,upperdir=/u"),
+            Some(vec!["/a".to_string(), "/b".to_string()])
+        );
+        assert_eq!(overlay_lowerdirs("rw,size=10k"), None);
+    }
+
+    #[test]
+    fn overlay_datadirs_reads_data_only_layers() {
+        assert_eq!(
+            overlay_datadirs("rw,lowerdir+=/a,datadir+=/d\\054e,metacopy=on"),
+            vec!["/d,e".to_string()]
+        );
+        assert_eq!(
+            overlay_datadirs("rw,lowerdir=/a:/b::/d,metacopy=on"),
+            vec!["/d".to_string()]
+        );
+        assert!(overlay_datadirs("rw,lowerdir=/a:/b").is_empty());
+    }
+}
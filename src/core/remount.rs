@@ -0,0 +1,527 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use procfs::process::MountInfo;
+use rustix::{
+    fs::CWD,
+    mount::{OpenTreeFlags, UnmountFlags, open_tree, unmount},
+};
+use serde::Serialize;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        inventory, merge,
+        planner::{self, OverlayOperation},
+        reconcile::MountTable,
+        state::RuntimeState,
+        storage::{self, OverlayLayout},
+        sync::{self, SyncReport},
+        winnow,
+    },
+    defs,
+    mount::{
+        journal,
+        overlayfs::overlayfs,
+        record::{self, MountRecord, RecordKind},
+    },
+    utils,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemountStrategy {
+    Swap,
+    Restack,
+    Mount,
+    Detach,
+    Unchanged,
+}
+
+#[derive(Serialize)]
+pub struct RemountReport {
+    pub partition: String,
+    pub target: PathBuf,
+    pub strategy: RemountStrategy,
+    pub modules: Vec<String>,
+    pub previous_modules: Vec<String>,
+    pub nested: Vec<String>,
+    pub detached_magic_modules: Vec<String>,
+    pub synced: Vec<SyncReport>,
+}
+
+struct LiveOverlay {
+    index: usize,
+    layers: Vec<String>,
+    datadirs: Vec<String>,
+    modules: BTreeSet<String>,
+}
+
+fn storage_overlay(
+    table: &MountTable,
+    m: &MountInfo,
+    storage_root: &Path,
+) -> Option<(Vec<String>, BTreeSet<String>)> {
+    if m.fs_type != "overlay" {
+        return None;
+    }
+
+    let layers = table.overlay_lowerdirs(m)?;
+
+    let modules: BTreeSet<String> = layers
+        .iter()
+        .filter(|layer| Path::new(layer).starts_with(storage_root))
+        .filter_map(record::module_of)
+        .collect();
+
+    if modules.is_empty() {
+        None
+    } else {
+        Some((layers, modules))
+    }
+}
+
+fn find_live_overlay(
+    table: &MountTable,
+    target: &Path,
+    storage_root: &Path,
+) -> Option<LiveOverlay> {
+    table
+        .mounts
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, m)| m.mount_point == target)
+        .find_map(|(index, m)| {
+            storage_overlay(table, m, storage_root).map(|(layers, modules)| LiveOverlay {
+                index,
+                layers,
+                datadirs: table.overlay_datadirs(m),
+                modules,
+            })
+        })
+}
+
+fn stock_parent(mounts: &[MountInfo], overlay: &LiveOverlay, target: &Path) -> Option<PathBuf> {
+    let by_id: HashMap<i32, &MountInfo> = mounts.iter().map(|m| (m.mnt_id, m)).collect();
+
+    let parent = by_id.get(&mounts[overlay.index].pid)?;
+
+    if parent.mount_point == target {
+        return None;
+    }
+
+    let covered = mounts
+        .iter()
+        .any(|m| m.pid == parent.mnt_id && m.mount_point == parent.mount_point);
+
+    let has_submounts = mounts.iter().any(|m| {
+        m.pid == parent.mnt_id && m.mount_point != target && m.mount_point.starts_with(target)
+    });
+
+    if covered || has_submounts {
+        None
+    } else {
+        Some(parent.mount_point.clone())
+    }
+}
+
+fn swap(
+    config: &Config,
+    parent: &Path,
+    target: &Path,
+    layers: &[String],
+    data_layers: &[String],
+    rw_dirs: (Option<PathBuf>, Option<PathBuf>),
+) -> Result<()> {
+    let tree = open_tree(
+        CWD,
+        parent,
+        OpenTreeFlags::OPEN_TREE_CLONE | OpenTreeFlags::OPEN_TREE_CLOEXEC,
+    )
+    .with_context(|| format!("Failed to clone stock mount {}", parent.display()))?;
+
+    let stock = Path::new("/proc/self/fd")
+        .join(tree.as_raw_fd().to_string())
+        .join(target.strip_prefix(parent)?);
+
+    let mut lowerdirs = layers.to_vec();
+    lowerdirs.push(stock.display().to_string());
+
+    let (upper, work) = rw_dirs;
+
+    overlayfs::swap_overlayfs(
+        &lowerdirs,
+        data_layers,
+        upper,
+        work,
+        target,
+        &config.mountsource,
+    )
+}
+
+fn restack(
+    config: &Config,
+    target: &Path,
+    layers: &[String],
+    data_layers: &[String],
+    previous: Option<&LiveOverlay>,
+    rw_dirs: (Option<PathBuf>, Option<PathBuf>),
+) -> Result<()> {
+    let target_str = target.display().to_string();
+
+    if previous.is_some() {
+        unmount(target, UnmountFlags::DETACH)
+            .with_context(|| format!("Failed to detach old overlay on {}", target.display()))?;
+    }
+
+    if layers.is_empty() {
+        return Ok(());
+    }
+
+    let (upper, work) = rw_dirs;
+
+    let result = overlayfs::mount_overlay(
+        &target_str,
+        &layers.to_vec(),
+        data_layers,
+        work.clone(),
+        upper.clone(),
+        &config.mountsource,
+    );
+
+    if let Err(e) = result {
+        if let Some(old) = previous {
+            tracing::warn!("Remount failed, restoring previous layers: {:#}", e);
+
+            let old_layers = old.layers[..old.layers.len().saturating_sub(1)].to_vec();
+
+            overlayfs::mount_overlay(
+                &target_str,
+                &old_layers,
+                &old.datadirs,
+                work,
+                upper,
+                &config.mountsource,
+            )
+            .context("Failed to restore previous overlay")?;
+        }
+
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn restore_records(previous: &[MountRecord]) {
+    let Ok(table) = MountTable::read() else {
+        return;
+    };
+
+    for r in previous {
+        if table.mounts.iter().any(|m| m.mount_point == r.target) {
+            record::push(r.clone());
+        }
+    }
+}
+
+pub fn remount_partition(config: &Config, partition: &str) -> Result<RemountReport> {
+    if !defs::BUILTIN_PARTITIONS.contains(&partition)
+        && !config.partitions.iter().any(|p| p == partition)
+    {
+        bail!("Unknown partition: {}", partition);
+    }
+
+    let state_path = config.state_file();
+
+    let mut state = RuntimeState::load(&state_path)?;
+
+    let storage_root = state.mount_point.clone();
+
+    if storage_root.as_os_str().is_empty() || !utils::is_mounted(&storage_root) {
+        bail!("Storage is not mounted, nothing to remount");
+    }
+
+    let _ = record::STORAGE_ROOT.set(storage_root.clone());
+
+    if let Err(e) = journal::resume(&config.mount_journal_file()) {
+        tracing::warn!("Failed to open mount journal: {:#}", e);
+    }
+
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    let mut synced = Vec::new();
+
+    if state.storage_mode.is_read_only() {
+        tracing::warn!(
+            "{} storage is read-only, remounting with its current content",
+            state.storage_mode
+        );
+    } else {
+        if let Err(e) = storage::ensure_live_capacity(config, &state) {
+            tracing::warn!("Failed to grow {} storage: {:#}", state.storage_mode, e);
+        }
+
+        synced = sync::sync_modules(&modules, &storage_root);
+
+        synced.retain(|r| !r.changes.is_empty());
+
+        if let Err(e) =
+            winnow::build_override_layer(config, &modules, &storage_root, Some(partition))
+        {
+            tracing::warn!("Failed to rebuild winnowing override layer: {:#}", e);
+        }
+    }
+
+    let plan = planner::generate(config, &modules, &storage_root)?;
+
+    let target = config.system_path(partition);
+    let target = target.canonicalize().unwrap_or(target);
+
+    let layout = OverlayLayout::from_mode(state.storage_mode, &config.system_rw_dir());
+    let rw_dirs = layout.rw_dirs(&storage_root, partition);
+
+    let merge_root = merge::merge_root(config, state.storage_mode, &storage_root);
+
+    let partition_op = plan
+        .overlay_ops
+        .iter()
+        .find(|op| op.partition_name == partition);
+
+    let new_modules: BTreeSet<String> = partition_op
+        .map(|op| op.lowerdirs.iter().filter_map(record::module_of).collect())
+        .unwrap_or_default();
+
+    let data_layers = partition_op.map(|op| op.data_layers()).unwrap_or_default();
+
+    let layers = match partition_op {
+        Some(op) => merge::fit_lowerdirs(
+            &plan,
+            op,
+            (rw_dirs.0.as_deref(), rw_dirs.1.as_deref()),
+            &merge_root,
+        )?,
+        None => Vec::new(),
+    };
+
+    let table = MountTable::read()?;
+
+    let mounts = &table.mounts;
+
+    let live = find_live_overlay(&table, &target, &storage_root);
+
+    let nested_prefix = format!("{partition}/");
+
+    let nested_ops: Vec<&OverlayOperation> = plan
+        .overlay_ops
+        .iter()
+        .filter(|op| op.partition_name.starts_with(&nested_prefix))
+        .collect();
+
+    let stale_nested: Vec<(i32, PathBuf)> = mounts
+        .iter()
+        .filter(|m| m.mount_point != target && m.mount_point.starts_with(&target))
+        .filter(|m| storage_overlay(&table, m, &storage_root).is_some())
+        .map(|m| (m.mnt_id, m.mount_point.clone()))
+        .collect();
+
+    let previous_modules = live.as_ref().map(|l| l.modules.clone()).unwrap_or_default();
+
+    let mut report = RemountReport {
+        partition: partition.to_string(),
+        target: target.clone(),
+        strategy: RemountStrategy::Unchanged,
+        modules: new_modules.iter().cloned().collect(),
+        previous_modules: previous_modules.iter().cloned().collect(),
+        nested: Vec::new(),
+        detached_magic_modules: Vec::new(),
+        synced,
+    };
+
+    if live.is_none() && layers.is_empty() && nested_ops.is_empty() && stale_nested.is_empty() {
+        return Ok(report);
+    }
+
+    let parent = live
+        .as_ref()
+        .filter(|_| !layers.is_empty())
+        .and_then(|l| stock_parent(mounts, l, &target));
+
+    let journal_path = config.mount_journal_file();
+
+    let previous: Vec<MountRecord> = record::load(&journal_path)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|r| r.kind != RecordKind::Storage && r.target.starts_with(&target))
+        .collect();
+
+    let dropped: BTreeSet<String> = previous
+        .iter()
+        .flat_map(|r| r.modules.iter().cloned())
+        .collect();
+
+    for r in previous.iter().rev() {
+        record::release(&r.target);
+    }
+
+    let swapped = match &parent {
+        Some(parent) => match swap(
+            config,
+            parent,
+            &target,
+            &layers,
+            &data_layers,
+            rw_dirs.clone(),
+        ) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Atomic swap failed, falling back to restack: {:#}", e);
+                false
+            }
+        },
+        None => false,
+    };
+
+    if swapped {
+        record::push(MountRecord::new(
+            RecordKind::Overlay,
+            &target,
+            new_modules.clone(),
+        ));
+        report.strategy = RemountStrategy::Swap;
+    } else {
+        if let Err(e) = restack(
+            config,
+            &target,
+            &layers,
+            &data_layers,
+            live.as_ref(),
+            rw_dirs,
+        ) {
+            restore_records(&previous);
+
+            return Err(e);
+        }
+
+        report.strategy = match (live.is_some(), layers.is_empty()) {
+            (true, true) => RemountStrategy::Detach,
+            (true, false) => RemountStrategy::Restack,
+            _ => RemountStrategy::Mount,
+        };
+    }
+
+    let mounts = MountTable::read()?.mounts;
+
+    for (mnt_id, mount_point) in stale_nested.iter().rev() {
+        if mounts.iter().any(|m| m.mnt_id == *mnt_id)
+            && let Err(e) = unmount(mount_point, UnmountFlags::DETACH)
+        {
+            tracing::warn!(
+                "Failed to detach stale overlay on {}: {}",
+                mount_point.display(),
+                e
+            );
+        }
+    }
+
+    let mut nested_targets = Vec::new();
+
+    for op in nested_ops {
+        let (upper, work) = layout.rw_dirs(&storage_root, &op.partition_name);
+
+        let mounted =
+            merge::fit_lowerdirs(&plan, op, (upper.as_deref(), work.as_deref()), &merge_root)
+                .and_then(|nested_layers| {
+                    overlayfs::mount_overlay(
+                        &op.target,
+                        &nested_layers,
+                        &op.data_layers(),
+                        work,
+                        upper,
+                        &config.mountsource,
+                    )
+                });
+
+        match mounted {
+            Ok(()) => {
+                report.nested.push(op.partition_name.clone());
+                nested_targets.push(op.target.clone());
+            }
+            Err(e) => tracing::warn!("Failed to mount nested overlay {}: {:#}", op.target, e),
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if !config.disable_umount && (!layers.is_empty() || !nested_targets.is_empty()) {
+        if !layers.is_empty() {
+            let _ = crate::try_umount::send_unmountable(&target);
+        }
+        for nested_target in &nested_targets {
+            let _ = crate::try_umount::send_unmountable(nested_target);
+        }
+        if let Err(e) = crate::try_umount::commit() {
+            tracing::warn!("try_umount commit failed: {}", e);
+        }
+    }
+
+    let records = record::load(&journal_path).unwrap_or_default();
+
+    report.detached_magic_modules = state
+        .magic_modules
+        .iter()
+        .filter(|id| dropped.contains(*id))
+        .cloned()
+        .collect();
+
+    if !report.detached_magic_modules.is_empty() {
+        tracing::warn!(
+            "Magic mounts under {} were detached with the old overlay: {:?}",
+            target.display(),
+            report.detached_magic_modules
+        );
+    }
+
+    let overlay_ids: BTreeSet<String> = records
+        .iter()
+        .filter(|r| r.kind == RecordKind::Overlay)
+        .flat_map(|r| r.modules.iter().cloned())
+        .collect();
+
+    state.overlay_modules = overlay_ids.into_iter().collect();
+    state
+        .magic_modules
+        .retain(|id| !report.detached_magic_modules.contains(id));
+    state
+        .active_mounts
+        .retain(|p| p != partition && !p.starts_with(&nested_prefix));
+
+    if !layers.is_empty() {
+        state.active_mounts.push(partition.to_string());
+    }
+
+    state.active_mounts.extend(report.nested.iter().cloned());
+
+    let (total, used, percent) = storage::get_usage(&storage_root);
+    state.storage_total = total;
+    state.storage_used = used;
+    state.storage_percent = percent;
+
+    state.save(&state_path)?;
+
+    Ok(report)
+}
+
+pub fn print_remount(config: &Config, partition: &str) -> Result<()> {
+    let report = remount_partition(config, partition)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
@@ -0,0 +1,666 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, bail};
+use flate2::{Compression, write::ZlibEncoder};
+
+use crate::core::image_tree::{self, Content, Entry, FileKind};
+
+const SUPER_MAGIC: u32 = 0x7371_7368;
+const SUPER_SIZE: usize = 96;
+const BLOCK_LOG: u16 = 17;
+const BLOCK_SIZE: usize = 1 << BLOCK_LOG;
+const METADATA_SIZE: usize = 8192;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const IMAGE_ALIGN: u64 = 4096;
+const INVALID_TABLE: u64 = u64::MAX;
+const INVALID_FRAGMENT: u32 = u32::MAX;
+const NO_XATTR: u32 = u32::MAX;
+const DIR_HEADER_ENTRIES: usize = 256;
+
+const COMPRESSION_ZLIB: u16 = 1;
+
+const FLAG_NO_FRAGMENTS: u16 = 0x10;
+const FLAG_NO_XATTRS: u16 = 0x200;
+
+const TYPE_DIR: u16 = 1;
+const TYPE_FILE: u16 = 2;
+const TYPE_SYMLINK: u16 = 3;
+const TYPE_BLKDEV: u16 = 4;
+const TYPE_CHRDEV: u16 = 5;
+const TYPE_FIFO: u16 = 6;
+const TYPE_SOCKET: u16 = 7;
+const EXTENDED_TYPE_OFFSET: u16 = 7;
+
+const XATTR_PREFIXES: &[(&str, u16)] = &[("user.", 0), ("trusted.", 1), ("security.", 2)];
+
+type XattrSet = Vec<(u16, Vec<u8>, Vec<u8>)>;
+
+type DirGroup<'a> = (u32, u32, Vec<&'a (Vec<u8>, usize)>);
+
+#[derive(Default)]
+struct FileData {
+    blocks_start: u64,
+    block_sizes: Vec<u32>,
+    sparse: u64,
+}
+
+struct Node {
+    entry: Entry,
+    kind: u16,
+    uid_index: u16,
+    gid_index: u16,
+    xattr: u32,
+    data: FileData,
+    number: u32,
+    inode_ref: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+    data_bytes: u64,
+    compressed_blocks: u64,
+    sparse_blocks: u64,
+    skipped_xattrs: usize,
+}
+
+#[derive(Default)]
+struct MetadataWriter {
+    out: Vec<u8>,
+    pending: Vec<u8>,
+    block_starts: Vec<u64>,
+}
+
+impl MetadataWriter {
+    fn reference(&self) -> u64 {
+        ((self.out.len() as u64) << 16) | self.pending.len() as u64
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+
+        while self.pending.len() >= METADATA_SIZE {
+            let rest = self.pending.split_off(METADATA_SIZE);
+
+            let block = std::mem::replace(&mut self.pending, rest);
+
+            self.flush(&block);
+        }
+    }
+
+    fn flush(&mut self, block: &[u8]) {
+        self.block_starts.push(self.out.len() as u64);
+
+        match compress(block) {
+            Some(packed) => {
+                self.out
+                    .extend_from_slice(&(packed.len() as u16).to_le_bytes());
+                self.out.extend_from_slice(&packed);
+            }
+            None => {
+                self.out
+                    .extend_from_slice(&(block.len() as u16 | METADATA_UNCOMPRESSED).to_le_bytes());
+                self.out.extend_from_slice(block);
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Vec<u8>, Vec<u64>) {
+        if !self.pending.is_empty() {
+            let block = std::mem::take(&mut self.pending);
+
+            self.flush(&block);
+        }
+
+        (self.out, self.block_starts)
+    }
+}
+
+struct Builder {
+    nodes: Vec<Node>,
+    ids: Vec<u32>,
+    id_lookup: HashMap<u32, u16>,
+    xattr_sets: Vec<XattrSet>,
+    xattr_lookup: HashMap<XattrSet, u32>,
+    stats: Stats,
+}
+
+fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(input.len()), Compression::default());
+
+    encoder.write_all(input).ok()?;
+
+    let packed = encoder.finish().ok()?;
+
+    (packed.len() < input.len()).then_some(packed)
+}
+
+fn kind_of(kind: FileKind) -> u16 {
+    match kind {
+        FileKind::Regular => TYPE_FILE,
+        FileKind::Directory => TYPE_DIR,
+        FileKind::Symlink => TYPE_SYMLINK,
+        FileKind::BlockDevice => TYPE_BLKDEV,
+        FileKind::CharDevice => TYPE_CHRDEV,
+        FileKind::Fifo => TYPE_FIFO,
+        FileKind::Socket => TYPE_SOCKET,
+    }
+}
+
+fn xattr_set(entry: &Entry, skipped: &mut usize) -> XattrSet {
+    let mut entries = Vec::new();
+
+    for (name, value) in &entry.xattrs {
+        let Some((prefix, id)) = XATTR_PREFIXES
+            .iter()
+            .find(|(prefix, _)| name.starts_with(prefix.as_bytes()))
+        else {
+            tracing::debug!(
+                "Skipping unsupported xattr {} on {}",
+                String::from_utf8_lossy(name),
+                entry.path.display()
+            );
+            *skipped += 1;
+            continue;
+        };
+
+        entries.push((*id, name[prefix.len()..].to_vec(), value.clone()));
+    }
+
+    entries.sort();
+
+    entries
+}
+
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+
+    Ok(filled)
+}
+
+impl Builder {
+    fn id_index(&mut self, id: u32) -> Result<u16> {
+        if let Some(&index) = self.id_lookup.get(&id) {
+            return Ok(index);
+        }
+
+        let index = u16::try_from(self.ids.len()).context("Too many distinct uid/gid values")?;
+
+        self.ids.push(id);
+        self.id_lookup.insert(id, index);
+
+        Ok(index)
+    }
+
+    fn xattr_index(&mut self, set: XattrSet) -> u32 {
+        if set.is_empty() {
+            return NO_XATTR;
+        }
+
+        if let Some(&index) = self.xattr_lookup.get(&set) {
+            return index;
+        }
+
+        let index = self.xattr_sets.len() as u32;
+
+        self.xattr_sets.push(set.clone());
+        self.xattr_lookup.insert(set, index);
+
+        index
+    }
+
+    fn add_node(&mut self, entry: Entry) -> Result<()> {
+        let uid_index = self.id_index(entry.uid)?;
+        let gid_index = self.id_index(entry.gid)?;
+
+        let xattrs = xattr_set(&entry, &mut self.stats.skipped_xattrs);
+        let xattr = self.xattr_index(xattrs);
+
+        self.nodes.push(Node {
+            kind: kind_of(entry.kind),
+            uid_index,
+            gid_index,
+            xattr,
+            data: FileData::default(),
+            number: 0,
+            inode_ref: 0,
+            entry,
+        });
+
+        Ok(())
+    }
+
+    fn write_data(&mut self, out: &mut BufWriter<File>, mut offset: u64) -> Result<u64> {
+        let mut buf = vec![0u8; BLOCK_SIZE];
+
+        for node in &mut self.nodes {
+            let Content::File = node.entry.content else {
+                continue;
+            };
+
+            let path = &node.entry.path;
+
+            let mut reader =
+                File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+            let data = &mut node.data;
+
+            data.blocks_start = offset;
+
+            let mut size = 0;
+
+            loop {
+                let len = read_chunk(&mut reader, &mut buf)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+
+                if len == 0 {
+                    break;
+                }
+
+                let chunk = &buf[..len];
+
+                size += len as u64;
+
+                if chunk.iter().all(|b| *b == 0) {
+                    data.block_sizes.push(0);
+                    data.sparse += len as u64;
+                    self.stats.sparse_blocks += 1;
+                } else if let Some(packed) = compress(chunk) {
+                    out.write_all(&packed)?;
+                    data.block_sizes.push(packed.len() as u32);
+                    offset += packed.len() as u64;
+                    self.stats.compressed_blocks += 1;
+                } else {
+                    out.write_all(chunk)?;
+                    data.block_sizes.push(len as u32 | DATA_UNCOMPRESSED);
+                    offset += len as u64;
+                }
+
+                if len < BLOCK_SIZE {
+                    break;
+                }
+            }
+
+            node.entry.size = size;
+
+            self.stats.data_bytes += size;
+        }
+
+        Ok(offset)
+    }
+
+    fn post_order(&self, index: usize, seen: &mut [bool], order: &mut Vec<usize>) {
+        if let Content::Dir { children, .. } = &self.nodes[index].entry.content {
+            for (_, child) in children {
+                if !seen[*child] {
+                    seen[*child] = true;
+                    self.post_order(*child, seen, order);
+                }
+            }
+        }
+
+        order.push(index);
+    }
+
+    fn listing(&self, index: usize) -> Result<Vec<u8>> {
+        let Content::Dir { children, .. } = &self.nodes[index].entry.content else {
+            return Ok(Vec::new());
+        };
+
+        let mut groups: Vec<DirGroup> = Vec::new();
+
+        for entry in children {
+            let child = &self.nodes[entry.1];
+
+            let start = (child.inode_ref >> 16) as u32;
+
+            let fits = groups.last().is_some_and(|(block, base, entries)| {
+                *block == start
+                    && entries.len() < DIR_HEADER_ENTRIES
+                    && i16::try_from(child.number as i64 - *base as i64).is_ok()
+            });
+
+            if fits {
+                groups.last_mut().unwrap().2.push(entry);
+            } else {
+                groups.push((start, child.number, vec![entry]));
+            }
+        }
+
+        let mut out = Vec::new();
+
+        for (start, base, entries) in groups {
+            out.extend_from_slice(&(entries.len() as u32 - 1).to_le_bytes());
+            out.extend_from_slice(&start.to_le_bytes());
+            out.extend_from_slice(&base.to_le_bytes());
+
+            for (name, child) in entries {
+                let child = &self.nodes[*child];
+
+                if name.is_empty() || name.len() > 256 {
+                    bail!("Invalid directory entry name length {}", name.len());
+                }
+
+                out.extend_from_slice(&((child.inode_ref & 0xffff) as u16).to_le_bytes());
+                out.extend_from_slice(&((child.number as i64 - base as i64) as i16).to_le_bytes());
+                out.extend_from_slice(&child.kind.to_le_bytes());
+                out.extend_from_slice(&(name.len() as u16 - 1).to_le_bytes());
+                out.extend_from_slice(name);
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn inode(&self, index: usize, listing: (u64, usize), root_parent: u32) -> Result<Vec<u8>> {
+        let node = &self.nodes[index];
+
+        let entry = &node.entry;
+
+        let mtime = entry.mtime.clamp(0, u32::MAX as i64) as u32;
+
+        let mut out = Vec::with_capacity(64);
+
+        out.extend_from_slice(&(node.kind + EXTENDED_TYPE_OFFSET).to_le_bytes());
+        out.extend_from_slice(&((entry.mode & 0o7777) as u16).to_le_bytes());
+        out.extend_from_slice(&node.uid_index.to_le_bytes());
+        out.extend_from_slice(&node.gid_index.to_le_bytes());
+        out.extend_from_slice(&mtime.to_le_bytes());
+        out.extend_from_slice(&node.number.to_le_bytes());
+
+        match &entry.content {
+            Content::Dir { parent, .. } => {
+                let parent = if *parent == index {
+                    root_parent
+                } else {
+                    self.nodes[*parent].number
+                };
+
+                let size = u32::try_from(listing.1 + 3).context("Directory listing too large")?;
+
+                out.extend_from_slice(&entry.nlink.to_le_bytes());
+                out.extend_from_slice(&size.to_le_bytes());
+                out.extend_from_slice(&((listing.0 >> 16) as u32).to_le_bytes());
+                out.extend_from_slice(&parent.to_le_bytes());
+                out.extend_from_slice(&0u16.to_le_bytes());
+                out.extend_from_slice(&((listing.0 & 0xffff) as u16).to_le_bytes());
+                out.extend_from_slice(&node.xattr.to_le_bytes());
+            }
+            Content::File => {
+                let data = &node.data;
+
+                out.extend_from_slice(&data.blocks_start.to_le_bytes());
+                out.extend_from_slice(&entry.size.to_le_bytes());
+                out.extend_from_slice(&data.sparse.to_le_bytes());
+                out.extend_from_slice(&entry.nlink.to_le_bytes());
+                out.extend_from_slice(&INVALID_FRAGMENT.to_le_bytes());
+                out.extend_from_slice(&0u32.to_le_bytes());
+                out.extend_from_slice(&node.xattr.to_le_bytes());
+
+                for size in &data.block_sizes {
+                    out.extend_from_slice(&size.to_le_bytes());
+                }
+            }
+            Content::Symlink(target) => {
+                out.extend_from_slice(&entry.nlink.to_le_bytes());
+                out.extend_from_slice(&(target.len() as u32).to_le_bytes());
+                out.extend_from_slice(target);
+                out.extend_from_slice(&node.xattr.to_le_bytes());
+            }
+            Content::Special(rdev) => {
+                out.extend_from_slice(&entry.nlink.to_le_bytes());
+
+                if matches!(node.kind, TYPE_BLKDEV | TYPE_CHRDEV) {
+                    out.extend_from_slice(&rdev.to_le_bytes());
+                }
+
+                out.extend_from_slice(&node.xattr.to_le_bytes());
+            }
+        }
+
+        Ok(out)
+    }
+
+    fn write_xattrs(&self, base: u64) -> (Vec<u8>, u64) {
+        let mut values = MetadataWriter::default();
+        let mut ids = MetadataWriter::default();
+
+        for set in &self.xattr_sets {
+            let reference = values.reference();
+
+            let mut size = 0u32;
+
+            for (prefix, name, value) in set {
+                values.write(&prefix.to_le_bytes());
+                values.write(&(name.len() as u16).to_le_bytes());
+                values.write(name);
+                values.write(&(value.len() as u32).to_le_bytes());
+                values.write(value);
+
+                size +=
+                    (XATTR_PREFIXES[*prefix as usize].0.len() + name.len() + value.len()) as u32;
+            }
+
+            ids.write(&reference.to_le_bytes());
+            ids.write(&(set.len() as u32).to_le_bytes());
+            ids.write(&size.to_le_bytes());
+        }
+
+        let (mut out, _) = values.finish();
+
+        let ids_start = base + out.len() as u64;
+
+        let (id_blocks, id_block_starts) = ids.finish();
+
+        out.extend_from_slice(&id_blocks);
+
+        let table_start = base + out.len() as u64;
+
+        out.extend_from_slice(&base.to_le_bytes());
+        out.extend_from_slice(&(self.xattr_sets.len() as u32).to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+
+        for start in id_block_starts {
+            out.extend_from_slice(&(ids_start + start).to_le_bytes());
+        }
+
+        (out, table_start)
+    }
+}
+
+struct Superblock {
+    inodes: u32,
+    ids: u16,
+    flags: u16,
+    root_inode: u64,
+    bytes_used: u64,
+    id_table_start: u64,
+    xattr_table_start: u64,
+    inode_table_start: u64,
+    directory_table_start: u64,
+}
+
+impl Superblock {
+    fn encode(&self) -> [u8; SUPER_SIZE] {
+        let mut sb = [0u8; SUPER_SIZE];
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or(0);
+
+        sb[0..4].copy_from_slice(&SUPER_MAGIC.to_le_bytes());
+        sb[4..8].copy_from_slice(&self.inodes.to_le_bytes());
+        sb[8..12].copy_from_slice(&now.to_le_bytes());
+        sb[12..16].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        sb[20..22].copy_from_slice(&COMPRESSION_ZLIB.to_le_bytes());
+        sb[22..24].copy_from_slice(&BLOCK_LOG.to_le_bytes());
+        sb[24..26].copy_from_slice(&self.flags.to_le_bytes());
+        sb[26..28].copy_from_slice(&self.ids.to_le_bytes());
+        sb[28..30].copy_from_slice(&4u16.to_le_bytes());
+        sb[32..40].copy_from_slice(&self.root_inode.to_le_bytes());
+        sb[40..48].copy_from_slice(&self.bytes_used.to_le_bytes());
+        sb[48..56].copy_from_slice(&self.id_table_start.to_le_bytes());
+        sb[56..64].copy_from_slice(&self.xattr_table_start.to_le_bytes());
+        sb[64..72].copy_from_slice(&self.inode_table_start.to_le_bytes());
+        sb[72..80].copy_from_slice(&self.directory_table_start.to_le_bytes());
+        sb[80..88].copy_from_slice(&INVALID_TABLE.to_le_bytes());
+        sb[88..96].copy_from_slice(&INVALID_TABLE.to_le_bytes());
+
+        sb
+    }
+}
+
+pub fn build_image(src_dir: &Path, image_path: &Path) -> Result<()> {
+    tracing::info!("Packing squashfs image: {} (zlib)", image_path.display());
+
+    let mut builder = Builder {
+        nodes: Vec::new(),
+        ids: Vec::new(),
+        id_lookup: HashMap::new(),
+        xattr_sets: Vec::new(),
+        xattr_lookup: HashMap::new(),
+        stats: Stats::default(),
+    };
+
+    for entry in image_tree::scan(src_dir)?.entries {
+        builder.add_node(entry)?;
+    }
+
+    let mut order = Vec::with_capacity(builder.nodes.len());
+    let mut seen = vec![false; builder.nodes.len()];
+
+    seen[0] = true;
+    builder.post_order(0, &mut seen, &mut order);
+
+    let inodes = u32::try_from(order.len()).context("Too many inodes for squashfs")?;
+
+    for (position, &index) in order.iter().enumerate() {
+        builder.nodes[index].number = position as u32 + 1;
+    }
+
+    let file = File::create(image_path)
+        .with_context(|| format!("Failed to create {}", image_path.display()))?;
+
+    let mut out = BufWriter::new(file);
+
+    out.seek(SeekFrom::Start(SUPER_SIZE as u64))?;
+
+    let inode_table_start = builder.write_data(&mut out, SUPER_SIZE as u64)?;
+
+    let mut inode_table = MetadataWriter::default();
+    let mut directory_table = MetadataWriter::default();
+
+    for &index in &order {
+        let listing = builder.listing(index)?;
+
+        let location = directory_table.reference();
+
+        directory_table.write(&listing);
+
+        builder.nodes[index].inode_ref = inode_table.reference();
+
+        let inode = builder.inode(index, (location, listing.len()), inodes + 1)?;
+
+        inode_table.write(&inode);
+    }
+
+    let (inode_bytes, _) = inode_table.finish();
+    let (directory_bytes, _) = directory_table.finish();
+
+    let directory_table_start = inode_table_start + inode_bytes.len() as u64;
+    let id_blocks_start = directory_table_start + directory_bytes.len() as u64;
+
+    let mut ids = MetadataWriter::default();
+
+    for id in &builder.ids {
+        ids.write(&id.to_le_bytes());
+    }
+
+    let (id_bytes, id_block_starts) = ids.finish();
+
+    let id_table_start = id_blocks_start + id_bytes.len() as u64;
+
+    let mut tail = id_bytes;
+
+    for start in id_block_starts {
+        tail.extend_from_slice(&(id_blocks_start + start).to_le_bytes());
+    }
+
+    let mut flags = FLAG_NO_FRAGMENTS;
+
+    let xattr_table_start = if builder.xattr_sets.is_empty() {
+        flags |= FLAG_NO_XATTRS;
+
+        INVALID_TABLE
+    } else {
+        let (xattrs, table_start) = builder.write_xattrs(id_blocks_start + tail.len() as u64);
+
+        tail.extend_from_slice(&xattrs);
+
+        table_start
+    };
+
+    out.write_all(&inode_bytes)?;
+    out.write_all(&directory_bytes)?;
+    out.write_all(&tail)?;
+
+    let bytes_used = id_blocks_start + tail.len() as u64;
+
+    let superblock = Superblock {
+        inodes,
+        ids: builder.ids.len() as u16,
+        flags,
+        root_inode: builder.nodes[0].inode_ref,
+        bytes_used,
+        id_table_start,
+        xattr_table_start,
+        inode_table_start,
+        directory_table_start,
+    };
+
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&superblock.encode())?;
+
+    file.set_len(bytes_used.next_multiple_of(IMAGE_ALIGN))?;
+    file.sync_all()?;
+
+    let stats = &builder.stats;
+
+    tracing::info!(
+        "Squashfs image built: {} inodes, {} bytes used for {} bytes of data, {} compressed \
+         and {} sparse blocks",
+        inodes,
+        bytes_used,
+        stats.data_bytes,
+        stats.compressed_blocks,
+        stats.sparse_blocks
+    );
+
+    if stats.skipped_xattrs > 0 {
+        tracing::warn!(
+            "Skipped {} xattrs with unsupported namespaces",
+            stats.skipped_xattrs
+        );
+    }
+
+    Ok(())
+}
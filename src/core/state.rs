@@ -3,20 +3,20 @@
 
 use std::{
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use crate::defs;
+use crate::core::storage::{ProbeReport, StorageMode};
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct RuntimeState {
     pub timestamp: u64,
     pub pid: u32,
-    pub storage_mode: String,
+    pub storage_mode: StorageMode,
     pub mount_point: PathBuf,
     pub overlay_modules: Vec<String>,
     pub magic_modules: Vec<String>,
@@ -31,18 +31,21 @@ pub struct RuntimeState {
     pub storage_percent: u8,
     #[serde(default)]
     pub zygisksu_enforce: bool,
+    #[serde(default)]
+    pub storage_probe: Option<ProbeReport>,
 }
 
 impl RuntimeState {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        storage_mode: String,
+        storage_mode: StorageMode,
         mount_point: PathBuf,
         overlay_modules: Vec<String>,
         magic_modules: Vec<String>,
         nuke_active: bool,
         active_mounts: Vec<String>,
         storage_info: (u64, u64, u8),
+        storage_probe: ProbeReport,
     ) -> Self {
         let start = SystemTime::now();
 
@@ -68,23 +71,24 @@ impl RuntimeState {
             storage_used: storage_info.1,
             storage_percent: storage_info.2,
             zygisksu_enforce,
+            storage_probe: Some(storage_probe),
         }
     }
 
-    pub fn save(&self) -> Result<()> {
+    pub fn save(&self, path: &Path) -> Result<()> {
         let json = serde_json::to_string_pretty(self)?;
 
-        fs::write(defs::STATE_FILE, json)?;
+        fs::write(path, json)?;
 
         Ok(())
     }
 
-    pub fn load() -> Result<Self> {
-        if !std::path::Path::new(defs::STATE_FILE).exists() {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
             return Ok(Self::default());
         }
 
-        let content = fs::read_to_string(defs::STATE_FILE)?;
+        let content = fs::read_to_string(path)?;
 
         let state = serde_json::from_str(&content)?;
 
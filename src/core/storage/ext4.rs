@@ -0,0 +1,335 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use jwalk::WalkDir;
+use rustix::mount::{UnmountFlags, unmount};
+use serde::Serialize;
+
+use super::{
+    DEFAULT_SELINUX_CONTEXT, ProbeReport, StorageBackend, StorageContext, StorageMode,
+    calculate_total_size, get_usage,
+};
+use crate::{
+    conf::config::Config,
+    mount::{loop_device, overlayfs::utils as overlay_utils},
+    utils,
+};
+
+const EXT4_HEADROOM: u64 = 128 * 1024 * 1024;
+const EXT4_MIN_FREE: u64 = 32 * 1024 * 1024;
+const EXT4_SIZE_ALIGN: u64 = 1024 * 1024;
+const EXT4_SUPERBLOCK_OFFSET: u64 = 1024;
+const EXT4_MAGIC: u16 = 0xEF53;
+
+#[derive(Default)]
+pub struct Ext4Backend {
+    image: Option<PathBuf>,
+}
+
+impl Ext4Backend {
+    pub fn attached(image: PathBuf) -> Self {
+        Self { image: Some(image) }
+    }
+}
+
+impl StorageBackend for Ext4Backend {
+    fn mode(&self) -> StorageMode {
+        StorageMode::Ext4
+    }
+
+    fn probe(&self, report: &ProbeReport) -> bool {
+        report.loop_devices
+    }
+
+    fn setup(&mut self, ctx: &StorageContext) -> Result<()> {
+        if !ctx.img_path.exists() || check_image(ctx.img_path).is_err() {
+            tracing::info!("Modules image missing or corrupted. Fallback to creation.");
+
+            if ctx.img_path.exists()
+                && let Err(e) = fs::remove_file(ctx.img_path)
+            {
+                tracing::warn!("Failed to remove old image: {}", e);
+            }
+
+            tracing::info!("- Preparing image");
+
+            let total_size = calculate_total_size(ctx.moduledir)?;
+            tracing::info!(
+                "Total size of files in '{}': {} bytes",
+                ctx.moduledir.display(),
+                total_size,
+            );
+
+            let grow_size = EXT4_HEADROOM + total_size;
+
+            fs::File::create(ctx.img_path)
+                .context("Failed to create ext4 image file")?
+                .set_len(grow_size)
+                .context("Failed to extend ext4 image")?;
+
+            let result = Command::new("mkfs.ext4")
+                .arg("-b")
+                .arg("1024")
+                .arg(ctx.img_path)
+                .stdout(std::process::Stdio::piped())
+                .output()?;
+
+            ensure!(
+                result.status.success(),
+                "Failed to format ext4 image: {}",
+                String::from_utf8(result.stderr)?
+            );
+
+            tracing::info!("Checking Image");
+            check_image(ctx.img_path)?;
+        }
+
+        utils::lsetfilecon(ctx.img_path, "u:object_r:ksu_file:s0").ok();
+
+        tracing::info!("- Mounting image");
+        if overlay_utils::AutoMountExt4::try_new(ctx.img_path, ctx.mnt_base, false).is_err() {
+            if utils::repair_image(ctx.img_path).is_ok() {
+                overlay_utils::AutoMountExt4::try_new(ctx.img_path, ctx.mnt_base, false)
+                    .context("Failed to mount modules.img after repair")
+                    .map(|_| ())?;
+            } else {
+                bail!("Failed to repair modules.img");
+            }
+        }
+
+        tracing::info!(
+            "mounted {} to {}",
+            ctx.img_path.display(),
+            ctx.mnt_base.display()
+        );
+
+        for dir_entry in WalkDir::new(ctx.mnt_base).parallelism(jwalk::Parallelism::Serial) {
+            if let Some(path) = dir_entry.ok().map(|dir_entry| dir_entry.path()) {
+                let _ = utils::lsetfilecon(&path, DEFAULT_SELINUX_CONTEXT);
+            }
+        }
+
+        self.image = Some(ctx.img_path.to_path_buf());
+
+        Ok(())
+    }
+
+    fn ensure_capacity(&mut self, config: &Config, mount_point: &Path) -> Result<()> {
+        let Some(image_path) = self.image.as_deref() else {
+            return Ok(());
+        };
+
+        let required = calculate_total_size(&config.moduledir)?
+            + calculate_total_size(&mount_point.join("overlay_rw"))?
+            + EXT4_MIN_FREE;
+
+        let (total, _, _) = get_usage(mount_point);
+
+        if total >= required {
+            return Ok(());
+        }
+
+        let target = (required + EXT4_HEADROOM).next_multiple_of(EXT4_SIZE_ALIGN);
+
+        tracing::info!(
+            "Modules need ~{} bytes but {} holds {}, growing image to {} bytes",
+            required,
+            image_path.display(),
+            total,
+            target
+        );
+
+        grow_image(image_path, mount_point, target)
+    }
+}
+
+fn check_image<P>(img: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = img.as_ref();
+    let path_str = path.to_str().context("Invalid path string")?;
+    let result = Command::new("e2fsck")
+        .args(["-yf", path_str])
+        .output()
+        .with_context(|| format!("Failed to exec e2fsck {}", path.display()))?;
+    let code = result.status.code();
+
+    tracing::debug!("e2fsck: {}", String::from_utf8_lossy(&result.stdout).trim());
+    tracing::info!("e2fsck exit code: {}", code.unwrap_or(-1));
+    Ok(())
+}
+
+fn run_resize2fs(args: &[&OsStr]) -> Result<String> {
+    let output = Command::new("resize2fs")
+        .args(args)
+        .output()
+        .context("Failed to execute resize2fs")?;
+
+    ensure!(
+        output.status.success(),
+        "resize2fs failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn grow_online(image_path: &Path) -> Result<()> {
+    let device = loop_device::find_attached(image_path)
+        .with_context(|| format!("{} is not attached to a loop device", image_path.display()))?;
+
+    loop_device::refresh_capacity(&device)?;
+
+    run_resize2fs(&[device.as_os_str()])?;
+
+    Ok(())
+}
+
+fn grow_offline(image_path: &Path, mount_point: &Path) -> Result<()> {
+    unmount(mount_point, UnmountFlags::DETACH).context("Failed to unmount ext4 storage")?;
+
+    check_image(image_path)?;
+
+    let resized = run_resize2fs(&[image_path.as_os_str()]);
+
+    overlay_utils::AutoMountExt4::try_new(image_path, mount_point, false)
+        .context("Failed to remount modules.img after resize")?;
+
+    resized.map(|_| ())
+}
+
+fn grow_image(image_path: &Path, mount_point: &Path, target: u64) -> Result<()> {
+    let current = fs::metadata(image_path)?.len();
+
+    if target <= current {
+        return Ok(());
+    }
+
+    fs::OpenOptions::new()
+        .write(true)
+        .open(image_path)?
+        .set_len(target)
+        .context("Failed to extend ext4 image")?;
+
+    match grow_online(image_path) {
+        Ok(()) => tracing::info!("Resized {} online", image_path.display()),
+        Err(e) => {
+            tracing::warn!("Online resize failed: {:#}, resizing offline", e);
+
+            grow_offline(image_path, mount_point)?;
+
+            tracing::info!("Resized {} offline", image_path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn ext4_block_size(image_path: &Path) -> Result<u64> {
+    let mut superblock = [0u8; 64];
+
+    let mut file = fs::File::open(image_path)?;
+
+    file.seek(SeekFrom::Start(EXT4_SUPERBLOCK_OFFSET))?;
+    file.read_exact(&mut superblock)?;
+
+    ensure!(
+        u16::from_le_bytes([superblock[56], superblock[57]]) == EXT4_MAGIC,
+        "{} is not an ext4 image",
+        image_path.display()
+    );
+
+    let log_block_size = u32::from_le_bytes(superblock[24..28].try_into()?);
+
+    Ok(1024 << log_block_size)
+}
+
+#[derive(Serialize)]
+pub struct CompactReport {
+    pub image: PathBuf,
+    pub before: u64,
+    pub after: u64,
+}
+
+pub fn compact(config: &Config) -> Result<CompactReport> {
+    let image_path = config.modules_img_file();
+
+    ensure!(
+        image_path.exists(),
+        "No ext4 modules image at {}",
+        image_path.display()
+    );
+
+    if let Some(device) = loop_device::find_attached(&image_path) {
+        bail!(
+            "{} is in use by {}, run `unmount` before compacting",
+            image_path.display(),
+            device.display()
+        );
+    }
+
+    let before = fs::metadata(&image_path)?.len();
+
+    check_image(&image_path)?;
+
+    let estimate = run_resize2fs(&[OsStr::new("-P"), image_path.as_os_str()])?;
+
+    let minimum_blocks: u64 = estimate
+        .lines()
+        .find(|line| line.contains("minimum size"))
+        .and_then(|line| line.rsplit(':').next())
+        .and_then(|value| value.trim().parse().ok())
+        .context("Failed to parse resize2fs minimum size estimate")?;
+
+    let target = (minimum_blocks * ext4_block_size(&image_path)? + EXT4_HEADROOM)
+        .next_multiple_of(EXT4_SIZE_ALIGN);
+
+    if target >= before {
+        tracing::info!("{} is already compact", image_path.display());
+
+        return Ok(CompactReport {
+            image: image_path,
+            before,
+            after: before,
+        });
+    }
+
+    run_resize2fs(&[
+        image_path.as_os_str(),
+        OsStr::new(&format!("{}K", target / 1024)),
+    ])?;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&image_path)?
+        .set_len(target)
+        .context("Failed to truncate ext4 image")?;
+
+    tracing::info!(
+        "Compacted {} from {} to {} bytes",
+        image_path.display(),
+        before,
+        target
+    );
+
+    Ok(CompactReport {
+        image: image_path,
+        before,
+        after: target,
+    })
+}
+
+pub fn print_compact(config: &Config) -> Result<()> {
+    let report = compact(config)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use rustix::mount::{UnmountFlags, unmount};
+use sha2::{Digest, Sha256};
+
+use super::{OverlayLayout, ProbeReport, StorageBackend, StorageContext, StorageMode};
+use crate::{
+    conf::config::Config,
+    core::{
+        erofs, file_contexts,
+        inventory::Module,
+        manifest::{self, Manifest},
+        squashfs, sync,
+    },
+    defs, utils,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Erofs,
+    Squashfs,
+}
+
+pub const IMAGE_FORMATS: [ImageFormat; 2] = [ImageFormat::Erofs, ImageFormat::Squashfs];
+
+impl ImageFormat {
+    fn mode(self) -> StorageMode {
+        match self {
+            Self::Erofs => StorageMode::Erofs,
+            Self::Squashfs => StorageMode::Squashfs,
+        }
+    }
+
+    pub fn image_path(self, img_path: &Path) -> PathBuf {
+        img_path.with_extension(self.mode().as_str())
+    }
+
+    fn is_supported(self, report: &ProbeReport) -> bool {
+        let kernel = match self {
+            Self::Erofs => report.erofs,
+            Self::Squashfs => report.squashfs,
+        };
+
+        kernel && report.loop_devices
+    }
+
+    fn pack(self, src_dir: &Path, image: &Path, config: &Config) -> Result<()> {
+        match self {
+            Self::Erofs => erofs::build_image(src_dir, image, config.erofs_compression)?,
+            Self::Squashfs => squashfs::build_image(src_dir, image)?,
+        }
+
+        let _ = fs::set_permissions(image, fs::Permissions::from_mode(0o644));
+
+        utils::lsetfilecon(image, "u:object_r:ksu_file:s0")
+    }
+
+    fn mount(self, image: &Path, target: &Path) -> Result<()> {
+        match self {
+            Self::Erofs => utils::mount_erofs_image(image, target),
+            Self::Squashfs => utils::mount_squashfs_image(image, target),
+        }
+    }
+}
+
+pub fn remove_stale_images(img_path: &Path, keep: Option<ImageFormat>) {
+    for format in IMAGE_FORMATS {
+        let image = format.image_path(img_path);
+
+        if Some(format) != keep && image.exists() {
+            let _ = fs::remove_file(image);
+        }
+    }
+}
+
+pub struct ImageBackend {
+    format: ImageFormat,
+    image: PathBuf,
+    rw_base: Option<PathBuf>,
+}
+
+impl ImageBackend {
+    pub fn new(format: ImageFormat) -> Self {
+        Self {
+            format,
+            image: PathBuf::new(),
+            rw_base: None,
+        }
+    }
+
+    fn finish(&mut self, config: &Config, mount_point: &Path) {
+        self.hide(mount_point, config.disable_umount);
+
+        self.rw_base = Some(config.system_rw_dir());
+    }
+}
+
+impl StorageBackend for ImageBackend {
+    fn mode(&self) -> StorageMode {
+        self.format.mode()
+    }
+
+    fn probe(&self, report: &ProbeReport) -> bool {
+        self.format.is_supported(report)
+    }
+
+    fn setup(&mut self, ctx: &StorageContext) -> Result<()> {
+        utils::mount_tmpfs(ctx.mnt_base, ctx.mount_source)?;
+
+        self.image = self.format.image_path(ctx.img_path);
+
+        remove_stale_images(ctx.img_path, Some(self.format));
+
+        Ok(())
+    }
+
+    fn reuse_image(
+        &mut self,
+        config: &Config,
+        modules: &[Module],
+        mount_point: &Path,
+    ) -> Result<bool> {
+        if !self.image.exists() {
+            return Ok(false);
+        }
+
+        unmount(mount_point, UnmountFlags::DETACH).context("Failed to unmount staging tmpfs")?;
+
+        let matched = self.format.mount(&self.image, mount_point).and_then(|()| {
+            let stored = fs::read_to_string(mount_point.join(defs::IMAGE_FINGERPRINT_FILE_NAME))
+                .unwrap_or_default();
+
+            Ok(stored.trim() == fingerprint(self.format, config, modules, mount_point)?)
+        });
+
+        match matched {
+            Ok(true) => {
+                tracing::info!("{} image is up to date, skipping repack", self.mode());
+
+                self.finish(config, mount_point);
+
+                return Ok(true);
+            }
+            Ok(false) => tracing::info!(
+                "Modules changed since last pack, rebuilding {} image",
+                self.mode()
+            ),
+            Err(e) => tracing::warn!("Failed to inspect existing {} image: {:#}", self.mode(), e),
+        }
+
+        if utils::is_mounted(mount_point) {
+            unmount(mount_point, UnmountFlags::DETACH)
+                .with_context(|| format!("Failed to unmount stale {} image", self.mode()))?;
+        }
+
+        utils::mount_tmpfs(mount_point, &config.mountsource)?;
+
+        self.hide(mount_point, config.disable_umount);
+
+        Ok(false)
+    }
+
+    fn commit(&mut self, config: &Config, modules: &[Module], mount_point: &Path) -> Result<()> {
+        let fingerprint = fingerprint(self.format, config, modules, mount_point)?;
+
+        fs::write(
+            mount_point.join(defs::IMAGE_FINGERPRINT_FILE_NAME),
+            &fingerprint,
+        )?;
+
+        self.format
+            .pack(mount_point, &self.image, config)
+            .with_context(|| format!("Failed to pack {} image", self.mode()))?;
+
+        unmount(mount_point, UnmountFlags::DETACH).context("Failed to unmount staging tmpfs")?;
+
+        self.format
+            .mount(&self.image, mount_point)
+            .with_context(|| format!("Failed to mount finalized {} image", self.mode()))?;
+
+        self.finish(config, mount_point);
+
+        Ok(())
+    }
+
+    fn layout(&self) -> OverlayLayout {
+        match &self.rw_base {
+            Some(rw_base) => OverlayLayout::Split {
+                rw_base: rw_base.clone(),
+            },
+            None => OverlayLayout::Contained,
+        }
+    }
+}
+
+fn fingerprint(
+    format: ImageFormat,
+    config: &Config,
+    modules: &[Module],
+    storage_root: &Path,
+) -> Result<String> {
+    let mut hasher = Sha256::new();
+
+    hasher.update(format!("format={}\n", format.mode()));
+
+    if format == ImageFormat::Erofs {
+        hasher.update(format!("compression={:?}\n", config.erofs_compression));
+    }
+
+    let winnowing: BTreeMap<_, _> = config.winnowing.rules.iter().collect();
+
+    hasher.update(serde_json::to_vec(&winnowing)?);
+
+    for policy_file in file_contexts::policy().files() {
+        hasher.update(format!(
+            "policy={}:{}\n",
+            policy_file.display(),
+            manifest::hash_file(policy_file)?
+        ));
+    }
+
+    let order: Vec<&str> = modules.iter().map(|m| m.id.as_str()).collect();
+
+    hasher.update(format!("order={}\n", order.join(",")));
+
+    for module in modules {
+        hasher.update(format!("module={}\n", module.id));
+
+        let rules = &module.rules;
+
+        let paths: BTreeMap<_, _> = rules.paths.iter().collect();
+
+        hasher.update(serde_json::to_vec(&(
+            &rules.default_mode,
+            paths,
+            rules.priority,
+            &rules.before,
+            &rules.after,
+        ))?);
+
+        if !rules.uses_overlay() || !sync::has_content(module) {
+            continue;
+        }
+
+        let previous = Manifest::load(
+            &storage_root
+                .join(&module.id)
+                .join(defs::SYNC_MANIFEST_FILE_NAME),
+        );
+
+        let current = Manifest::build(&module.source_path, previous.as_ref())?;
+
+        hasher.update(serde_json::to_vec(&current)?);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::inventory::{ModuleRules, MountMode};
+
+    fn module(id: &str, rules: ModuleRules) -> Module {
+        Module {
+            id: id.to_string(),
+            source_path: PathBuf::from("/nonexistent").join(id),
+            rules,
+        }
+    }
+
+    #[test]
+    fn fingerprint_tracks_rules_and_order() {
+        let config = Config::default();
+        let root = Path::new("/nonexistent");
+        let print =
+            |modules: &[Module]| fingerprint(ImageFormat::Erofs, &config, modules, root).unwrap();
+
+        let base = vec![
+            module("a", ModuleRules::default()),
+            module("b", ModuleRules::default()),
+        ];
+        let reordered = vec![base[1].clone(), base[0].clone()];
+
+        let mut magic_rules = ModuleRules::default();
+        magic_rules
+            .paths
+            .insert("system/etc".to_string(), MountMode::Magic);
+        let with_path = vec![module("a", magic_rules), base[1].clone()];
+
+        let prioritized = vec![
+            module(
+                "a",
+                ModuleRules {
+                    priority: Some(10),
+                    ..Default::default()
+                },
+            ),
+            base[1].clone(),
+        ];
+
+        assert_eq!(print(&base), print(&base.clone()));
+        assert_ne!(print(&base), print(&reordered));
+        assert_ne!(print(&base), print(&with_path));
+        assert_ne!(print(&base), print(&prioritized));
+    }
+}
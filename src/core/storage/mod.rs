@@ -0,0 +1,543 @@
+mod ext4;
+mod image;
+mod probe;
+mod tmpfs;
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use rustix::{
+    fs::Mode,
+    mount::{UnmountFlags, unmount},
+};
+use serde::{Deserialize, Serialize};
+
+use self::{
+    ext4::Ext4Backend,
+    image::{ImageBackend, ImageFormat},
+    tmpfs::{TmpfsBackend, TmpfsBudget},
+};
+pub use self::{ext4::print_compact, probe::ProbeReport};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::try_umount::send_unmountable;
+use crate::{
+    conf::config::Config,
+    core::{inventory::Module, state::RuntimeState},
+    utils,
+};
+
+const DEFAULT_SELINUX_CONTEXT: &str = "u:object_r:system_file:s0";
+const PROBE_DIR_NAME: &str = ".xattr_probe";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMode {
+    #[default]
+    Tmpfs,
+    Ext4,
+    Erofs,
+    Squashfs,
+}
+
+impl StorageMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tmpfs => "tmpfs",
+            Self::Ext4 => "ext4",
+            Self::Erofs => "erofs",
+            Self::Squashfs => "squashfs",
+        }
+    }
+
+    pub fn is_read_only(self) -> bool {
+        matches!(self, Self::Erofs | Self::Squashfs)
+    }
+}
+
+impl fmt::Display for StorageMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OverlayLayout {
+    Contained,
+    Split {
+        rw_base: PathBuf,
+    },
+    #[allow(dead_code)]
+    Direct {
+        rw_base: PathBuf,
+    },
+}
+
+impl OverlayLayout {
+    pub fn from_mode(mode: StorageMode, rw_base: &Path) -> Self {
+        if mode.is_read_only() {
+            Self::Split {
+                rw_base: rw_base.to_path_buf(),
+            }
+        } else {
+            Self::Contained
+        }
+    }
+
+    pub fn rw_dirs(
+        &self,
+        mount_point: &Path,
+        partition: &str,
+    ) -> (Option<PathBuf>, Option<PathBuf>) {
+        match self {
+            Self::Contained => {
+                let part_rw = mount_point.join("overlay_rw").join(partition);
+                let upper = part_rw.join("upperdir");
+                let work = part_rw.join("workdir");
+
+                if fs::create_dir_all(&work).is_ok() && fs::create_dir_all(&upper).is_ok() {
+                    (Some(upper), Some(work))
+                } else {
+                    tracing::warn!("Failed to create overlay directories in contained storage");
+                    (None, None)
+                }
+            }
+            Self::Split { rw_base } | Self::Direct { rw_base } => {
+                let part_rw = rw_base.join(partition);
+                let upper = part_rw.join("upperdir");
+                let work = part_rw.join("workdir");
+                if upper.exists() && work.exists() {
+                    (Some(upper), Some(work))
+                } else {
+                    (None, None)
+                }
+            }
+        }
+    }
+}
+
+pub struct StorageContext<'a> {
+    pub mnt_base: &'a Path,
+    pub img_path: &'a Path,
+    pub moduledir: &'a Path,
+    pub mount_source: &'a str,
+    pub probe: &'a ProbeReport,
+}
+
+pub trait StorageBackend {
+    fn mode(&self) -> StorageMode;
+
+    fn probe(&self, report: &ProbeReport) -> bool;
+
+    fn setup(&mut self, ctx: &StorageContext) -> Result<()>;
+
+    fn reuse_image(
+        &mut self,
+        _config: &Config,
+        _modules: &[Module],
+        _mount_point: &Path,
+    ) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn ensure_capacity(&mut self, _config: &Config, _mount_point: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn commit(&mut self, _config: &Config, _modules: &[Module], _mount_point: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn layout(&self) -> OverlayLayout {
+        OverlayLayout::Contained
+    }
+
+    fn usage(&self, mount_point: &Path) -> (u64, u64, u8) {
+        get_usage(mount_point)
+    }
+
+    fn teardown(&mut self, mount_point: &Path) -> Result<()> {
+        if utils::is_mounted(mount_point) {
+            unmount(mount_point, UnmountFlags::DETACH)
+                .with_context(|| format!("Failed to unmount {}", mount_point.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn hide(&self, mount_point: &Path, disable_umount: bool) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if !disable_umount {
+            let _ = send_unmountable(mount_point);
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = (mount_point, disable_umount);
+    }
+}
+
+pub struct StorageHandle {
+    pub mount_point: PathBuf,
+    img_path: PathBuf,
+    pub probe: ProbeReport,
+    backend: Box<dyn StorageBackend>,
+}
+
+impl StorageHandle {
+    pub fn mode(&self) -> StorageMode {
+        self.backend.mode()
+    }
+
+    pub fn layout(&self) -> OverlayLayout {
+        self.backend.layout()
+    }
+
+    pub fn usage(&self) -> (u64, u64, u8) {
+        self.backend.usage(&self.mount_point)
+    }
+
+    pub fn reuse_image(&mut self, config: &Config, modules: &[Module]) -> Result<bool> {
+        self.backend.reuse_image(config, modules, &self.mount_point)
+    }
+
+    pub fn ensure_capacity(&mut self, config: &Config) -> Result<()> {
+        self.backend.ensure_capacity(config, &self.mount_point)
+    }
+
+    pub fn commit(&mut self, config: &Config, modules: &[Module]) -> Result<()> {
+        self.backend.commit(config, modules, &self.mount_point)
+    }
+
+    pub fn fall_back(&mut self, config: &Config) -> Result<()> {
+        let failed = self.mode();
+
+        if let Err(e) = self.backend.teardown(&self.mount_point) {
+            tracing::warn!("Failed to tear down {} storage: {:#}", failed, e);
+        }
+
+        let remaining: Vec<StorageMode> = self
+            .probe
+            .ranking
+            .iter()
+            .skip_while(|mode| **mode != failed)
+            .skip(1)
+            .copied()
+            .collect();
+
+        let ctx = StorageContext {
+            mnt_base: &self.mount_point,
+            img_path: &self.img_path,
+            moduledir: &config.moduledir,
+            mount_source: &config.mountsource,
+            probe: &self.probe,
+        };
+
+        let backend = select_backend(config, &ctx, &remaining)
+            .with_context(|| format!("No storage backend left after {failed} failed"))?;
+
+        tracing::warn!("Falling back from {} to {} storage", failed, backend.mode());
+
+        self.probe.selected = Some(backend.mode());
+        self.backend = backend;
+
+        Ok(())
+    }
+}
+
+pub fn ensure_live_capacity(config: &Config, state: &RuntimeState) -> Result<()> {
+    let mut backend: Box<dyn StorageBackend> = match state.storage_mode {
+        StorageMode::Tmpfs => Box::new(TmpfsBackend),
+        StorageMode::Ext4 => Box::new(Ext4Backend::attached(config.modules_img_file())),
+        StorageMode::Erofs | StorageMode::Squashfs => return Ok(()),
+    };
+
+    backend.ensure_capacity(config, &state.mount_point)
+}
+
+fn backends() -> Vec<Box<dyn StorageBackend>> {
+    vec![
+        Box::new(TmpfsBackend),
+        Box::new(Ext4Backend::default()),
+        Box::new(ImageBackend::new(ImageFormat::Erofs)),
+        Box::new(ImageBackend::new(ImageFormat::Squashfs)),
+    ]
+}
+
+fn calculate_total_size(path: &Path) -> Result<u64> {
+    let mut total_size = 0;
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_file() {
+                total_size += entry.metadata()?.len();
+            } else if file_type.is_dir() {
+                total_size += calculate_total_size(&entry.path())?;
+            }
+        }
+    }
+    Ok(total_size)
+}
+
+pub fn setup(config: &Config, mnt_base: &Path, img_path: &Path) -> Result<StorageHandle> {
+    if utils::is_mounted(mnt_base) {
+        let _ = unmount(mnt_base, UnmountFlags::DETACH);
+    }
+
+    let mut probe = ProbeReport::run(config, mnt_base, &config.mountsource);
+
+    tracing::info!(
+        "Storage probe: {} of modules, {} bytes available, ranking {:?}",
+        probe.modules_size,
+        probe.mem_available,
+        probe.ranking
+    );
+
+    let ctx = StorageContext {
+        mnt_base,
+        img_path,
+        moduledir: &config.moduledir,
+        mount_source: &config.mountsource,
+        probe: &probe,
+    };
+
+    let backend = select_backend(config, &ctx, &probe.ranking)?;
+
+    probe.selected = Some(backend.mode());
+
+    Ok(StorageHandle {
+        mount_point: mnt_base.to_path_buf(),
+        img_path: img_path.to_path_buf(),
+        probe,
+        backend,
+    })
+}
+
+fn select_backend(
+    config: &Config,
+    ctx: &StorageContext,
+    ranking: &[StorageMode],
+) -> Result<Box<dyn StorageBackend>> {
+    let mut available = backends();
+
+    let mut last_error = None;
+
+    for &mode in ranking {
+        let Some(index) = available.iter().position(|b| b.mode() == mode) else {
+            continue;
+        };
+
+        let mut backend = available.remove(index);
+
+        match backend.setup(ctx) {
+            Ok(()) => {
+                backend.hide(ctx.mnt_base, config.disable_umount);
+
+                return Ok(backend);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to set up {} storage: {:#}", mode, e);
+
+                let _ = backend.teardown(ctx.mnt_base);
+
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        anyhow!(
+            "No storage backend is available for {:?} mode",
+            config.overlay_mode
+        )
+    }))
+}
+
+#[derive(Serialize)]
+struct StorageStatus {
+    #[serde(rename = "type")]
+    mode: String,
+    mount_point: String,
+    usage_percent: u8,
+    total_size: u64,
+    used_size: u64,
+    supported_modes: Vec<String>,
+    tmpfs_budget: TmpfsBudget,
+}
+
+pub fn get_usage(path: &Path) -> (u64, u64, u8) {
+    if let Ok(stat) = rustix::fs::statvfs(path) {
+        let total = stat.f_blocks * stat.f_frsize;
+
+        let free = stat.f_bfree * stat.f_frsize;
+
+        let used = total - free;
+
+        let percent = if total > 0 {
+            (used * 100 / total) as u8
+        } else {
+            0
+        };
+
+        (total, used, percent)
+    } else {
+        (0, 0, 0)
+    }
+}
+
+#[allow(dead_code)]
+pub fn finalize_storage_permissions(target: &Path) {
+    if let Err(e) = rustix::fs::chmod(target, Mode::from(0o755)) {
+        tracing::warn!("Failed to chmod storage root: {}", e);
+    }
+
+    if let Err(e) = rustix::fs::chown(
+        target,
+        Some(rustix::fs::Uid::from_raw(0)),
+        Some(rustix::fs::Gid::from_raw(0)),
+    ) {
+        tracing::warn!("Failed to chown storage root: {}", e);
+    }
+
+    if let Err(e) = utils::lsetfilecon(target, DEFAULT_SELINUX_CONTEXT) {
+        tracing::warn!("Failed to set SELinux context: {}", e);
+    }
+}
+
+fn live_probe(config: &Config) -> ProbeReport {
+    let probe_dir = config.run_dir().join(PROBE_DIR_NAME);
+
+    let report = ProbeReport::run(config, &probe_dir, "mh_check");
+
+    let _ = fs::remove_dir(&probe_dir);
+
+    report
+}
+
+pub fn print_probe(config: &Config) -> Result<()> {
+    let probe = RuntimeState::load(&config.state_file())
+        .ok()
+        .and_then(|s| s.storage_probe)
+        .unwrap_or_else(|| live_probe(config));
+
+    println!("{}", serde_json::to_string(&probe)?);
+
+    Ok(())
+}
+
+pub fn print_status(config: &Config) -> Result<()> {
+    let state = RuntimeState::load(&config.state_file()).ok();
+    let (mnt_base, expected_mode) = if let Some(ref s) = state {
+        (s.mount_point.clone(), s.storage_mode.to_string())
+    } else {
+        (PathBuf::from(&config.hybrid_mnt_dir), "unknown".to_string())
+    };
+
+    let mut mode = "unknown".to_string();
+
+    let mut total = 0;
+
+    let mut used = 0;
+
+    let mut percent = 0;
+
+    if utils::is_mounted(&mnt_base)
+        && let Ok(stat) = rustix::fs::statvfs(&mnt_base)
+    {
+        mode = if expected_mode != "unknown" {
+            expected_mode
+        } else {
+            "active".to_string()
+        };
+
+        total = stat.f_blocks * stat.f_frsize;
+
+        let free = stat.f_bfree * stat.f_frsize;
+
+        used = total - free;
+
+        if total > 0 {
+            percent = (used * 100 / total) as u8;
+        }
+    }
+
+    let probe = state
+        .and_then(|s| s.storage_probe)
+        .unwrap_or_else(|| live_probe(config));
+
+    let mut supported_modes = vec!["auto".to_string()];
+
+    supported_modes.extend(probe.supported_modes());
+
+    let status = StorageStatus {
+        mode,
+        mount_point: mnt_base.to_string_lossy().to_string(),
+        usage_percent: percent,
+        total_size: total,
+        used_size: used,
+        supported_modes,
+        tmpfs_budget: probe.tmpfs_budget,
+    };
+
+    println!("{}", serde_json::to_string(&status)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_modes_split_the_writable_layers() {
+        let rw_base = Config::default().system_rw_dir();
+
+        for mode in [StorageMode::Erofs, StorageMode::Squashfs] {
+            assert!(matches!(
+                OverlayLayout::from_mode(mode, &rw_base),
+                OverlayLayout::Split { rw_base: base } if base == rw_base
+            ));
+        }
+
+        for mode in [StorageMode::Tmpfs, StorageMode::Ext4] {
+            assert!(matches!(
+                OverlayLayout::from_mode(mode, &rw_base),
+                OverlayLayout::Contained
+            ));
+        }
+    }
+
+    #[test]
+    fn rw_dirs_follow_the_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            data_root: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let storage = dir.path().join("storage");
+        let rw_base = config.system_rw_dir();
+
+        let (upper, work) = OverlayLayout::Contained.rw_dirs(&storage, "system");
+
+        assert_eq!(upper, Some(storage.join("overlay_rw/system/upperdir")));
+        assert_eq!(work, Some(storage.join("overlay_rw/system/workdir")));
+        assert!(storage.join("overlay_rw/system/workdir").is_dir());
+
+        let split = OverlayLayout::from_mode(StorageMode::Erofs, &rw_base);
+
+        assert_eq!(split.rw_dirs(&storage, "vendor"), (None, None));
+
+        fs::create_dir_all(rw_base.join("vendor/upperdir")).unwrap();
+        fs::create_dir_all(rw_base.join("vendor/workdir")).unwrap();
+
+        assert_eq!(
+            split.rw_dirs(&storage, "vendor"),
+            (
+                Some(rw_base.join("vendor/upperdir")),
+                Some(rw_base.join("vendor/workdir"))
+            )
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use procfs::{Current, Meminfo};
+use rustix::mount::{UnmountFlags, unmount};
+use serde::{Deserialize, Serialize};
+
+use super::{StorageMode, backends, calculate_total_size, tmpfs::TmpfsBudget};
+use crate::{
+    conf::config::{Config, OverlayMode},
+    mount::{
+        loop_device::LOOP_CONTROL,
+        overlayfs::overlayfs::{self, OverlayFeatures},
+    },
+    utils,
+};
+
+const OVERLAY_PARAMETERS: &str = "/sys/module/overlay/parameters";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendProbe {
+    pub mode: StorageMode,
+    pub supported: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProbeReport {
+    pub tmpfs_xattr: bool,
+    pub erofs: bool,
+    pub squashfs: bool,
+    pub loop_devices: bool,
+    pub overlay: bool,
+    #[serde(default)]
+    pub overlay_features: BTreeMap<String, String>,
+    #[serde(default)]
+    pub overlay_mount_api: OverlayFeatures,
+    pub mem_available: u64,
+    pub modules_size: u64,
+    #[serde(default)]
+    pub tmpfs_budget: TmpfsBudget,
+    pub backends: Vec<BackendProbe>,
+    pub ranking: Vec<StorageMode>,
+    pub selected: Option<StorageMode>,
+}
+
+fn probe_tmpfs_xattr(scratch: &Path, mount_source: &str) -> bool {
+    if utils::mount_tmpfs(scratch, mount_source).is_err() {
+        return false;
+    }
+
+    let supported = utils::is_overlay_xattr_supported(scratch);
+
+    let _ = unmount(scratch, UnmountFlags::DETACH);
+
+    if !supported {
+        tracing::warn!("Tmpfs mounted but XATTRs (trusted.*) are NOT supported.");
+        tracing::warn!(">> Your kernel likely lacks CONFIG_TMPFS_XATTR=y.");
+    }
+
+    supported
+}
+
+fn overlay_features() -> BTreeMap<String, String> {
+    let Ok(entries) = fs::read_dir(OVERLAY_PARAMETERS) else {
+        return BTreeMap::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let value = fs::read_to_string(entry.path()).ok()?;
+
+            Some((
+                entry.file_name().to_string_lossy().to_string(),
+                value.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn preference(overlay_mode: &OverlayMode) -> Vec<StorageMode> {
+    match overlay_mode {
+        OverlayMode::Auto => vec![
+            StorageMode::Tmpfs,
+            StorageMode::Erofs,
+            StorageMode::Squashfs,
+            StorageMode::Ext4,
+        ],
+        OverlayMode::Tmpfs => vec![StorageMode::Tmpfs, StorageMode::Ext4],
+        OverlayMode::Ext4 => vec![StorageMode::Ext4],
+        OverlayMode::Erofs => vec![
+            StorageMode::Erofs,
+            StorageMode::Squashfs,
+            StorageMode::Tmpfs,
+            StorageMode::Ext4,
+        ],
+        OverlayMode::Squashfs => vec![StorageMode::Squashfs, StorageMode::Tmpfs, StorageMode::Ext4],
+    }
+}
+
+impl ProbeReport {
+    pub fn run(config: &Config, scratch: &Path, mount_source: &str) -> Self {
+        let filesystems = fs::read_to_string("/proc/filesystems").unwrap_or_default();
+
+        let has_fs = |name: &str| {
+            filesystems
+                .lines()
+                .any(|line| line.split_whitespace().last() == Some(name))
+        };
+
+        let mut report = Self {
+            tmpfs_xattr: probe_tmpfs_xattr(scratch, mount_source),
+            erofs: has_fs("erofs"),
+            squashfs: has_fs("squashfs"),
+            loop_devices: Path::new(LOOP_CONTROL).exists(),
+            overlay: has_fs("overlay"),
+            overlay_features: overlay_features(),
+            overlay_mount_api: overlayfs::features(),
+            mem_available: Meminfo::current()
+                .ok()
+                .and_then(|m| m.mem_available)
+                .unwrap_or(0),
+            modules_size: calculate_total_size(&config.moduledir).unwrap_or(0),
+            ..Default::default()
+        };
+
+        report.backends = backends()
+            .iter()
+            .map(|backend| BackendProbe {
+                mode: backend.mode(),
+                supported: backend.probe(&report),
+            })
+            .collect();
+
+        report.tmpfs_budget = TmpfsBudget::new(
+            report.modules_size,
+            report.mem_available,
+            config.tmpfs_ram_ratio,
+        );
+
+        report.rank(&config.overlay_mode);
+
+        report
+    }
+
+    fn rank(&mut self, overlay_mode: &OverlayMode) {
+        self.ranking = preference(overlay_mode)
+            .into_iter()
+            .filter(|mode| self.supports(*mode))
+            .collect();
+
+        if !self.fits_in_ram()
+            && let Some(index) = self
+                .ranking
+                .iter()
+                .position(|mode| *mode == StorageMode::Tmpfs)
+        {
+            tracing::warn!(
+                "Module payload ({} bytes) exceeds the tmpfs RAM budget ({} bytes), deferring tmpfs",
+                self.tmpfs_budget.payload,
+                self.tmpfs_budget.ram_limit
+            );
+
+            let tmpfs = self.ranking.remove(index);
+
+            self.ranking.push(tmpfs);
+        }
+    }
+
+    pub fn supports(&self, mode: StorageMode) -> bool {
+        self.backends
+            .iter()
+            .any(|backend| backend.mode == mode && backend.supported)
+    }
+
+    pub fn fits_in_ram(&self) -> bool {
+        self.tmpfs_budget.within_budget
+    }
+
+    pub fn supported_modes(&self) -> Vec<String> {
+        self.backends
+            .iter()
+            .filter(|backend| backend.supported)
+            .map(|backend| backend.mode.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [StorageMode; 4] = [
+        StorageMode::Tmpfs,
+        StorageMode::Ext4,
+        StorageMode::Erofs,
+        StorageMode::Squashfs,
+    ];
+
+    fn ranked(
+        overlay_mode: OverlayMode,
+        supported: &[StorageMode],
+        fits: bool,
+    ) -> Vec<StorageMode> {
+        let mut report = ProbeReport {
+            backends: ALL
+                .iter()
+                .map(|mode| BackendProbe {
+                    mode: *mode,
+                    supported: supported.contains(mode),
+                })
+                .collect(),
+            tmpfs_budget: TmpfsBudget {
+                within_budget: fits,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        report.rank(&overlay_mode);
+
+        report.ranking
+    }
+
+    #[test]
+    fn preference_leads_with_the_configured_mode() {
+        assert_eq!(
+            preference(&OverlayMode::Auto),
+            [
+                StorageMode::Tmpfs,
+                StorageMode::Erofs,
+                StorageMode::Squashfs,
+                StorageMode::Ext4
+            ]
+        );
+        assert_eq!(preference(&OverlayMode::Ext4), [StorageMode::Ext4]);
+
+        for (overlay_mode, first) in [
+            (OverlayMode::Tmpfs, StorageMode::Tmpfs),
+            (OverlayMode::Erofs, StorageMode::Erofs),
+            (OverlayMode::Squashfs, StorageMode::Squashfs),
+        ] {
+            let order = preference(&overlay_mode);
+
+            assert_eq!(order[0], first);
+            assert_eq!(order.last(), Some(&StorageMode::Ext4));
+        }
+    }
+
+    #[test]
+    fn ranking_skips_unsupported_backends() {
+        assert_eq!(
+            ranked(
+                OverlayMode::Auto,
+                &[StorageMode::Ext4, StorageMode::Squashfs],
+                true
+            ),
+            [StorageMode::Squashfs, StorageMode::Ext4]
+        );
+        assert!(ranked(OverlayMode::Ext4, &[StorageMode::Tmpfs], true).is_empty());
+    }
+
+    #[test]
+    fn tmpfs_is_deferred_when_modules_exceed_the_ram_budget() {
+        assert_eq!(
+            ranked(OverlayMode::Auto, &ALL, false),
+            [
+                StorageMode::Erofs,
+                StorageMode::Squashfs,
+                StorageMode::Ext4,
+                StorageMode::Tmpfs
+            ]
+        );
+        assert_eq!(
+            ranked(OverlayMode::Tmpfs, &ALL, false),
+            [StorageMode::Ext4, StorageMode::Tmpfs]
+        );
+        assert_eq!(
+            ranked(OverlayMode::Tmpfs, &ALL, true),
+            [StorageMode::Tmpfs, StorageMode::Ext4]
+        );
+    }
+}
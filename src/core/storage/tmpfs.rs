@@ -0,0 +1,160 @@
+use std::path::Path;
+
+use anyhow::{Result, ensure};
+use procfs::{Current, Meminfo};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ProbeReport, StorageBackend, StorageContext, StorageMode, calculate_total_size, get_usage,
+    image,
+};
+use crate::{conf::config::Config, utils};
+
+const TMPFS_MIN_HEADROOM: u64 = 64 * 1024 * 1024;
+const TMPFS_SIZE_ALIGN: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TmpfsBudget {
+    pub payload: u64,
+    pub limit: u64,
+    pub ram_ratio: f64,
+    pub ram_limit: u64,
+    pub within_budget: bool,
+}
+
+impl TmpfsBudget {
+    pub fn new(payload: u64, mem_available: u64, ram_ratio: f64) -> Self {
+        let ram_ratio = ram_ratio.clamp(0.0, 1.0);
+
+        let headroom = (payload / 4).max(TMPFS_MIN_HEADROOM);
+
+        let limit = (payload + headroom).div_ceil(TMPFS_SIZE_ALIGN) * TMPFS_SIZE_ALIGN;
+
+        let ram_limit = (mem_available as f64 * ram_ratio) as u64;
+
+        Self {
+            payload,
+            limit,
+            ram_ratio,
+            ram_limit,
+            within_budget: mem_available == 0 || payload <= ram_limit,
+        }
+    }
+}
+
+pub struct TmpfsBackend;
+
+impl StorageBackend for TmpfsBackend {
+    fn mode(&self) -> StorageMode {
+        StorageMode::Tmpfs
+    }
+
+    fn probe(&self, report: &ProbeReport) -> bool {
+        report.tmpfs_xattr
+    }
+
+    fn setup(&mut self, ctx: &StorageContext) -> Result<()> {
+        let budget = &ctx.probe.tmpfs_budget;
+
+        if !budget.within_budget {
+            tracing::warn!(
+                "Module payload ({} bytes) exceeds {:.0}% of available RAM ({} bytes), using tmpfs as a last resort",
+                budget.payload,
+                budget.ram_ratio * 100.0,
+                budget.ram_limit
+            );
+        }
+
+        utils::mount_tmpfs_sized(ctx.mnt_base, ctx.mount_source, Some(budget.limit))?;
+
+        tracing::info!("Tmpfs mounted and supports xattrs (CONFIG_TMPFS_XATTR=y).");
+        tracing::info!(
+            "Tmpfs limited to {} bytes for a {} byte payload",
+            budget.limit,
+            budget.payload
+        );
+
+        image::remove_stale_images(ctx.img_path, None);
+
+        Ok(())
+    }
+
+    fn ensure_capacity(&mut self, config: &Config, mount_point: &Path) -> Result<()> {
+        let (total, used, _) = get_usage(mount_point);
+
+        let payload = calculate_total_size(&config.moduledir)?
+            + calculate_total_size(&mount_point.join("overlay_rw"))?;
+
+        let mem_available = Meminfo::current()
+            .ok()
+            .and_then(|m| m.mem_available)
+            .unwrap_or(0);
+
+        let budget = TmpfsBudget::new(payload, mem_available + used, config.tmpfs_ram_ratio);
+
+        if total >= budget.limit {
+            return Ok(());
+        }
+
+        let target = if mem_available == 0 {
+            budget.limit
+        } else {
+            budget.limit.min(budget.ram_limit)
+        };
+
+        ensure!(
+            target > total,
+            "Tmpfs holds {} bytes and cannot grow within {:.0}% of RAM ({} bytes)",
+            total,
+            budget.ram_ratio * 100.0,
+            budget.ram_limit
+        );
+
+        tracing::info!(
+            "Modules need ~{} bytes but tmpfs holds {}, growing it to {} bytes",
+            payload,
+            total,
+            target
+        );
+
+        utils::resize_tmpfs(mount_point, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+    const GIB: u64 = 1024 * MIB;
+
+    #[test]
+    fn small_payloads_get_the_minimum_headroom() {
+        let budget = TmpfsBudget::new(1000, 8 * GIB, 0.25);
+
+        assert_eq!(budget.limit, 65 * MIB);
+        assert_eq!(budget.ram_limit, 2 * GIB);
+        assert!(budget.within_budget);
+    }
+
+    #[test]
+    fn large_payloads_get_a_quarter_headroom_and_can_exceed_the_budget() {
+        let budget = TmpfsBudget::new(GIB, 2 * GIB, 0.25);
+
+        assert_eq!(budget.limit, GIB + 256 * MIB);
+        assert_eq!(budget.ram_limit, 512 * MIB);
+        assert!(!budget.within_budget);
+    }
+
+    #[test]
+    fn unknown_memory_and_out_of_range_ratios() {
+        assert!(TmpfsBudget::new(GIB, 0, 0.25).within_budget);
+        assert_eq!(TmpfsBudget::new(GIB, 2 * GIB, 2.0).ram_ratio, 1.0);
+
+        let none = TmpfsBudget::new(1, 2 * GIB, -1.0);
+
+        assert_eq!(none.ram_ratio, 0.0);
+        assert_eq!(none.ram_limit, 0);
+        assert!(!none.within_budget);
+    }
+}
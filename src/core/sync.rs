@@ -1,15 +1,24 @@
-use std::{collections::HashSet, fs, path::Path};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::Path,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rayon::prelude::*;
+use serde::Serialize;
 use walkdir::WalkDir;
 
 use crate::{
-    core::inventory::{Module, MountMode},
+    core::{
+        file_contexts,
+        inventory::Module,
+        manifest::{Manifest, ManifestDiff},
+    },
     defs, utils,
 };
 
-pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
+pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<Vec<SyncReport>> {
     tracing::info!("Starting smart module sync to {}", target_base.display());
 
     if !crate::utils::is_mounted(target_base) {
@@ -17,52 +26,183 @@ pub fn perform_sync(modules: &[Module], target_base: &Path) -> Result<()> {
             "Storage {} is not mounted, skipping sync.",
             target_base.display()
         );
-        return Ok(());
+        return Ok(Vec::new());
     }
 
     prune_orphaned_modules(modules, target_base)?;
 
-    modules.par_iter().for_each(|module| {
-        if matches!(module.rules.default_mode, MountMode::Magic) {
-            tracing::debug!("Skipping sync for Magic Mount module: {}", module.id);
+    Ok(sync_modules(modules, target_base))
+}
 
-            return;
-        }
+#[derive(Debug, Serialize)]
+pub struct SyncReport {
+    pub id: String,
+    pub full: bool,
+    #[serde(flatten)]
+    pub changes: ManifestDiff,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub preserve_failures: Vec<utils::PreserveFailure>,
+}
 
-        let dst = target_base.join(&module.id);
+pub fn sync_modules(modules: &[Module], target_base: &Path) -> Vec<SyncReport> {
+    modules
+        .par_iter()
+        .filter_map(|module| {
+            if !module.rules.uses_overlay() {
+                tracing::debug!("Skipping sync for Magic Mount module: {}", module.id);
 
-        let has_content = defs::BUILTIN_PARTITIONS.iter().any(|p| {
-            let part_path = module.source_path.join(p);
+                return None;
+            }
 
-            part_path.exists() && has_files_recursive(&part_path)
-        });
+            let dst = target_base.join(&module.id);
 
-        if has_content && should_sync(&module.source_path, &dst) {
-            tracing::info!("Syncing module: {} (Updated/New)", module.id);
+            if !has_content(module) {
+                tracing::debug!("Skipping module: {}", module.id);
 
-            if dst.exists()
-                && let Err(e) = fs::remove_dir_all(&dst)
-            {
-                tracing::warn!("Failed to clean target dir for {}: {}", module.id, e);
+                return None;
             }
 
-            if let Err(e) = utils::sync_dir(&module.source_path, &dst, true) {
-                tracing::error!("Failed to sync module {}: {}", module.id, e);
-            } else {
-                if let Err(e) = apply_overlay_opaque_flags(&dst) {
-                    tracing::warn!(
-                        "Failed to apply overlay opaque xattrs for {}: {}",
-                        module.id,
-                        e
-                    );
+            match sync_module(module, &dst) {
+                Ok(report) => {
+                    if !report.changes.is_empty() {
+                        tracing::info!(
+                            "Synced module {}: +{} ~{} -{}{}",
+                            module.id,
+                            report.changes.added.len(),
+                            report.changes.modified.len(),
+                            report.changes.removed.len(),
+                            if report.full { " (full copy)" } else { "" }
+                        );
+                    } else {
+                        tracing::debug!("Module {} is up to date", module.id);
+                    }
+
+                    if !report.preserve_failures.is_empty() {
+                        tracing::warn!(
+                            "Module {}: failed to preserve metadata of {} entries",
+                            module.id,
+                            report.preserve_failures.len()
+                        );
+                    }
+
+                    Some(report)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to sync module {}: {:#}", module.id, e);
+
+                    None
                 }
             }
-        } else {
-            tracing::debug!("Skipping module: {}", module.id);
+        })
+        .collect()
+}
+
+fn sync_module(module: &Module, dst: &Path) -> Result<SyncReport> {
+    let manifest_path = dst.join(defs::SYNC_MANIFEST_FILE_NAME);
+
+    let previous = dst
+        .is_dir()
+        .then(|| Manifest::load(&manifest_path))
+        .flatten();
+
+    let current = Manifest::build(&module.source_path, previous.as_ref())?;
+
+    let changes = match &previous {
+        Some(previous) => previous.diff(&current),
+        None => ManifestDiff {
+            added: current.files.keys().cloned().collect(),
+            ..Default::default()
+        },
+    };
+
+    let full = previous.is_none()
+        || changes.removed.iter().any(|p| {
+            Path::new(p)
+                .file_name()
+                .is_some_and(|name| name == defs::REPLACE_DIR_FILE_NAME)
+        });
+
+    let preserve_failures = if full {
+        if dst.exists()
+            && let Err(e) = fs::remove_dir_all(dst)
+        {
+            tracing::warn!("Failed to clean target dir for {}: {}", module.id, e);
         }
-    });
 
-    Ok(())
+        utils::sync_dir(&module.source_path, dst, Some(&file_contexts::context_for))?
+    } else if !changes.is_empty() {
+        fs::remove_file(&manifest_path)?;
+
+        apply_changes(&module.source_path, dst, &changes)?
+    } else {
+        Vec::new()
+    };
+
+    if (full || !changes.is_empty())
+        && let Err(e) = apply_overlay_opaque_flags(dst)
+    {
+        tracing::warn!(
+            "Failed to apply overlay opaque xattrs for {}: {}",
+            module.id,
+            e
+        );
+    }
+
+    current.save(&manifest_path)?;
+
+    Ok(SyncReport {
+        id: module.id.clone(),
+        full,
+        changes,
+        preserve_failures,
+    })
+}
+
+fn apply_changes(
+    src: &Path,
+    dst: &Path,
+    changes: &ManifestDiff,
+) -> Result<Vec<utils::PreserveFailure>> {
+    for relative in changes.removed.iter().rev() {
+        let path = dst.join(relative);
+
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => fs::remove_dir_all(&path)?,
+            Ok(_) => fs::remove_file(&path)?,
+            Err(_) => {}
+        }
+    }
+
+    let updated: BTreeSet<&String> = changes.added.iter().chain(&changes.modified).collect();
+
+    let mut failures = Vec::new();
+
+    for relative in updated {
+        failures.extend(
+            utils::sync_entry(
+                src,
+                dst,
+                Path::new(relative),
+                Some(&file_contexts::context_for),
+            )
+            .with_context(|| format!("Failed to sync {}", relative))?,
+        );
+    }
+
+    let touched: BTreeSet<&Path> = changes
+        .added
+        .iter()
+        .chain(&changes.modified)
+        .chain(&changes.removed)
+        .flat_map(|relative| Path::new(relative).ancestors())
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .collect();
+
+    for relative in touched.into_iter().rev() {
+        failures.extend(utils::sync_dir_metadata(src, dst, relative));
+    }
+
+    Ok(failures)
 }
 
 fn apply_overlay_opaque_flags(root: &Path) -> Result<()> {
@@ -97,6 +237,7 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
         if name != "lost+found"
             && name != "meta-hybrid"
             && name != "overlay_rw"
+            && name != defs::WINNOW_LAYER_DIR_NAME
             && !active_ids.contains(name.as_ref())
         {
             tracing::info!("Pruning orphaned module storage: {}", name);
@@ -114,23 +255,12 @@ fn prune_orphaned_modules(modules: &[Module], target_base: &Path) -> Result<()>
     Ok(())
 }
 
-fn should_sync(src: &Path, dst: &Path) -> bool {
-    if !dst.exists() {
-        return true;
-    }
-
-    let src_prop = src.join("module.prop");
-
-    let dst_prop = dst.join("module.prop");
+pub fn has_content(module: &Module) -> bool {
+    defs::BUILTIN_PARTITIONS.iter().any(|p| {
+        let part_path = module.source_path.join(p);
 
-    if !src_prop.exists() || !dst_prop.exists() {
-        return true;
-    }
-
-    match (fs::read(&src_prop), fs::read(&dst_prop)) {
-        (Ok(s), Ok(d)) => s != d,
-        _ => true,
-    }
+        part_path.exists() && has_files_recursive(&part_path)
+    })
 }
 
 fn has_files_recursive(path: &Path) -> bool {
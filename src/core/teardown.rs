@@ -0,0 +1,173 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use rustix::{
+    io::Errno,
+    mount::{UnmountFlags, unmount},
+};
+use serde::Serialize;
+
+use crate::{
+    conf::config::Config,
+    core::state::RuntimeState,
+    mount::{
+        journal::{self, JournalEntry, JournalKind},
+        record::{self, MountRecord, RecordKind},
+    },
+};
+
+#[derive(Serialize)]
+pub struct FailedUnmount {
+    pub target: PathBuf,
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct SkippedMount {
+    pub target: PathBuf,
+    pub modules: Vec<String>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct TeardownReport {
+    pub unmounted: Vec<PathBuf>,
+    pub failed: Vec<FailedUnmount>,
+    pub skipped: Vec<SkippedMount>,
+}
+
+fn detach(target: &Path) -> std::result::Result<(), Errno> {
+    let result = match unmount(target, UnmountFlags::empty()) {
+        Err(Errno::BUSY) => {
+            tracing::warn!("{} is busy, detaching lazily", target.display());
+            unmount(target, UnmountFlags::DETACH)
+        }
+        other => other,
+    };
+
+    journal::log(JournalEntry::new(JournalKind::Unmount, "", target), &result);
+
+    result
+}
+
+fn select(records: &[MountRecord], module: Option<&str>, report: &mut TeardownReport) -> Vec<bool> {
+    records
+        .iter()
+        .map(|r| match module {
+            None => true,
+            Some(id) if r.modules.len() == 1 && r.modules[0] == id => true,
+            Some(id) if r.modules.iter().any(|m| m == id) => {
+                report.skipped.push(SkippedMount {
+                    target: r.target.clone(),
+                    modules: r.modules.clone(),
+                    reason: "shared with other modules, remount required".to_string(),
+                });
+                false
+            }
+            Some(_) => false,
+        })
+        .collect()
+}
+
+pub fn teardown(config: &Config, module: Option<&str>) -> Result<TeardownReport> {
+    let journal_path = config.mount_journal_file();
+
+    let records =
+        record::load(&journal_path).context("No mount journal from a previous session")?;
+
+    if let Err(e) = journal::resume(&journal_path) {
+        tracing::warn!("Failed to open mount journal: {:#}", e);
+    }
+
+    let mut report = TeardownReport::default();
+
+    let selected = select(&records, module, &mut report);
+
+    let mut removed = vec![false; records.len()];
+
+    for (i, r) in records.iter().enumerate().rev() {
+        if !selected[i] {
+            continue;
+        }
+
+        tracing::info!("unmounting {:?} {}", r.kind, r.target.display());
+
+        match detach(&r.target) {
+            Ok(()) => {
+                report.unmounted.push(r.target.clone());
+                removed[i] = true;
+            }
+            Err(Errno::INVAL) | Err(Errno::NOENT) => {
+                report.skipped.push(SkippedMount {
+                    target: r.target.clone(),
+                    modules: r.modules.clone(),
+                    reason: "not mounted".to_string(),
+                });
+                removed[i] = true;
+            }
+            Err(e) => report.failed.push(FailedUnmount {
+                target: r.target.clone(),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    let remaining: Vec<MountRecord> = records
+        .iter()
+        .zip(&removed)
+        .filter(|(_, removed)| !**removed)
+        .map(|(r, _)| r.clone())
+        .collect();
+
+    update_state(config, &records, &removed, &remaining)?;
+
+    Ok(report)
+}
+
+fn update_state(
+    config: &Config,
+    records: &[MountRecord],
+    removed: &[bool],
+    remaining: &[MountRecord],
+) -> Result<()> {
+    let state_path = config.state_file();
+
+    let mut state = RuntimeState::load(&state_path)?;
+
+    let still_mounted: HashSet<&String> = remaining.iter().flat_map(|r| &r.modules).collect();
+
+    state
+        .overlay_modules
+        .retain(|id| still_mounted.contains(id));
+    state.magic_modules.retain(|id| still_mounted.contains(id));
+
+    let gone_overlays: Vec<&PathBuf> = records
+        .iter()
+        .zip(removed)
+        .filter(|(r, removed)| **removed && r.kind == RecordKind::Overlay)
+        .map(|(r, _)| &r.target)
+        .collect();
+
+    state.active_mounts.retain(|partition| {
+        let target = config.system_path(partition);
+        let target = target.canonicalize().unwrap_or(target);
+
+        !gone_overlays.contains(&&target)
+    });
+
+    state.save(&state_path)
+}
+
+pub fn print_teardown(config: &Config, module: Option<&str>) -> Result<()> {
+    let report = teardown(config, module)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
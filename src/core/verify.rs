@@ -0,0 +1,413 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    conf::config::Config,
+    core::{
+        file_contexts,
+        inventory::{self, Module},
+        manifest::{self, EntryKind, Manifest},
+        state::RuntimeState,
+        sync,
+    },
+    defs, utils,
+};
+
+const SELINUX_ENFORCE: &str = "/sys/fs/selinux/enforce";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Attribute {
+    Missing,
+    Extra,
+    Type,
+    Mode,
+    Owner,
+    Content,
+    Capability,
+    Symlink,
+    Device,
+    Context,
+    Opaque,
+    Xattr,
+}
+
+#[derive(Serialize)]
+pub struct Mismatch {
+    pub path: String,
+    pub attributes: Vec<Attribute>,
+    pub repaired: bool,
+}
+
+#[derive(Serialize)]
+pub struct ModuleVerification {
+    pub id: String,
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+#[derive(Serialize)]
+pub struct VerifyReport {
+    pub storage_root: PathBuf,
+    pub repair: bool,
+    pub read_only: bool,
+    pub modules: Vec<ModuleVerification>,
+}
+
+fn labels_available(dst_root: &Path) -> bool {
+    Path::new(SELINUX_ENFORCE).exists() || utils::lgetfilecon(dst_root).is_ok()
+}
+
+fn compare_entry(
+    src: &Path,
+    dst: &Path,
+    relative: &Path,
+    check_context: bool,
+) -> Result<Vec<Attribute>> {
+    let src_meta = fs::symlink_metadata(src)?;
+
+    let Ok(dst_meta) = fs::symlink_metadata(dst) else {
+        return Ok(vec![Attribute::Missing]);
+    };
+
+    let kind = EntryKind::of(&src_meta);
+
+    if kind != EntryKind::of(&dst_meta) {
+        return Ok(vec![Attribute::Type]);
+    }
+
+    let mut attributes = Vec::new();
+
+    if kind != EntryKind::Symlink && src_meta.mode() & 0o7777 != dst_meta.mode() & 0o7777 {
+        attributes.push(Attribute::Mode);
+    }
+
+    if src_meta.uid() != dst_meta.uid() || src_meta.gid() != dst_meta.gid() {
+        attributes.push(Attribute::Owner);
+    }
+
+    match kind {
+        EntryKind::File => {
+            if src_meta.len() != dst_meta.len()
+                || manifest::hash_file(src)? != manifest::hash_file(dst)?
+            {
+                attributes.push(Attribute::Content);
+            }
+
+            if utils::file_capability(src) != utils::file_capability(dst) {
+                attributes.push(Attribute::Capability);
+            }
+        }
+        EntryKind::Symlink => {
+            if fs::read_link(src)? != fs::read_link(dst)? {
+                attributes.push(Attribute::Symlink);
+            }
+        }
+        EntryKind::Device => {
+            if src_meta.rdev() != dst_meta.rdev() {
+                attributes.push(Attribute::Device);
+            }
+        }
+        EntryKind::Dir => {
+            let opaque = src.join(defs::REPLACE_DIR_FILE_NAME).is_file();
+
+            if utils::is_overlay_opaque(dst) != opaque {
+                attributes.push(Attribute::Opaque);
+            }
+        }
+    }
+
+    if utils::extra_xattrs(src) != utils::extra_xattrs(dst) {
+        attributes.push(Attribute::Xattr);
+    }
+
+    if check_context {
+        let expected = file_contexts::label_for(relative, file_contexts::file_type(&src_meta));
+
+        if utils::lgetfilecon(dst).ok() != Some(expected.context) {
+            attributes.push(Attribute::Context);
+        }
+    }
+
+    Ok(attributes)
+}
+
+fn repair_entry(
+    src_root: &Path,
+    dst_root: &Path,
+    relative: &Path,
+    attributes: &[Attribute],
+) -> Result<()> {
+    let src = src_root.join(relative);
+
+    let dst = dst_root.join(relative);
+
+    if attributes != [Attribute::Opaque] {
+        for failure in utils::sync_entry(
+            src_root,
+            dst_root,
+            relative,
+            Some(&file_contexts::context_for),
+        )? {
+            tracing::warn!(
+                "Failed to preserve {} of {}: {}",
+                failure.attribute,
+                dst.display(),
+                failure.error
+            );
+        }
+    }
+
+    if src.is_dir() && !src.is_symlink() {
+        if src.join(defs::REPLACE_DIR_FILE_NAME).is_file() {
+            utils::set_overlay_opaque(&dst)?;
+        } else if utils::is_overlay_opaque(&dst) {
+            utils::clear_overlay_opaque(&dst)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_extra(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+
+    Ok(())
+}
+
+fn verify_module(module: &Module, dst: &Path, repair: bool) -> Result<ModuleVerification> {
+    let src = &module.source_path;
+
+    let check_context = labels_available(dst);
+
+    if !check_context {
+        tracing::debug!(
+            "No SELinux labels on {}, skipping context checks",
+            dst.display()
+        );
+    }
+
+    let mut report = ModuleVerification {
+        id: module.id.clone(),
+        checked: 0,
+        mismatches: Vec::new(),
+    };
+
+    let mut walker = WalkDir::new(dst)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter();
+
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+
+        if entry.depth() == 1 && entry.file_name() == defs::SYNC_MANIFEST_FILE_NAME {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(dst)?;
+
+        if fs::symlink_metadata(src.join(relative)).is_ok() {
+            continue;
+        }
+
+        if entry.file_type().is_dir() {
+            walker.skip_current_dir();
+        }
+
+        report.mismatches.push(Mismatch {
+            path: relative.to_string_lossy().to_string(),
+            attributes: vec![Attribute::Extra],
+            repaired: repair && remove_extra(entry.path()).is_ok(),
+        });
+    }
+
+    for entry in WalkDir::new(src).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+
+        let relative = entry.path().strip_prefix(src)?;
+
+        let dst_path = dst.join(relative);
+
+        report.checked += 1;
+
+        let attributes = compare_entry(entry.path(), &dst_path, relative, check_context)
+            .with_context(|| format!("Failed to compare {}", relative.display()))?;
+
+        if attributes.is_empty() {
+            continue;
+        }
+
+        let repaired = repair
+            && match repair_entry(src, dst, relative, &attributes) {
+                Ok(()) => compare_entry(entry.path(), &dst_path, relative, check_context)
+                    .is_ok_and(|remaining| remaining.is_empty()),
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to repair {}/{}: {:#}",
+                        module.id,
+                        relative.display(),
+                        e
+                    );
+                    false
+                }
+            };
+
+        report.mismatches.push(Mismatch {
+            path: relative.to_string_lossy().to_string(),
+            attributes,
+            repaired,
+        });
+    }
+
+    if repair && report.mismatches.iter().any(|m| m.repaired) {
+        let manifest_path = dst.join(defs::SYNC_MANIFEST_FILE_NAME);
+
+        let previous = Manifest::load(&manifest_path);
+
+        Manifest::build(src, previous.as_ref())?.save(&manifest_path)?;
+    }
+
+    Ok(report)
+}
+
+pub fn verify(config: &Config, repair: bool) -> Result<VerifyReport> {
+    let state = RuntimeState::load(&config.state_file())?;
+
+    let storage_root = state.mount_point.clone();
+
+    if storage_root.as_os_str().is_empty() || !utils::is_mounted(&storage_root) {
+        bail!("Storage is not mounted, nothing to verify");
+    }
+
+    let read_only = state.storage_mode.is_read_only();
+
+    if repair && read_only {
+        tracing::warn!(
+            "{} storage is read-only, mismatches will only be reported",
+            state.storage_mode
+        );
+    }
+
+    let modules = inventory::scan(&config.moduledir, config)?;
+
+    let mut report = VerifyReport {
+        storage_root: storage_root.clone(),
+        repair: repair && !read_only,
+        read_only,
+        modules: Vec::new(),
+    };
+
+    for module in modules
+        .iter()
+        .filter(|m| m.rules.uses_overlay() && sync::has_content(m))
+    {
+        let dst = storage_root.join(&module.id);
+
+        let verification = verify_module(module, &dst, report.repair)
+            .with_context(|| format!("Failed to verify module {}", module.id))?;
+
+        if !verification.mismatches.is_empty() {
+            tracing::info!(
+                "Module {}: {} mismatch(es) in {} entries",
+                module.id,
+                verification.mismatches.len(),
+                verification.checked
+            );
+        }
+
+        report.modules.push(verification);
+    }
+
+    Ok(report)
+}
+
+pub fn print_verify(config: &Config, repair: bool) -> Result<()> {
+    let report = verify(config, repair)?;
+
+    println!("{}", serde_json::to_string(&report)?);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use extattr::{Flags, lsetxattr};
+
+    use super::*;
+
+    #[test]
+    fn compare_entry_reports_and_repairs_xattrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src_root, dst_root) = (dir.path().join("src"), dir.path().join("dst"));
+        let relative = Path::new("system/etc/test.conf");
+        let (src, dst) = (src_root.join(relative), dst_root.join(relative));
+
+        fs::create_dir_all(src.parent().unwrap()).unwrap();
+        fs::create_dir_all(dst.parent().unwrap()).unwrap();
+        fs::write(&src, "same\n").unwrap();
+        fs::write(&dst, "same\n").unwrap();
+
+        let has_xattr_mismatch = || {
+            compare_entry(&src, &dst, relative, true)
+                .unwrap()
+                .contains(&Attribute::Xattr)
+        };
+
+        assert!(!has_xattr_mismatch());
+
+        lsetxattr(&src, "user.meta_hybrid", b"one", Flags::empty()).unwrap();
+        lsetxattr(&dst, "user.meta_hybrid", b"two", Flags::empty()).unwrap();
+        lsetxattr(&dst, "user.stale", b"stale", Flags::empty()).unwrap();
+
+        assert!(has_xattr_mismatch());
+
+        utils::sync_entry(
+            &src_root,
+            &dst_root,
+            relative,
+            Some(&file_contexts::context_for),
+        )
+        .unwrap();
+
+        assert!(!has_xattr_mismatch());
+    }
+
+    #[test]
+    fn compare_entry_skips_contexts_without_labels() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let relative = Path::new("system/etc/test.conf");
+
+        fs::write(&src, "same\n").unwrap();
+        fs::write(&dst, "same\n").unwrap();
+        let _ = extattr::lremovexattr(&dst, "security.selinux");
+
+        assert!(
+            compare_entry(&src, &dst, relative, true)
+                .unwrap()
+                .contains(&Attribute::Context)
+        );
+        assert!(
+            compare_entry(&src, &dst, relative, false)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
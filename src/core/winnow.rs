@@ -1,11 +1,25 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
 
-use crate::{conf::config::WinnowingTable, core::planner::ConflictEntry};
+use crate::{
+    conf::config::{Config, WinnowingTable},
+    core::{
+        inventory::{Module, MountMode},
+        planner::ConflictEntry,
+    },
+    defs, utils,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChaffConflict {
@@ -28,13 +42,13 @@ pub fn sift_conflicts(conflicts: Vec<ConflictEntry>, table: &WinnowingTable) ->
                     forced.clone()
                 } else {
                     c.contending_modules
-                        .last()
+                        .first()
                         .unwrap_or(&"unknown".to_string())
                         .clone()
                 }
             } else {
                 c.contending_modules
-                    .last()
+                    .first()
                     .unwrap_or(&"unknown".to_string())
                     .clone()
             };
@@ -48,3 +62,179 @@ pub fn sift_conflicts(conflicts: Vec<ConflictEntry>, table: &WinnowingTable) ->
         })
         .collect()
 }
+
+pub fn override_layer(storage_root: &Path, partition: &str) -> Option<PathBuf> {
+    let layer = storage_root
+        .join(defs::WINNOW_LAYER_DIR_NAME)
+        .join(partition);
+
+    layer.is_dir().then_some(layer)
+}
+
+pub fn is_override_layer(layer_path: &Path) -> bool {
+    layer_path
+        .components()
+        .any(|c| c.as_os_str() == defs::WINNOW_LAYER_DIR_NAME)
+}
+
+pub fn build_override_layer(
+    config: &Config,
+    modules: &[Module],
+    storage_root: &Path,
+    only_partition: Option<&str>,
+) -> Result<usize> {
+    let layer_root = storage_root.join(defs::WINNOW_LAYER_DIR_NAME);
+
+    let stale = match only_partition {
+        Some(partition) => layer_root.join(partition),
+        None => layer_root.clone(),
+    };
+
+    if stale.exists() {
+        fs::remove_dir_all(&stale)
+            .with_context(|| format!("Failed to clear winnow layer {}", stale.display()))?;
+    }
+
+    let table = &config.winnowing;
+
+    let mut partitions = defs::BUILTIN_PARTITIONS.to_vec();
+
+    partitions.extend(config.partitions.iter().map(|s| s.as_str()));
+
+    let targets: BTreeSet<&String> = table.rules.values().collect();
+
+    let mut enforced = 0;
+
+    for module_id in targets {
+        let Some(module) = modules.iter().find(|m| &m.id == module_id) else {
+            tracing::warn!(
+                "Winnowing target {} is not an enabled module, skipping",
+                module_id
+            );
+
+            continue;
+        };
+
+        for partition in &partitions {
+            if only_partition.is_some_and(|p| !Path::new(p).starts_with(partition)) {
+                continue;
+            }
+
+            let module_partition = storage_root.join(module_id).join(partition);
+
+            if !module_partition.is_dir() {
+                continue;
+            }
+
+            let layer_partition = layer_root.join(partition);
+
+            for entry in WalkDir::new(&module_partition)
+                .min_depth(1)
+                .into_iter()
+                .flatten()
+            {
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+
+                let Ok(relative) = entry.path().strip_prefix(&module_partition) else {
+                    continue;
+                };
+
+                let rule_path = Path::new(partition).join(relative);
+
+                if only_partition.is_some_and(|p| !rule_path.starts_with(p)) {
+                    continue;
+                }
+
+                if !matches!(
+                    module.rules.get_mode(&rule_path.to_string_lossy()),
+                    MountMode::Overlay
+                ) {
+                    tracing::debug!(
+                        "Winnowing for {}/{} skipped, path is not overlay mounted",
+                        module_id,
+                        rule_path.display()
+                    );
+
+                    continue;
+                }
+
+                let system_path = Path::new("/").join(partition).join(relative);
+
+                if table.get_preferred_module(&system_path).as_ref() != Some(module_id) {
+                    continue;
+                }
+
+                if let Err(e) = place_override(&module_partition, &layer_partition, relative) {
+                    tracing::warn!(
+                        "Failed to place winnowing override {} -> {}: {:#}",
+                        system_path.display(),
+                        module_id,
+                        e
+                    );
+
+                    let _ = fs::remove_file(layer_partition.join(relative));
+
+                    continue;
+                }
+
+                tracing::debug!(
+                    "Winnowing override: {} -> {}",
+                    system_path.display(),
+                    module_id
+                );
+
+                enforced += 1;
+            }
+        }
+    }
+
+    Ok(enforced)
+}
+
+fn place_override(module_partition: &Path, layer_partition: &Path, relative: &Path) -> Result<()> {
+    let mut src_dir = module_partition.to_path_buf();
+
+    let mut dst_dir = layer_partition.to_path_buf();
+
+    mirror_dir(&src_dir, &dst_dir)?;
+
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            src_dir.push(component);
+
+            dst_dir.push(component);
+
+            mirror_dir(&src_dir, &dst_dir)?;
+        }
+    }
+
+    let src = module_partition.join(relative);
+
+    let dst = layer_partition.join(relative);
+
+    if fs::hard_link(&src, &dst).is_err() {
+        utils::reflink_or_copy(&src, &dst)?;
+
+        utils::copy_path_context(&src, &dst)?;
+    }
+
+    Ok(())
+}
+
+fn mirror_dir(src: &Path, dst: &Path) -> Result<()> {
+    if dst.is_dir() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(dst)?;
+
+    let meta = fs::metadata(src)?;
+
+    fs::set_permissions(dst, meta.permissions())?;
+
+    std::os::unix::fs::lchown(dst, Some(meta.uid()), Some(meta.gid()))?;
+
+    utils::copy_path_context(src, dst)
+}
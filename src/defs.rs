@@ -1,14 +1,19 @@
 pub const DEFAULT_HYBRID_MNT_DIR: &str = "/debug_ramdisk";
-pub const MODULES_IMG_FILE: &str = "/data/adb/meta-hybrid/modules.img";
-pub const RUN_DIR: &str = "/data/adb/meta-hybrid/run/";
-pub const STATE_FILE: &str = "/data/adb/meta-hybrid/run/daemon_state.json";
-pub const DAEMON_LOG_FILE: &str = "/data/adb/meta-hybrid/daemon.log";
+pub const DEFAULT_DATA_ROOT: &str = "/data/adb/meta-hybrid";
+pub const DEFAULT_SYSTEM_ROOT: &str = "/";
+pub const MODULES_IMG_FILE_NAME: &str = "modules.img";
+pub const RUN_DIR_NAME: &str = "run";
+pub const STATE_FILE_NAME: &str = "daemon_state.json";
+pub const MOUNT_JOURNAL_FILE_NAME: &str = "mount_journal.jsonl";
+pub const DAEMON_LOG_FILE_NAME: &str = "daemon.log";
+pub const SYSTEM_RW_DIR_NAME: &str = "rw";
+pub const RULES_DIR_NAME: &str = "rules";
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+pub const SELF_MODULE_ID: &str = "meta-hybrid";
+pub const MODULE_PROP_FILE_NAME: &str = "module.prop";
 pub const DISABLE_FILE_NAME: &str = "disable";
 pub const REMOVE_FILE_NAME: &str = "remove";
 pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
-pub const SYSTEM_RW_DIR: &str = "/data/adb/meta-hybrid/rw";
-pub const MODULE_PROP_FILE: &str = "/data/adb/modules/meta-hybrid/module.prop";
-pub const MODULES_DIR: &str = "/data/adb/modules";
 pub const BUILTIN_PARTITIONS: &[&str] = &[
     "system",
     "vendor",
@@ -19,5 +24,23 @@ pub const BUILTIN_PARTITIONS: &[&str] = &[
     "apex",
 ];
 pub const REPLACE_DIR_FILE_NAME: &str = ".replace";
+pub const WINNOW_LAYER_DIR_NAME: &str = ".winnow";
+pub const MERGED_LAYER_DIR_NAME: &str = ".merged";
+pub const DATA_LAYER_DIR_NAME: &str = ".datadir";
+pub const SYNC_MANIFEST_FILE_NAME: &str = ".sync_manifest.json";
+pub const IMAGE_FINGERPRINT_FILE_NAME: &str = ".image_fingerprint";
 pub const REPLACE_DIR_XATTR: &str = "trusted.overlay.opaque";
+pub const FILE_CONTEXTS_CANDIDATES: &[&[&str]] = &[
+    &[
+        "system/etc/selinux/plat_file_contexts",
+        "plat_file_contexts",
+    ],
+    &["system_ext/etc/selinux/system_ext_file_contexts"],
+    &["product/etc/selinux/product_file_contexts"],
+    &[
+        "vendor/etc/selinux/vendor_file_contexts",
+        "vendor_file_contexts",
+    ],
+    &["odm/etc/selinux/odm_file_contexts"],
+];
 pub const TMPFS_CANDIDATES: &[&str] = &["/debug_ramdisk", "/patch_hw", "/oem", "/root", "/sbin"];
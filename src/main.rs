@@ -7,51 +7,19 @@ mod try_umount;
 mod utils;
 
 use core::{OryzaEngine, executor, granary, inventory, planner, winnow};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use conf::{
     cli::{Cli, Commands},
     cli_handlers,
-    config::{CONFIG_FILE_DEFAULT, Config},
 };
 use mimalloc::MiMalloc;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-fn load_config(cli: &Cli) -> Result<Config> {
-    if let Some(config_path) = &cli.config {
-        return Config::from_file(config_path).with_context(|| {
-            format!(
-                "Failed to load config from custom path: {}",
-                config_path.display()
-            )
-        });
-    }
-
-    match Config::load_default() {
-        Ok(config) => Ok(config),
-        Err(e) => {
-            let is_not_found = e
-                .root_cause()
-                .downcast_ref::<std::io::Error>()
-                .map(|io_err| io_err.kind() == std::io::ErrorKind::NotFound)
-                .unwrap_or(false);
-
-            if is_not_found {
-                Ok(Config::default())
-            } else {
-                Err(e).context(format!(
-                    "Failed to load default config from {}",
-                    CONFIG_FILE_DEFAULT
-                ))
-            }
-        }
-    }
-}
-
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
@@ -61,12 +29,22 @@ fn main() -> Result<()> {
             Commands::ShowConfig => cli_handlers::handle_show_config(&cli)?,
             Commands::SaveConfig { payload } => cli_handlers::handle_save_config(&cli, payload)?,
             Commands::SaveRules { module, payload } => {
-                cli_handlers::handle_save_rules(module, payload)?
+                cli_handlers::handle_save_rules(&cli, module, payload)?
+            }
+            Commands::Storage { probe, action } => {
+                cli_handlers::handle_storage(&cli, *probe, action.as_ref())?
             }
-            Commands::Storage => cli_handlers::handle_storage()?,
+            Commands::Status => cli_handlers::handle_status(&cli)?,
+            Commands::Unmount { module } => cli_handlers::handle_unmount(&cli, module.as_deref())?,
+            Commands::Remount { partition } => cli_handlers::handle_remount(&cli, partition)?,
             Commands::Modules => cli_handlers::handle_modules(&cli)?,
+            Commands::Module { action } => cli_handlers::handle_module(&cli, action)?,
             Commands::Conflicts => cli_handlers::handle_conflicts(&cli)?,
             Commands::Diagnostics => cli_handlers::handle_diagnostics(&cli)?,
+            Commands::Verify { repair } => cli_handlers::handle_verify(&cli, *repair)?,
+            Commands::Contexts { module } => {
+                cli_handlers::handle_contexts(&cli, module.as_deref())?
+            }
             Commands::SystemAction { action, value } => {
                 cli_handlers::handle_system_action(&cli, action, value.as_deref())?
             }
@@ -75,7 +53,7 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let mut config = load_config(&cli)?;
+    let mut config = cli_handlers::load_config(&cli)?;
 
     config.merge_with_cli(
         cli.moduledir.clone(),
@@ -86,7 +64,7 @@ fn main() -> Result<()> {
     );
 
     if !config.dry_run
-        && let Err(e) = granary::engage_ratoon_protocol()
+        && let Err(e) = granary::engage_ratoon_protocol(&config, &cli.config_path())
     {
         tracing::error!("Failed to engage Ratoon Protocol: {}", e);
     }
@@ -111,10 +89,10 @@ fn main() -> Result<()> {
     let log_path = if config.dry_run {
         None
     } else {
-        Some(Path::new(defs::DAEMON_LOG_FILE))
+        Some(config.daemon_log_file())
     };
 
-    let _log_guard = utils::init_logging(config.verbose, config.dry_run, log_path)
+    let _log_guard = utils::init_logging(config.verbose, config.dry_run, log_path.as_deref())
         .context("Failed to initialize logging")?;
 
     if config.dry_run {
@@ -125,6 +103,11 @@ fn main() -> Result<()> {
 
         tracing::info!(">> Inventory: Found {} modules", module_list.len());
 
+        tracing::info!(
+            ">> SELinux: {} file_contexts specs loaded",
+            core::file_contexts::policy().spec_count()
+        );
+
         let plan = planner::generate(&config, &module_list, &config.moduledir)
             .context("Plan generation failed")?;
 
@@ -157,7 +140,9 @@ fn main() -> Result<()> {
 
         tracing::info!(">> Running System Diagnostics...");
 
-        let issues = executor::diagnose_plan(&plan);
+        let mut issues = executor::diagnose_modules(&module_list);
+
+        issues.extend(executor::diagnose_plan(&plan));
 
         let mut critical_count = 0;
 
@@ -213,13 +198,24 @@ fn main() -> Result<()> {
         tracing::warn!("!! Umount is DISABLED via config.");
     }
 
-    utils::ensure_dir_exists(defs::RUN_DIR)
-        .with_context(|| format!("Failed to create run directory: {}", defs::RUN_DIR))?;
+    let run_dir = config.run_dir();
 
-    let mnt_base = PathBuf::from(&config.hybrid_mnt_dir);
-    let img_path = PathBuf::from(defs::MODULES_IMG_FILE);
+    utils::ensure_dir_exists(&run_dir)
+        .with_context(|| format!("Failed to create run directory: {}", run_dir.display()))?;
 
-    if let Err(e) = granary::create_silo(&config, "Boot Backup", "Automatic Pre-Mount") {
+    if let Err(e) = mount::journal::open(&config.mount_journal_file()) {
+        tracing::warn!("Failed to open mount journal: {:#}", e);
+    }
+
+    let mnt_base = PathBuf::from(&config.hybrid_mnt_dir);
+    let img_path = config.modules_img_file();
+
+    if let Err(e) = granary::create_silo(
+        &config,
+        &cli.config_path(),
+        "Boot Backup",
+        "Automatic Pre-Mount",
+    ) {
         tracing::warn!("Granary: Failed to create boot snapshot: {}", e);
     }
 
@@ -0,0 +1,210 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use rustix::io::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::mount::record::{self, RecordKind};
+
+static JOURNAL: Mutex<Option<File>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalKind {
+    Overlay,
+    Bind,
+    Tmpfs,
+    Move,
+    Image,
+    Unmount,
+    Record,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalResult {
+    Ok,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: u128,
+    pub kind: JournalKind,
+    pub source: String,
+    pub target: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lowerdirs: Vec<String>,
+    pub modules: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record: Option<RecordKind>,
+    pub result: JournalResult,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub errno: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub trait MountError {
+    fn errno(&self) -> Option<i32>;
+    fn describe(&self) -> String;
+}
+
+impl MountError for Errno {
+    fn errno(&self) -> Option<i32> {
+        Some(self.raw_os_error())
+    }
+
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl MountError for anyhow::Error {
+    fn errno(&self) -> Option<i32> {
+        self.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<Errno>()
+                .map(|e| e.raw_os_error())
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<std::io::Error>()
+                        .and_then(|e| e.raw_os_error())
+                })
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("{self:#}")
+    }
+}
+
+impl JournalEntry {
+    pub fn new<S, T>(kind: JournalKind, source: S, target: T) -> Self
+    where
+        S: AsRef<OsStr>,
+        T: AsRef<Path>,
+    {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            kind,
+            source: source.as_ref().to_string_lossy().to_string(),
+            target: target.as_ref().to_path_buf(),
+            lowerdirs: Vec::new(),
+            modules: Vec::new(),
+            record: None,
+            result: JournalResult::Ok,
+            errno: None,
+            error: None,
+        }
+    }
+
+    pub fn with_lowerdirs(mut self, lowerdirs: Vec<String>) -> Self {
+        self.lowerdirs = lowerdirs;
+        self
+    }
+}
+
+pub fn open(path: &Path) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+
+    install(file)
+}
+
+pub fn resume(path: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    install(file)
+}
+
+fn install(file: File) -> Result<()> {
+    *JOURNAL
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Failed to lock mount journal"))? = Some(file);
+
+    Ok(())
+}
+
+pub fn log<T, E>(mut entry: JournalEntry, result: &std::result::Result<T, E>)
+where
+    E: MountError,
+{
+    let modules: BTreeSet<String> = std::iter::once(entry.source.as_str())
+        .chain(entry.lowerdirs.iter().map(String::as_str))
+        .filter_map(record::module_of)
+        .collect();
+
+    entry.modules = modules.into_iter().collect();
+
+    if let Err(e) = result {
+        entry.result = JournalResult::Failed;
+        entry.errno = e.errno();
+        entry.error = Some(e.describe());
+    }
+
+    append(&entry);
+}
+
+pub fn track<P>(kind: RecordKind, target: P, modules: &[String])
+where
+    P: AsRef<Path>,
+{
+    let mut entry = JournalEntry::new(JournalKind::Record, "", target);
+
+    entry.record = Some(kind);
+    entry.modules = modules.to_vec();
+
+    append(&entry);
+}
+
+fn append(entry: &JournalEntry) {
+    let Ok(mut journal) = JOURNAL.lock() else {
+        return;
+    };
+
+    let Some(file) = journal.as_mut() else {
+        return;
+    };
+
+    match serde_json::to_string(entry) {
+        Ok(line) => {
+            if let Err(e) = writeln!(file, "{line}").and_then(|_| file.flush()) {
+                tracing::warn!("Failed to write mount journal: {}", e);
+            }
+        }
+        Err(e) => tracing::warn!("Failed to serialize mount journal entry: {}", e),
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<JournalEntry>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Malformed mount journal entry: {line}"))
+        })
+        .collect()
+}
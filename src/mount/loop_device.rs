@@ -0,0 +1,304 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use nix::errno::Errno;
+use rustix::mount::{MountFlags, mount};
+
+use crate::mount::journal::{self, JournalEntry, JournalKind};
+
+pub const LOOP_CONTROL: &str = "/dev/loop-control";
+const LO_NAME_SIZE: usize = 64;
+const LO_FLAGS_READ_ONLY: u32 = 1;
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_FLAGS_DIRECT_IO: u32 = 16;
+const ATTACH_RETRIES: usize = 8;
+const NODE_WAIT_STEPS: usize = 50;
+const NODE_WAIT_STEP: Duration = Duration::from_millis(10);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; LO_NAME_SIZE],
+    lo_crypt_name: [u8; LO_NAME_SIZE],
+    lo_encrypt_key: [u8; 32],
+    lo_init: [u64; 2],
+}
+
+#[repr(C)]
+struct LoopConfig {
+    fd: u32,
+    block_size: u32,
+    info: LoopInfo64,
+    reserved: [u64; 8],
+}
+
+nix::ioctl_none_bad!(loop_ctl_get_free, 0x4C82);
+nix::ioctl_write_int_bad!(loop_set_fd, 0x4C00);
+nix::ioctl_none_bad!(loop_clr_fd, 0x4C01);
+nix::ioctl_write_ptr_bad!(loop_set_status64, 0x4C04, LoopInfo64);
+nix::ioctl_write_ptr_bad!(loop_configure, 0x4C0A, LoopConfig);
+nix::ioctl_none_bad!(loop_set_capacity, 0x4C07);
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoopOptions {
+    pub read_only: bool,
+    pub direct_io: bool,
+}
+
+pub struct LoopDevice {
+    path: PathBuf,
+    _device: File,
+}
+
+impl LoopInfo64 {
+    fn new(image: &Path, flags: u32) -> Self {
+        let mut lo_file_name = [0u8; LO_NAME_SIZE];
+
+        let name = image.as_os_str().as_bytes();
+
+        let len = name.len().min(LO_NAME_SIZE - 1);
+
+        lo_file_name[..len].copy_from_slice(&name[..len]);
+
+        Self {
+            lo_device: 0,
+            lo_inode: 0,
+            lo_rdevice: 0,
+            lo_offset: 0,
+            lo_sizelimit: 0,
+            lo_number: 0,
+            lo_encrypt_type: 0,
+            lo_encrypt_key_size: 0,
+            lo_flags: flags,
+            lo_file_name,
+            lo_crypt_name: [0; LO_NAME_SIZE],
+            lo_encrypt_key: [0; 32],
+            lo_init: [0; 2],
+        }
+    }
+}
+
+fn node_path(index: i32) -> Option<PathBuf> {
+    [
+        PathBuf::from(format!("/dev/block/loop{}", index)),
+        PathBuf::from(format!("/dev/loop{}", index)),
+    ]
+    .into_iter()
+    .find(|p| p.exists())
+}
+
+fn open_node(index: i32, read_only: bool) -> Result<(PathBuf, File)> {
+    for _ in 0..NODE_WAIT_STEPS {
+        if let Some(path) = node_path(index) {
+            let device = OpenOptions::new()
+                .read(true)
+                .write(!read_only)
+                .open(&path)
+                .with_context(|| format!("Failed to open loop device {}", path.display()))?;
+
+            return Ok((path, device));
+        }
+
+        thread::sleep(NODE_WAIT_STEP);
+    }
+
+    bail!("Loop device node for loop{} did not appear", index)
+}
+
+fn configure_legacy(device: &File, backing: &File, info: &LoopInfo64) -> Result<(), Errno> {
+    unsafe { loop_set_fd(device.as_raw_fd(), backing.as_raw_fd()) }?;
+
+    let mut info = *info;
+
+    info.lo_flags &= !(LO_FLAGS_READ_ONLY | LO_FLAGS_DIRECT_IO);
+
+    if let Err(e) = unsafe { loop_set_status64(device.as_raw_fd(), &info) } {
+        let _ = unsafe { loop_clr_fd(device.as_raw_fd()) };
+
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn configure(
+    device: &File,
+    backing: &File,
+    image: &Path,
+    options: LoopOptions,
+) -> Result<(), Errno> {
+    let mut flags = LO_FLAGS_AUTOCLEAR;
+
+    if options.read_only {
+        flags |= LO_FLAGS_READ_ONLY;
+    }
+
+    if options.direct_io {
+        flags |= LO_FLAGS_DIRECT_IO;
+    }
+
+    let config = LoopConfig {
+        fd: backing.as_raw_fd() as u32,
+        block_size: 0,
+        info: LoopInfo64::new(image, flags),
+        reserved: [0; 8],
+    };
+
+    match unsafe { loop_configure(device.as_raw_fd(), &config) } {
+        Ok(_) => Ok(()),
+        Err(Errno::EINVAL) if options.direct_io => {
+            tracing::debug!(
+                "Direct I/O rejected for {}, retrying buffered",
+                image.display()
+            );
+
+            configure(
+                device,
+                backing,
+                image,
+                LoopOptions {
+                    direct_io: false,
+                    ..options
+                },
+            )
+        }
+        Err(Errno::EINVAL | Errno::ENOTTY) => configure_legacy(device, backing, &config.info),
+        Err(e) => Err(e),
+    }
+}
+
+impl LoopDevice {
+    pub fn attach(image: &Path, options: LoopOptions) -> Result<Self> {
+        let backing = OpenOptions::new()
+            .read(true)
+            .write(!options.read_only)
+            .open(image)
+            .with_context(|| format!("Failed to open image {}", image.display()))?;
+
+        let control = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(LOOP_CONTROL)
+            .with_context(|| format!("Failed to open {}", LOOP_CONTROL))?;
+
+        for _ in 0..ATTACH_RETRIES {
+            let index = unsafe { loop_ctl_get_free(control.as_raw_fd()) }
+                .map_err(io::Error::from)
+                .context("LOOP_CTL_GET_FREE failed")?;
+
+            let (path, device) = open_node(index, options.read_only)?;
+
+            match configure(&device, &backing, image, options) {
+                Ok(()) => {
+                    tracing::debug!("Attached {} to {}", image.display(), path.display());
+
+                    return Ok(Self {
+                        path,
+                        _device: device,
+                    });
+                }
+                Err(Errno::EBUSY) => {
+                    tracing::debug!("{} was claimed concurrently, retrying", path.display());
+                }
+                Err(e) => {
+                    return Err(io::Error::from(e)).with_context(|| {
+                        format!(
+                            "Failed to configure {} for {}",
+                            path.display(),
+                            image.display()
+                        )
+                    });
+                }
+            }
+        }
+
+        bail!(
+            "No free loop device for {} after {} attempts",
+            image.display(),
+            ATTACH_RETRIES
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub fn find_attached(image: &Path) -> Option<PathBuf> {
+    let image = image.canonicalize().ok()?;
+
+    fs::read_dir("/sys/block")
+        .ok()?
+        .flatten()
+        .find_map(|entry| {
+            let name = entry.file_name();
+
+            let index = name.to_str()?.strip_prefix("loop")?.parse::<i32>().ok()?;
+
+            let backing = fs::read_to_string(entry.path().join("loop/backing_file")).ok()?;
+
+            if Path::new(backing.trim()) != image {
+                return None;
+            }
+
+            node_path(index)
+        })
+}
+
+pub fn refresh_capacity(device: &Path) -> Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(device)
+        .with_context(|| format!("Failed to open loop device {}", device.display()))?;
+
+    unsafe { loop_set_capacity(file.as_raw_fd()) }
+        .map_err(io::Error::from)
+        .with_context(|| format!("LOOP_SET_CAPACITY failed on {}", device.display()))?;
+
+    Ok(())
+}
+
+pub fn mount_image(
+    image: &Path,
+    target: &Path,
+    fs_type: &str,
+    flags: MountFlags,
+    options: LoopOptions,
+) -> Result<()> {
+    let result = LoopDevice::attach(image, options).and_then(|device| {
+        mount(device.path(), target, fs_type, flags, None).with_context(|| {
+            format!(
+                "Failed to mount {} ({}) on {}",
+                device.path().display(),
+                fs_type,
+                target.display()
+            )
+        })
+    });
+
+    journal::log(
+        JournalEntry::new(JournalKind::Image, image, target),
+        &result,
+    );
+
+    result
+}
@@ -1,7 +1,7 @@
 mod utils;
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
     sync::atomic::AtomicU32,
@@ -17,8 +17,10 @@ use rustix::mount::{
 use crate::try_umount::send_unmountable;
 use crate::{
     mount::{
+        journal::{self, JournalEntry, JournalKind},
         magic_mount::utils::{clone_symlink, collect_module_files, mount_mirror},
         node::{Node, NodeFileType},
+        record::{self, MountRecord, RecordKind},
     },
     try_umount,
     utils::ensure_dir_exists,
@@ -27,6 +29,16 @@ use crate::{
 static MOUNTDED_FILES: AtomicU32 = AtomicU32::new(0);
 static MOUNTDED_SYMBOLS_FILES: AtomicU32 = AtomicU32::new(0);
 
+fn collect_node_modules(node: &Node, modules: &mut BTreeSet<String>) {
+    if let Some(id) = node.module_path.as_ref().and_then(record::module_of) {
+        modules.insert(id);
+    }
+
+    for child in node.children.values() {
+        collect_node_modules(child, modules);
+    }
+}
+
 struct MagicMount {
     node: Node,
     path: PathBuf,
@@ -113,7 +125,13 @@ impl MagicMount {
             self.work_dir_path.display()
         );
 
-        mount_bind(module_path, target).with_context(|| {
+        let ret = mount_bind(module_path, target);
+        journal::log(
+            JournalEntry::new(JournalKind::Bind, module_path, target),
+            &ret,
+        );
+
+        ret.with_context(|| {
             #[cfg(any(target_os = "linux", target_os = "android"))]
             if self.umount {
                 // tell ksu about this mount
@@ -126,6 +144,14 @@ impl MagicMount {
             )
         })?;
 
+        if !self.has_tmpfs {
+            record::push(MountRecord::new(
+                RecordKind::Bind,
+                target,
+                record::module_of(module_path).into_iter().collect(),
+            ));
+        }
+
         // we should use MS_REMOUNT | MS_BIND | MS_xxx to change mount flags
         if let Err(e) = mount_remount(target, MountFlags::RDONLY | MountFlags::BIND, "") {
             tracing::warn!("make file {} ro: {e:#?}", target.display());
@@ -178,7 +204,13 @@ impl MagicMount {
         }
 
         if tmpfs {
-            mount_bind(&self.work_dir_path, &self.work_dir_path).with_context(|| {
+            let ret = mount_bind(&self.work_dir_path, &self.work_dir_path);
+            journal::log(
+                JournalEntry::new(JournalKind::Bind, &self.work_dir_path, &self.work_dir_path),
+                &ret,
+            );
+
+            ret.with_context(|| {
                 format!(
                     "creating tmpfs for {} at {}",
                     self.path.display(),
@@ -242,13 +274,23 @@ impl MagicMount {
             ) {
                 tracing::warn!("make dir {} ro: {e:#?}", self.path.display());
             }
-            mount_move(&self.work_dir_path, &self.path).with_context(|| {
+            let ret = mount_move(&self.work_dir_path, &self.path);
+            journal::log(
+                JournalEntry::new(JournalKind::Move, &self.work_dir_path, &self.path),
+                &ret,
+            );
+
+            ret.with_context(|| {
                 format!(
                     "moving tmpfs {} -> {}",
                     self.work_dir_path.display(),
                     self.path.display()
                 )
             })?;
+            let mut owners = BTreeSet::new();
+            collect_node_modules(&self.node, &mut owners);
+            record::push(MountRecord::new(RecordKind::Tmpfs, &self.path, owners));
+
             // make private to reduce peer group count
             if let Err(e) = mount_change(&self.path, MountPropagationFlags::PRIVATE) {
                 tracing::warn!("make dir {} private: {e:#?}", self.path.display());
@@ -304,30 +346,40 @@ impl MagicMount {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn magic_mount<P>(
     tmp_path: P,
     module_dir: &Path,
+    system_root: &Path,
     mount_source: &str,
     extra_partitions: &[String],
     need_id: HashSet<String>,
+    scopes: &HashMap<String, Vec<PathBuf>>,
     #[cfg(any(target_os = "linux", target_os = "android"))] umount: bool,
     #[cfg(not(any(target_os = "linux", target_os = "android")))] _umount: bool,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    if let Some(root) = collect_module_files(module_dir, extra_partitions, need_id)? {
+    if let Some(root) =
+        collect_module_files(module_dir, system_root, extra_partitions, need_id, scopes)?
+    {
         tracing::debug!("collected: {root:?}");
         let tmp_root = tmp_path.as_ref();
         let tmp_dir = tmp_root.join("workdir");
         ensure_dir_exists(&tmp_dir)?;
 
-        mount(mount_source, &tmp_dir, "tmpfs", MountFlags::empty(), None).context("mount tmp")?;
+        let ret = mount(mount_source, &tmp_dir, "tmpfs", MountFlags::empty(), None);
+        journal::log(
+            JournalEntry::new(JournalKind::Tmpfs, mount_source, &tmp_dir),
+            &ret,
+        );
+        ret.context("mount tmp")?;
         mount_change(&tmp_dir, MountPropagationFlags::PRIVATE).context("make tmp private")?;
 
         let ret = MagicMount::new(
             &root,
-            Path::new("/"),
+            system_root,
             tmp_dir.as_path(),
             false,
             #[cfg(any(target_os = "linux", target_os = "android"))]
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::{self, DirEntry, Metadata, create_dir, create_dir_all, read_link},
     os::unix::fs::{MetadataExt, symlink},
     path::{Path, PathBuf},
@@ -105,8 +105,10 @@ where
 
 pub fn collect_module_files(
     module_dir: &Path,
+    system_root: &Path,
     extra_partitions: &[String],
     need_id: HashSet<String>,
+    scopes: &HashMap<String, Vec<PathBuf>>,
 ) -> Result<Option<Node>> {
     let mut root = Node::new_root("");
     let mut system = Node::new_root("system");
@@ -169,12 +171,26 @@ pub fn collect_module_files(
 
         tracing::debug!("collecting {}", entry.path().display());
 
+        let scope = scopes.get(&id).map(Vec::as_slice);
+
         for p in partitions {
             if !entry.path().join(&p).exists() {
                 continue;
             }
 
-            has_file.insert(system.collect_module_files(entry.path().join(&p))?);
+            let relative = Path::new(&p);
+            let partition_scope = match scope {
+                Some(s) if s.iter().any(|path| relative.starts_with(path)) => None,
+                Some(s) if s.iter().any(|path| path.starts_with(relative)) => Some(s),
+                Some(_) => continue,
+                None => None,
+            };
+
+            has_file.insert(system.collect_module_files(
+                entry.path().join(&p),
+                relative,
+                partition_scope,
+            )?);
         }
     }
 
@@ -187,8 +203,8 @@ pub fn collect_module_files(
         ];
 
         for (partition, require_symlink) in BUILTIN_PARTITIONS {
-            let path_of_root = Path::new("/").join(partition);
-            let path_of_system = Path::new("/system").join(partition);
+            let path_of_root = system_root.join(partition);
+            let path_of_system = system_root.join("system").join(partition);
             if path_of_root.is_dir() && (!require_symlink || path_of_system.is_symlink()) {
                 let name = partition.to_string();
                 if let Some(node) = system.children.remove(&name) {
@@ -205,8 +221,8 @@ pub fn collect_module_files(
                 continue;
             }
 
-            let path_of_root = Path::new("/").join(partition);
-            let path_of_system = Path::new("/system").join(partition);
+            let path_of_root = system_root.join(partition);
+            let path_of_system = system_root.join("system").join(partition);
             let require_symlink = false;
 
             if path_of_root.is_dir() && (!require_symlink || path_of_system.is_symlink()) {
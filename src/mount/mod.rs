@@ -1,6 +1,9 @@
 // Copyright 2025 Meta-Hybrid Mount Authors
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod journal;
+pub mod loop_device;
 pub mod magic_mount;
 pub mod node;
 pub mod overlayfs;
+pub mod record;
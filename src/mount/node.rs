@@ -57,7 +57,12 @@ impl fmt::Display for Node {
 }
 
 impl Node {
-    pub fn collect_module_files<P>(&mut self, module_dir: P) -> Result<bool>
+    pub fn collect_module_files<P>(
+        &mut self,
+        module_dir: P,
+        relative: &Path,
+        scope: Option<&[PathBuf]>,
+    ) -> Result<bool>
     where
         P: AsRef<Path>,
     {
@@ -65,15 +70,31 @@ impl Node {
         let mut has_file = false;
         for entry in dir.read_dir()?.flatten() {
             let name = entry.file_name().to_string_lossy().to_string();
+            let child_relative = relative.join(&name);
+
+            // a scoped module only contributes the listed subtrees, plus the
+            // directories leading to them
+            let in_scope = scope.is_none_or(|s| s.iter().any(|p| child_relative.starts_with(p)));
+            let on_path = !in_scope
+                && scope.is_some_and(|s| s.iter().any(|p| p.starts_with(&child_relative)));
+
+            if !in_scope && !on_path {
+                continue;
+            }
 
             let node = match self.children.entry(name.clone()) {
                 Entry::Occupied(o) => Some(o.into_mut()),
-                Entry::Vacant(v) => Self::new_module(&name, &entry).map(|it| v.insert(it)),
+                Entry::Vacant(v) => Self::new_module(&name, &entry).map(|mut it| {
+                    it.replace &= in_scope;
+                    v.insert(it)
+                }),
             };
 
             if let Some(node) = node {
                 has_file |= if node.file_type == NodeFileType::Directory {
-                    node.collect_module_files(dir.join(&node.name))? || node.replace
+                    let child_scope = if in_scope { None } else { scope };
+                    node.collect_module_files(dir.join(&node.name), &child_relative, child_scope)?
+                        || node.replace
                 } else {
                     true
                 }
@@ -14,14 +14,15 @@ use crate::defs;
 
 #[allow(dead_code)]
 pub fn mount_systemlessly(
+    module_dir: &Path,
+    system_rw_dir: &Path,
     module_id: HashSet<String>,
     extra_partitions: &[String],
     mount_source: &str,
 ) -> Result<()> {
-    let module_dir = Path::new(defs::MODULES_DIR);
     let dir = module_dir.read_dir();
     let Ok(dir) = dir else {
-        bail!("open {} failed", defs::MODULES_DIR);
+        bail!("open {} failed", module_dir.display());
     };
 
     let mut system_lowerdir: Vec<String> = Vec::new();
@@ -76,12 +77,12 @@ pub fn mount_systemlessly(
         }
     }
 
-    if let Err(e) = mount_partition("system", &system_lowerdir, mount_source) {
+    if let Err(e) = mount_partition("system", &system_lowerdir, system_rw_dir, mount_source) {
         tracing::warn!("mount system failed: {:#}", e);
     }
 
     for (k, v) in partition_lowerdir {
-        if let Err(e) = mount_partition(k.clone(), &v, mount_source) {
+        if let Err(e) = mount_partition(k.clone(), &v, system_rw_dir, mount_source) {
             tracing::warn!("mount {k} failed: {:#}", e);
         }
     }
@@ -90,7 +91,12 @@ pub fn mount_systemlessly(
 }
 
 #[allow(dead_code)]
-fn mount_partition<S>(partition_name: S, lowerdir: &Vec<String>, mount_source: &str) -> Result<()>
+fn mount_partition<S>(
+    partition_name: S,
+    lowerdir: &Vec<String>,
+    system_rw_dir: &Path,
+    mount_source: &str,
+) -> Result<()>
 where
     S: AsRef<str>,
 {
@@ -109,11 +115,10 @@ where
 
     let mut workdir = None;
     let mut upperdir = None;
-    let system_rw_dir = Path::new(defs::SYSTEM_RW_DIR);
     if system_rw_dir.exists() {
         workdir = Some(system_rw_dir.join(partition_name).join("workdir"));
         upperdir = Some(system_rw_dir.join(partition_name).join("upperdir"));
     }
 
-    overlayfs::mount_overlay(&partition, lowerdir, workdir, upperdir, mount_source)
+    overlayfs::mount_overlay(&partition, lowerdir, &[], workdir, upperdir, mount_source)
 }
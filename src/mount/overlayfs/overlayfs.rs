@@ -1,35 +1,218 @@
 use std::{
+    collections::BTreeSet,
     ffi::CString,
-    os::fd::AsFd,
+    os::fd::{AsFd, OwnedFd},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
 use anyhow::{Context, Result, bail};
 use procfs::process::Process;
 use rustix::{
     fs::CWD,
+    io::Errno,
     mount::{
-        FsMountFlags, FsOpenFlags, MountAttrFlags, MountFlags, MoveMountFlags, fsconfig_create,
-        fsconfig_set_string, fsmount, fsopen, mount, move_mount,
+        FsMountFlags, FsOpenFlags, MountAttrFlags, MountFlags, MoveMountFlags, UnmountFlags,
+        fsconfig_create, fsconfig_set_string, fsmount, fsopen, mount, move_mount, unmount,
     },
 };
+use serde::{Deserialize, Serialize};
 
-use crate::mount::overlayfs::utils::umount_dir;
+use crate::mount::{
+    journal::{self, JournalEntry, JournalKind},
+    overlayfs::utils::umount_dir,
+    record::{self, MountRecord, RecordKind},
+};
+
+const OVL_MAX_STACK: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct OverlayFeatures {
+    pub lowerdir_append: bool,
+    pub datadir_append: bool,
+}
+
+fn supports_param(name: &str) -> bool {
+    fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)
+        .and_then(|fs| fsconfig_set_string(fs.as_fd(), name, "/"))
+        .is_ok()
+}
+
+pub fn features() -> OverlayFeatures {
+    static FEATURES: OnceLock<OverlayFeatures> = OnceLock::new();
+
+    *FEATURES.get_or_init(|| OverlayFeatures {
+        lowerdir_append: supports_param("lowerdir+"),
+        datadir_append: supports_param("datadir+"),
+    })
+}
+
+fn escape_layer(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '\\' | ':' | ',') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn lowerdir_config(lowerdirs: &[String], datadirs: &[String]) -> String {
+    let mut config = lowerdirs
+        .iter()
+        .map(|dir| escape_layer(dir))
+        .collect::<Vec<_>>()
+        .join(":");
+    for datadir in datadirs {
+        config.push_str("::");
+        config.push_str(&escape_layer(datadir));
+    }
+    config
+}
+
+fn mount_data_max() -> usize {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    usize::try_from(page_size).unwrap_or(4096) - 1
+}
+
+fn mount_data(
+    lowerdir_config: &str,
+    metacopy: bool,
+    upperdir: Option<&String>,
+    workdir: Option<&String>,
+) -> String {
+    let mut data = format!("lowerdir={lowerdir_config}");
+    if metacopy {
+        data.push_str(",metacopy=on");
+    }
+    if let (Some(upperdir), Some(workdir)) = (upperdir, workdir) {
+        data = format!(
+            "{data},upperdir={},workdir={}",
+            escape_layer(upperdir),
+            escape_layer(workdir)
+        );
+    }
+    data
+}
+
+fn child_mounts(root: &str) -> Result<Vec<String>> {
+    let mounts = Process::myself()?
+        .mountinfo()
+        .with_context(|| "get mountinfo")?;
+    let mut mount_seq = mounts
+        .0
+        .iter()
+        .filter(|m| {
+            m.mount_point.starts_with(root) && !Path::new(&root).starts_with(&m.mount_point)
+        })
+        .filter_map(|m| m.mount_point.to_str().map(str::to_string))
+        .collect::<Vec<_>>();
+    mount_seq.sort();
+    mount_seq.dedup();
+    Ok(mount_seq)
+}
+
+pub fn overlay_fits(
+    root: &str,
+    module_roots: &[String],
+    data_roots: &[String],
+    upperdir: Option<&Path>,
+    workdir: Option<&Path>,
+) -> bool {
+    if module_roots.len() + data_roots.len() + 1 > OVL_MAX_STACK {
+        return false;
+    }
+
+    let features = features();
+    if features.lowerdir_append && (data_roots.is_empty() || features.datadir_append) {
+        return true;
+    }
+
+    let max = mount_data_max();
+    let upperdir = upperdir.map(|p| p.display().to_string());
+    let workdir = workdir.map(|p| p.display().to_string());
+
+    let root_layers = module_roots
+        .iter()
+        .cloned()
+        .chain(std::iter::once(root.to_string()))
+        .collect::<Vec<_>>();
+    let root_config = lowerdir_config(&root_layers, data_roots);
+    let metacopy = !data_roots.is_empty();
+
+    if mount_data(&root_config, metacopy, upperdir.as_ref(), workdir.as_ref()).len() > max {
+        return false;
+    }
+
+    child_mounts(root)
+        .unwrap_or_default()
+        .iter()
+        .all(|mount_point| {
+            let relative = mount_point.replacen(root, "", 1);
+            let child_layers = module_roots
+                .iter()
+                .map(|lower| format!("{lower}{relative}"))
+                .chain(std::iter::once(format!(".{relative}")))
+                .collect::<Vec<_>>();
+            let child_data = data_roots
+                .iter()
+                .map(|data| format!("{data}{relative}"))
+                .collect::<Vec<_>>();
+            let child_config = lowerdir_config(&child_layers, &child_data);
+
+            mount_data(&child_config, metacopy, None, None).len() <= max
+        })
+}
+
+fn create_overlayfs(
+    lowerdirs: &[String],
+    datadirs: &[String],
+    upperdir: Option<&String>,
+    workdir: Option<&String>,
+    mount_source: &str,
+) -> rustix::io::Result<OwnedFd> {
+    let fs = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)?;
+    let fs = fs.as_fd();
+    let features = features();
+    if features.lowerdir_append && (datadirs.is_empty() || features.datadir_append) {
+        for lowerdir in lowerdirs {
+            fsconfig_set_string(fs, "lowerdir+", lowerdir)?;
+        }
+        for datadir in datadirs {
+            fsconfig_set_string(fs, "datadir+", datadir)?;
+        }
+    } else {
+        fsconfig_set_string(fs, "lowerdir", lowerdir_config(lowerdirs, datadirs))?;
+    }
+    if !datadirs.is_empty() {
+        fsconfig_set_string(fs, "metacopy", "on")?;
+    }
+    if let (Some(upperdir), Some(workdir)) = (upperdir, workdir) {
+        fsconfig_set_string(fs, "upperdir", escape_layer(upperdir))?;
+        fsconfig_set_string(fs, "workdir", escape_layer(workdir))?;
+    }
+    fsconfig_set_string(fs, "source", mount_source)?;
+    fsconfig_create(fs)?;
+    fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())
+}
 
 pub fn mount_overlayfs(
     lower_dirs: &[String],
+    data_dirs: &[String],
     lowest: &str,
     upperdir: Option<PathBuf>,
     workdir: Option<PathBuf>,
     dest: impl AsRef<Path>,
     mount_source: &str,
 ) -> Result<()> {
-    let lowerdir_config = lower_dirs
+    let lowerdirs = lower_dirs
         .iter()
-        .map(|s| s.as_ref())
-        .chain(std::iter::once(lowest))
-        .collect::<Vec<_>>()
-        .join(":");
+        .map(|s| s.to_string())
+        .chain(std::iter::once(lowest.to_string()))
+        .collect::<Vec<_>>();
+    let lowerdir_config = lowerdir_config(&lowerdirs, data_dirs);
     tracing::info!(
         "mount overlayfs on {:?}, lowerdir={}, upperdir={:?}, workdir={:?}, source={}",
         dest.as_ref(),
@@ -46,17 +229,14 @@ pub fn mount_overlayfs(
         .filter(|wd| wd.exists())
         .map(|e| e.display().to_string());
 
-    let result = (|| {
-        let fs = fsopen("overlay", FsOpenFlags::FSOPEN_CLOEXEC)?;
-        let fs = fs.as_fd();
-        fsconfig_set_string(fs, "lowerdir", &lowerdir_config)?;
-        if let (Some(upperdir), Some(workdir)) = (&upperdir, &workdir) {
-            fsconfig_set_string(fs, "upperdir", upperdir)?;
-            fsconfig_set_string(fs, "workdir", workdir)?;
-        }
-        fsconfig_set_string(fs, "source", mount_source)?;
-        fsconfig_create(fs)?;
-        let mount = fsmount(fs, FsMountFlags::FSMOUNT_CLOEXEC, MountAttrFlags::empty())?;
+    let result = create_overlayfs(
+        &lowerdirs,
+        data_dirs,
+        upperdir.as_ref(),
+        workdir.as_ref(),
+        mount_source,
+    )
+    .and_then(|mount| {
         move_mount(
             mount.as_fd(),
             "",
@@ -64,22 +244,88 @@ pub fn mount_overlayfs(
             dest.as_ref(),
             MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
         )
-    })();
+    });
 
-    if let Err(e) = result {
+    let result = result.or_else(|e| {
         tracing::warn!("fsopen mount failed: {:#}, fallback to mount", e);
-        let mut data = format!("lowerdir={lowerdir_config}");
-        if let (Some(upperdir), Some(workdir)) = (upperdir, workdir) {
-            data = format!("{data},upperdir={upperdir},workdir={workdir}");
-        }
+        let data = mount_data(
+            &lowerdir_config,
+            !data_dirs.is_empty(),
+            upperdir.as_ref(),
+            workdir.as_ref(),
+        );
+        let data = CString::new(data).map_err(|_| Errno::INVAL)?;
         mount(
             mount_source,
             dest.as_ref(),
             "overlay",
             MountFlags::empty(),
-            Some(CString::new(data)?.as_c_str()),
-        )?;
-    }
+            Some(data.as_c_str()),
+        )
+    });
+
+    journal::log(
+        JournalEntry::new(JournalKind::Overlay, mount_source, dest.as_ref())
+            .with_lowerdirs(lowerdirs),
+        &result,
+    );
+
+    result?;
+    Ok(())
+}
+
+pub fn swap_overlayfs(
+    lowerdirs: &[String],
+    data_dirs: &[String],
+    upperdir: Option<PathBuf>,
+    workdir: Option<PathBuf>,
+    dest: impl AsRef<Path>,
+    mount_source: &str,
+) -> Result<()> {
+    let lowerdir_config = lowerdir_config(lowerdirs, data_dirs);
+    tracing::info!(
+        "swap overlayfs on {:?}, lowerdir={}, upperdir={:?}, workdir={:?}, source={}",
+        dest.as_ref(),
+        lowerdir_config,
+        upperdir,
+        workdir,
+        mount_source
+    );
+
+    let upperdir = upperdir
+        .filter(|up| up.exists())
+        .map(|e| e.display().to_string());
+    let workdir = workdir
+        .filter(|wd| wd.exists())
+        .map(|e| e.display().to_string());
+
+    let result = create_overlayfs(
+        lowerdirs,
+        data_dirs,
+        upperdir.as_ref(),
+        workdir.as_ref(),
+        mount_source,
+    )
+    .and_then(|mount| {
+        move_mount(
+            mount.as_fd(),
+            "",
+            CWD,
+            dest.as_ref(),
+            MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH | MoveMountFlags::MOVE_MOUNT_BENEATH,
+        )
+    });
+
+    journal::log(
+        JournalEntry::new(JournalKind::Overlay, mount_source, dest.as_ref())
+            .with_lowerdirs(lowerdirs.to_vec()),
+        &result,
+    );
+
+    result.with_context(|| format!("failed to mount overlay beneath {:?}", dest.as_ref()))?;
+
+    unmount(dest.as_ref(), UnmountFlags::DETACH)
+        .with_context(|| format!("failed to detach old overlay on {:?}", dest.as_ref()))?;
     Ok(())
 }
 
@@ -90,47 +336,60 @@ pub fn bind_mount(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
         to.as_ref().display()
     );
     use rustix::mount::{OpenTreeFlags, open_tree};
-    match open_tree(
+    let result = match open_tree(
         CWD,
         from.as_ref(),
         OpenTreeFlags::OPEN_TREE_CLOEXEC
             | OpenTreeFlags::OPEN_TREE_CLONE
             | OpenTreeFlags::AT_RECURSIVE,
     ) {
-        Result::Ok(tree) => {
-            move_mount(
-                tree.as_fd(),
-                "",
-                CWD,
-                to.as_ref(),
-                MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
-            )?;
-        }
-        _ => {
-            mount(
-                from.as_ref(),
-                to.as_ref(),
-                "",
-                MountFlags::BIND | MountFlags::REC,
-                None,
-            )?;
-        }
-    }
+        Result::Ok(tree) => move_mount(
+            tree.as_fd(),
+            "",
+            CWD,
+            to.as_ref(),
+            MoveMountFlags::MOVE_MOUNT_F_EMPTY_PATH,
+        ),
+        _ => mount(
+            from.as_ref(),
+            to.as_ref(),
+            "",
+            MountFlags::BIND | MountFlags::REC,
+            None,
+        ),
+    };
+
+    journal::log(
+        JournalEntry::new(JournalKind::Bind, from.as_ref(), to.as_ref()),
+        &result,
+    );
+
+    result?;
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn mount_overlay_child(
     mount_point: &str,
     relative: &String,
     module_roots: &Vec<String>,
+    data_roots: &[String],
     stock_root: &String,
     mount_source: &str,
+    records: &mut Vec<MountRecord>,
+    owners: &BTreeSet<String>,
 ) -> Result<()> {
     if !module_roots
         .iter()
         .any(|lower| Path::new(&format!("{lower}{relative}")).exists())
     {
-        return bind_mount(stock_root, mount_point);
+        bind_mount(stock_root, mount_point)?;
+        records.push(MountRecord::new(
+            RecordKind::Bind,
+            mount_point,
+            owners.clone(),
+        ));
+        return Ok(());
     }
     if !Path::new(&stock_root).is_dir() {
         return Ok(());
@@ -148,8 +407,14 @@ fn mount_overlay_child(
     if lower_dirs.is_empty() {
         return Ok(());
     }
+    let data_dirs: Vec<String> = data_roots
+        .iter()
+        .map(|data| format!("{data}{relative}"))
+        .filter(|data| Path::new(data).is_dir())
+        .collect();
     if let Err(e) = mount_overlayfs(
         &lower_dirs,
+        &data_dirs,
         stock_root,
         None,
         None,
@@ -158,13 +423,25 @@ fn mount_overlay_child(
     ) {
         tracing::warn!("failed: {:#}, fallback to bind mount", e);
         bind_mount(stock_root, mount_point)?;
+        records.push(MountRecord::new(
+            RecordKind::Bind,
+            mount_point,
+            owners.clone(),
+        ));
+        return Ok(());
     }
+    records.push(MountRecord::new(
+        RecordKind::Overlay,
+        mount_point,
+        owners.clone(),
+    ));
     Ok(())
 }
 
 pub fn mount_overlay(
     root: &String,
     module_roots: &Vec<String>,
+    data_roots: &[String],
     workdir: Option<PathBuf>,
     upperdir: Option<PathBuf>,
     mount_source: &str,
@@ -173,26 +450,23 @@ pub fn mount_overlay(
     std::env::set_current_dir(root).with_context(|| format!("failed to chdir to {root}"))?;
     let stock_root = ".";
 
-    let mounts = Process::myself()?
-        .mountinfo()
-        .with_context(|| "get mountinfo")?;
-    let mut mount_seq = mounts
-        .0
-        .iter()
-        .filter(|m| {
-            m.mount_point.starts_with(root) && !Path::new(&root).starts_with(&m.mount_point)
-        })
-        .map(|m| m.mount_point.to_str())
-        .collect::<Vec<_>>();
-    mount_seq.sort();
-    mount_seq.dedup();
+    let mount_seq = child_mounts(root)?;
+
+    mount_overlayfs(
+        module_roots,
+        data_roots,
+        root,
+        upperdir,
+        workdir,
+        root,
+        mount_source,
+    )
+    .with_context(|| "mount overlayfs for root failed")?;
+
+    let owners: BTreeSet<String> = module_roots.iter().flat_map(record::modules_of).collect();
+    let mut records = vec![MountRecord::new(RecordKind::Overlay, root, owners.clone())];
 
-    mount_overlayfs(module_roots, root, upperdir, workdir, root, mount_source)
-        .with_context(|| "mount overlayfs for root failed")?;
     for mount_point in mount_seq.iter() {
-        let Some(mount_point) = mount_point else {
-            continue;
-        };
         let relative = mount_point.replacen(root, "", 1);
         let stock_root: String = format!("{stock_root}{relative}");
         if !Path::new(&stock_root).exists() {
@@ -202,8 +476,11 @@ pub fn mount_overlay(
             mount_point,
             &relative,
             module_roots,
+            data_roots,
             &stock_root,
             mount_source,
+            &mut records,
+            &owners,
         ) {
             tracing::warn!(
                 "failed to mount overlay for child {}: {:#}, revert",
@@ -214,5 +491,24 @@ pub fn mount_overlay(
             bail!(e);
         }
     }
+
+    record::extend(records);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowerdir_config_appends_data_only_layers() {
+        let lowerdirs = vec!["/a:b".to_string(), "/c".to_string()];
+        let datadirs = vec!["/d,e".to_string()];
+
+        assert_eq!(lowerdir_config(&lowerdirs, &datadirs), "/a\\:b:/c::/d\\,e");
+        assert_eq!(
+            mount_data(&lowerdir_config(&lowerdirs, &datadirs), true, None, None),
+            "lowerdir=/a\\:b:/c::/d\\,e,metacopy=on"
+        );
+    }
+}
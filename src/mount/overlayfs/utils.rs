@@ -1,12 +1,15 @@
 #[cfg(any(target_os = "linux", target_os = "android"))]
 use std::fs;
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+use std::{os::unix::fs::PermissionsExt, path::Path};
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use rustix::mount::{UnmountFlags, unmount};
+use rustix::mount::{MountFlags, UnmountFlags, unmount};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::mount::loop_device::{self, LoopOptions};
 
 #[allow(dead_code)]
 pub struct AutoMountExt4 {
@@ -81,17 +84,16 @@ pub fn mount_image(src: &str, target: &str, _autodrop: bool) -> Result<()> {
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
 pub fn mount_ext4(source: impl AsRef<Path>, target: impl AsRef<Path>) -> Result<()> {
-    let status = Command::new("mount")
-        .args(["-t", "ext4", "-o", "loop,rw,noatime"])
-        .arg(source.as_ref())
-        .arg(target.as_ref())
-        .status()
-        .context("Failed to execute mount command")?;
-
-    if !status.success() {
-        return Err(anyhow!("Mount command failed"));
-    }
-    Ok(())
+    loop_device::mount_image(
+        source.as_ref(),
+        target.as_ref(),
+        "ext4",
+        MountFlags::NOATIME,
+        LoopOptions {
+            read_only: false,
+            direct_io: true,
+        },
+    )
 }
 
 #[cfg(any(target_os = "linux", target_os = "android"))]
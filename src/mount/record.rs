@@ -0,0 +1,138 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Component, Path, PathBuf},
+    sync::{LazyLock, Mutex, OnceLock},
+};
+
+use anyhow::Result;
+use rustix::io::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    defs,
+    mount::journal::{self, JournalEntry, JournalKind, JournalResult},
+};
+
+pub static STORAGE_ROOT: OnceLock<PathBuf> = OnceLock::new();
+static MERGED_LAYERS: LazyLock<Mutex<BTreeMap<PathBuf, BTreeSet<String>>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Storage,
+    Overlay,
+    Bind,
+    Tmpfs,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountRecord {
+    pub kind: RecordKind,
+    pub target: PathBuf,
+    pub modules: Vec<String>,
+}
+
+impl MountRecord {
+    pub fn new<P>(kind: RecordKind, target: P, modules: BTreeSet<String>) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            kind,
+            target: target.as_ref().to_path_buf(),
+            modules: modules.into_iter().collect(),
+        }
+    }
+}
+
+pub fn module_of<P>(path: P) -> Option<String>
+where
+    P: AsRef<Path>,
+{
+    let root = STORAGE_ROOT.get()?;
+
+    path.as_ref()
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .find_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .filter(|name| name != defs::WINNOW_LAYER_DIR_NAME && name != defs::MERGED_LAYER_DIR_NAME)
+}
+
+pub fn register_merged_layer<P>(layer: P, modules: BTreeSet<String>)
+where
+    P: AsRef<Path>,
+{
+    if let Ok(mut layers) = MERGED_LAYERS.lock() {
+        layers.insert(layer.as_ref().to_path_buf(), modules);
+    }
+}
+
+pub fn modules_of<P>(path: P) -> Vec<String>
+where
+    P: AsRef<Path>,
+{
+    if let Ok(layers) = MERGED_LAYERS.lock()
+        && let Some((_, modules)) = layers
+            .iter()
+            .find(|(layer, _)| path.as_ref().starts_with(layer))
+    {
+        return modules.iter().cloned().collect();
+    }
+
+    module_of(path).into_iter().collect()
+}
+
+pub fn push(record: MountRecord) {
+    journal::track(record.kind, &record.target, &record.modules);
+}
+
+pub fn extend(batch: Vec<MountRecord>) {
+    batch.into_iter().for_each(push);
+}
+
+pub fn release<P>(target: P)
+where
+    P: AsRef<Path>,
+{
+    journal::log(
+        JournalEntry::new(JournalKind::Unmount, "", target),
+        &Ok::<(), Errno>(()),
+    );
+}
+
+fn replay(entries: Vec<JournalEntry>) -> Vec<MountRecord> {
+    let mut records: Vec<MountRecord> = Vec::new();
+
+    for entry in entries {
+        match (entry.kind, entry.record) {
+            (JournalKind::Record, Some(kind)) => records.push(MountRecord {
+                kind,
+                target: entry.target,
+                modules: entry.modules,
+            }),
+            (JournalKind::Unmount, _)
+                if entry.result == JournalResult::Ok
+                    || matches!(entry.errno, Some(libc::EINVAL | libc::ENOENT)) =>
+            {
+                if let Some(index) = records.iter().rposition(|r| r.target == entry.target) {
+                    records.remove(index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    records
+}
+
+pub fn load(journal_path: &Path) -> Result<Vec<MountRecord>> {
+    Ok(replay(journal::load(journal_path)?))
+}
@@ -1,27 +1,26 @@
 use std::{
-    ffi::CString,
+    collections::BTreeMap,
+    ffi::{CString, OsString},
     fmt as std_fmt,
     fs::{self, File, create_dir_all, remove_dir_all, remove_file, write},
     io::Write,
-    os::unix::{
-        ffi::OsStrExt,
-        fs::{FileTypeExt, MetadataExt, PermissionsExt, symlink},
-    },
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt, lchown, symlink},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::Command,
     sync::{OnceLock, atomic::AtomicBool},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result, bail};
 #[cfg(any(target_os = "linux", target_os = "android"))]
-use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lsetxattr};
+use extattr::{Flags as XattrFlags, lgetxattr, llistxattr, lremovexattr, lsetxattr};
 use procfs::process::Process;
 use regex_lite::Regex;
 use rustix::{
-    fs::ioctl_ficlone,
-    mount::{MountFlags, mount},
+    fs::{AtFlags, CWD, Timespec, Timestamps, ioctl_ficlone, utimensat},
+    mount::{MountFlags, mount, mount_remount},
 };
+use serde::Serialize;
 use tracing::{Event, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
@@ -32,10 +31,17 @@ use tracing_subscriber::{
     util::SubscriberInitExt,
 };
 
-use crate::defs::{self, TMPFS_CANDIDATES};
+use crate::{
+    defs::TMPFS_CANDIDATES,
+    mount::{
+        journal::{self, JournalEntry, JournalKind},
+        loop_device::{self, LoopOptions},
+    },
+};
 
 const SELINUX_XATTR: &str = "security.selinux";
 const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+const CAPABILITY_XATTR: &str = "security.capability";
 const CONTEXT_SYSTEM: &str = "u:object_r:system_file:s0";
 const CONTEXT_VENDOR: &str = "u:object_r:vendor_file:s0";
 const OVERLAY_TEST_XATTR: &str = "trusted.overlay.test";
@@ -232,19 +238,6 @@ fn copy_extended_attributes(src: &Path, dst: &Path) -> Result<()> {
         if let Ok(opaque) = lgetxattr(src, OVERLAY_OPAQUE_XATTR) {
             let _ = lsetxattr(dst, OVERLAY_OPAQUE_XATTR, &opaque, XattrFlags::empty());
         }
-        if let Ok(xattrs) = llistxattr(src) {
-            for xattr_name in xattrs {
-                let name_bytes = xattr_name.as_bytes();
-                let name_str = String::from_utf8_lossy(name_bytes);
-
-                #[allow(clippy::collapsible_if)]
-                if name_str.starts_with("trusted.overlay.") && name_str != OVERLAY_OPAQUE_XATTR {
-                    if let Ok(val) = lgetxattr(src, &xattr_name) {
-                        let _ = lsetxattr(dst, &xattr_name, &val, XattrFlags::empty());
-                    }
-                }
-            }
-        }
     }
     #[cfg(not(any(target_os = "linux", target_os = "android")))]
     {
@@ -271,6 +264,68 @@ pub fn set_overlay_opaque<P: AsRef<Path>>(path: P) -> Result<()> {
     Ok(())
 }
 
+pub fn is_overlay_opaque<P: AsRef<Path>>(path: P) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        lgetxattr(path.as_ref(), OVERLAY_OPAQUE_XATTR).is_ok_and(|v| v == b"y")
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+pub fn file_capability<P: AsRef<Path>>(path: P) -> Option<Vec<u8>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        lgetxattr(path.as_ref(), CAPABILITY_XATTR).ok()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+pub fn extra_xattrs<P: AsRef<Path>>(path: P) -> BTreeMap<OsString, Vec<u8>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let path = path.as_ref();
+
+        llistxattr(path)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|name| {
+                ![SELINUX_XATTR, CAPABILITY_XATTR, OVERLAY_OPAQUE_XATTR]
+                    .iter()
+                    .any(|handled| name == handled)
+            })
+            .filter_map(|name| {
+                let value = lgetxattr(path, &name).ok()?;
+                Some((name, value))
+            })
+            .collect()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = path;
+        BTreeMap::new()
+    }
+}
+
+pub fn clear_overlay_opaque<P: AsRef<Path>>(path: P) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        lremovexattr(path.as_ref(), OVERLAY_OPAQUE_XATTR)?;
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = path;
+    }
+    Ok(())
+}
+
 pub fn lsetfilecon<P: AsRef<Path>>(path: P, con: &str) -> Result<()> {
     #[cfg(any(target_os = "linux", target_os = "android"))]
     {
@@ -316,16 +371,12 @@ pub fn lgetfilecon<P: AsRef<Path>>(_path: P) -> Result<String> {
 }
 
 pub fn copy_path_context<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> Result<()> {
-    let mut context = if src.as_ref().exists() {
-        lgetfilecon(&src).unwrap_or_else(|_| CONTEXT_SYSTEM.to_string())
+    let context = if src.as_ref().exists() {
+        live_context(src.as_ref())
     } else {
         CONTEXT_SYSTEM.to_string()
     };
 
-    if context.contains("u:object_r:rootfs:s0") {
-        context = CONTEXT_SYSTEM.to_string();
-    }
-
     lsetfilecon(dst, &context)
 }
 
@@ -437,16 +488,34 @@ pub fn is_mounted<P: AsRef<Path>>(path: P) -> bool {
 }
 
 pub fn mount_tmpfs(target: &Path, source: &str) -> Result<()> {
+    mount_tmpfs_sized(target, source, None)
+}
+
+pub fn mount_tmpfs_sized(target: &Path, source: &str, size: Option<u64>) -> Result<()> {
     ensure_dir_exists(target)?;
-    let data = CString::new("mode=0755")?;
-    mount(
+    let data = match size {
+        Some(size) => CString::new(format!("mode=0755,size={size}"))?,
+        None => CString::new("mode=0755")?,
+    };
+    let result = mount(
         source,
         target,
         "tmpfs",
         MountFlags::empty(),
         Some(data.as_c_str()),
-    )
-    .context("Failed to mount tmpfs")?;
+    );
+    journal::log(
+        JournalEntry::new(JournalKind::Tmpfs, source, target),
+        &result,
+    );
+    result.context("Failed to mount tmpfs")?;
+    Ok(())
+}
+
+pub fn resize_tmpfs(target: &Path, size: u64) -> Result<()> {
+    let data = CString::new(format!("size={size}"))?;
+    mount_remount(target, MountFlags::empty(), data.as_c_str())
+        .with_context(|| format!("Failed to resize tmpfs at {}", target.display()))?;
     Ok(())
 }
 
@@ -493,7 +562,14 @@ fn make_device_node(path: &Path, mode: u32, rdev: u64) -> Result<()> {
     Ok(())
 }
 
-fn get_context_for_path(path: &Path) -> &'static str {
+#[derive(Debug, Clone, Serialize)]
+pub struct PreserveFailure {
+    pub path: PathBuf,
+    pub attribute: &'static str,
+    pub error: String,
+}
+
+pub fn get_context_for_path(path: &Path) -> &'static str {
     let path_str = path.to_string_lossy();
     if path_str.starts_with("/vendor") || path_str.starts_with("/odm") {
         return CONTEXT_VENDOR;
@@ -501,7 +577,9 @@ fn get_context_for_path(path: &Path) -> &'static str {
     CONTEXT_SYSTEM
 }
 
-fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
+pub type ContextResolver = dyn Fn(&Path, &Path) -> String;
+
+fn apply_system_context(current: &Path, relative: &Path, resolve: &ContextResolver) -> Result<()> {
     if let Some(name) = current.file_name().and_then(|n| n.to_str())
         && (name == "upperdir" || name == "workdir")
         && let Some(parent) = current.parent()
@@ -510,22 +588,26 @@ fn apply_system_context(current: &Path, relative: &Path) -> Result<()> {
         return lsetfilecon(current, &ctx);
     }
 
-    let system_path = Path::new("/").join(relative);
+    lsetfilecon(current, &resolve(current, relative))
+}
 
-    if system_path.exists() {
-        copy_path_context(&system_path, current)?;
-    } else if let Some(parent) = system_path.parent()
-        && parent.exists()
-    {
-        copy_path_context(parent, current)?;
+pub fn live_context(path: &Path) -> String {
+    let context = lgetfilecon(path).unwrap_or_else(|_| CONTEXT_SYSTEM.to_string());
+
+    if context.contains("u:object_r:rootfs:s0") {
+        CONTEXT_SYSTEM.to_string()
     } else {
-        let target_context = get_context_for_path(&system_path);
-        lsetfilecon(current, target_context)?;
+        context
     }
-    Ok(())
 }
 
-fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<()> {
+fn native_cp_r(
+    src: &Path,
+    dst: &Path,
+    relative: &Path,
+    repair: Option<&ContextResolver>,
+    failures: &mut Vec<PreserveFailure>,
+) -> Result<()> {
     if !dst.exists() {
         if src.is_dir() {
             create_dir_all(dst)?;
@@ -534,10 +616,14 @@ fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<
             let _ = fs::set_permissions(dst, src_meta.permissions());
         }
 
-        if repair && relative.as_os_str().is_empty() {
-            let _ = apply_system_context(dst, relative);
-        } else if !repair {
-            let _ = copy_extended_attributes(src, dst);
+        match repair {
+            Some(resolve) if relative.as_os_str().is_empty() => {
+                let _ = apply_system_context(dst, relative, resolve);
+            }
+            Some(_) => {}
+            None => {
+                let _ = copy_extended_attributes(src, dst);
+            }
         }
     }
 
@@ -549,48 +635,191 @@ fn native_cp_r(src: &Path, dst: &Path, relative: &Path, repair: bool) -> Result<
         let next_relative = relative.join(&file_name);
 
         let metadata = entry.metadata()?;
-        let ft = metadata.file_type();
 
-        if ft.is_dir() {
-            native_cp_r(&src_path, &dst_path, &next_relative, repair)?;
-        } else if ft.is_symlink() {
-            if dst_path.exists() {
-                remove_file(&dst_path)?;
-            }
-            let link_target = fs::read_link(&src_path)?;
-            symlink(&link_target, &dst_path)?;
-        } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
-            if dst_path.exists() {
-                remove_file(&dst_path)?;
-            }
-            let mode = metadata.permissions().mode();
-            let rdev = metadata.rdev();
-            make_device_node(&dst_path, mode, rdev)?;
+        if metadata.is_dir() {
+            native_cp_r(&src_path, &dst_path, &next_relative, repair, failures)?;
         } else {
-            reflink_or_copy(&src_path, &dst_path)?;
+            copy_node(&src_path, &dst_path, &metadata)?;
         }
 
-        if repair {
-            let _ = apply_system_context(&dst_path, &next_relative);
+        if let Some(resolve) = repair {
+            let _ = apply_system_context(&dst_path, &next_relative, resolve);
         } else {
             let _ = copy_extended_attributes(&src_path, &dst_path);
         }
+
+        preserve_metadata(&src_path, &dst_path, &next_relative, &metadata, failures);
+    }
+    Ok(())
+}
+
+fn preserve_metadata(
+    src: &Path,
+    dst: &Path,
+    relative: &Path,
+    metadata: &fs::Metadata,
+    failures: &mut Vec<PreserveFailure>,
+) {
+    let mut fail = |attribute: &'static str, error: std::io::Error| {
+        tracing::debug!(
+            "Failed to preserve {} of {}: {}",
+            attribute,
+            dst.display(),
+            error
+        );
+        failures.push(PreserveFailure {
+            path: relative.to_path_buf(),
+            attribute,
+            error: error.to_string(),
+        });
+    };
+
+    if let Err(e) = lchown(dst, Some(metadata.uid()), Some(metadata.gid())) {
+        fail("owner", e);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if metadata.is_file()
+        && let Ok(capability) = lgetxattr(src, CAPABILITY_XATTR)
+        && let Err(e) = lsetxattr(dst, CAPABILITY_XATTR, &capability, XattrFlags::empty())
+    {
+        fail("capability", std::io::Error::from(e));
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let wanted = extra_xattrs(src);
+
+        for name in extra_xattrs(dst).into_keys() {
+            if !wanted.contains_key(&name)
+                && let Err(e) = lremovexattr(dst, &name)
+            {
+                fail("xattrs", std::io::Error::from(e));
+            }
+        }
+
+        for (name, value) in &wanted {
+            if let Err(e) = lsetxattr(dst, name, value, XattrFlags::empty()) {
+                fail("xattrs", std::io::Error::from(e));
+            }
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let _ = src;
+
+    let timestamps = Timestamps {
+        last_access: Timespec {
+            tv_sec: metadata.atime(),
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        last_modification: Timespec {
+            tv_sec: metadata.mtime(),
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    };
+
+    if let Err(e) = utimensat(CWD, dst, &timestamps, AtFlags::SYMLINK_NOFOLLOW) {
+        fail("timestamps", e.into());
+    }
+}
+
+fn copy_node(src_path: &Path, dst_path: &Path, metadata: &fs::Metadata) -> Result<()> {
+    let ft = metadata.file_type();
+
+    if ft.is_symlink() {
+        if dst_path.exists() {
+            remove_file(dst_path)?;
+        }
+        let link_target = fs::read_link(src_path)?;
+        symlink(&link_target, dst_path)?;
+    } else if ft.is_char_device() || ft.is_block_device() || ft.is_fifo() {
+        if dst_path.exists() {
+            remove_file(dst_path)?;
+        }
+        let mode = metadata.permissions().mode();
+        let rdev = metadata.rdev();
+        make_device_node(dst_path, mode, rdev)?;
+    } else {
+        reflink_or_copy(src_path, dst_path)?;
     }
     Ok(())
 }
 
-pub fn sync_dir(src: &Path, dst: &Path, repair_context: bool) -> Result<()> {
+pub fn sync_entry(
+    src_root: &Path,
+    dst_root: &Path,
+    relative: &Path,
+    repair: Option<&ContextResolver>,
+) -> Result<Vec<PreserveFailure>> {
+    let src = src_root.join(relative);
+    let dst = dst_root.join(relative);
+    let metadata = fs::symlink_metadata(&src)?;
+    let existing = fs::symlink_metadata(&dst).ok();
+
+    if metadata.is_dir() {
+        if existing.as_ref().is_some_and(|m| !m.is_dir()) {
+            remove_file(&dst)?;
+        }
+        create_dir_all(&dst)?;
+        fs::set_permissions(&dst, metadata.permissions())?;
+    } else {
+        if let Some(parent) = dst.parent() {
+            create_dir_all(parent)?;
+        }
+        match existing {
+            Some(m) if m.is_dir() => remove_dir_all(&dst)?,
+            Some(_) => remove_file(&dst)?,
+            None => {}
+        }
+        copy_node(&src, &dst, &metadata)?;
+    }
+
+    if let Some(resolve) = repair {
+        let _ = apply_system_context(&dst, relative, resolve);
+    } else {
+        let _ = copy_extended_attributes(&src, &dst);
+    }
+
+    let mut failures = Vec::new();
+    preserve_metadata(&src, &dst, relative, &metadata, &mut failures);
+    Ok(failures)
+}
+
+pub fn sync_dir_metadata(
+    src_root: &Path,
+    dst_root: &Path,
+    relative: &Path,
+) -> Vec<PreserveFailure> {
+    let src = src_root.join(relative);
+    let dst = dst_root.join(relative);
+    let mut failures = Vec::new();
+
+    if let Ok(metadata) = fs::symlink_metadata(&src)
+        && metadata.is_dir()
+        && dst.is_dir()
+    {
+        preserve_metadata(&src, &dst, relative, &metadata, &mut failures);
+    }
+    failures
+}
+
+pub fn sync_dir(
+    src: &Path,
+    dst: &Path,
+    repair_context: Option<&ContextResolver>,
+) -> Result<Vec<PreserveFailure>> {
+    let mut failures = Vec::new();
     if !src.exists() {
-        return Ok(());
+        return Ok(failures);
     }
     ensure_dir_exists(dst)?;
-    native_cp_r(src, dst, Path::new(""), repair_context).with_context(|| {
+    native_cp_r(src, dst, Path::new(""), repair_context, &mut failures).with_context(|| {
         format!(
             "Failed to natively sync {} to {}",
             src.display(),
             dst.display()
         )
-    })
+    })?;
+    Ok(failures)
 }
 
 fn is_ok_empty<P: AsRef<Path>>(dir: P) -> bool {
@@ -602,15 +831,14 @@ fn is_ok_empty<P: AsRef<Path>>(dir: P) -> bool {
         .is_ok_and(|mut entries| entries.next().is_none())
 }
 
-pub fn select_temp_dir() -> Result<PathBuf> {
+pub fn select_temp_dir(system_root: &Path, run_dir: &Path) -> Result<PathBuf> {
     for path_str in TMPFS_CANDIDATES {
-        let path = Path::new(path_str);
-        if is_ok_empty(path) {
+        let path = system_root.join(path_str.trim_start_matches('/'));
+        if is_ok_empty(&path) {
             tracing::info!("Selected dynamic temp root: {}", path.display());
-            return Ok(path.to_path_buf());
+            return Ok(path);
         }
     }
-    let run_dir = Path::new(defs::RUN_DIR);
     ensure_dir_exists(run_dir)?;
     let work_dir = run_dir.join("workdir");
     Ok(work_dir)
@@ -636,72 +864,32 @@ pub fn ensure_temp_dir(temp_dir: &Path) -> Result<()> {
     Ok(())
 }
 
-pub fn is_erofs_supported() -> bool {
-    fs::read_to_string("/proc/filesystems")
-        .map(|content| content.contains("erofs"))
-        .unwrap_or(false)
-}
-
-pub fn create_erofs_image(src_dir: &Path, image_path: &Path) -> Result<()> {
-    let mkfs_bin = Path::new("/data/adb/metamodule/tools/mkfs.erofs");
-    let cmd_name = if mkfs_bin.exists() {
-        mkfs_bin.as_os_str()
-    } else {
-        std::ffi::OsStr::new("mkfs.erofs")
-    };
-
-    tracing::info!("Packing EROFS image: {}", image_path.display());
-
-    let output = Command::new(cmd_name)
-        .arg("-z")
-        .arg("lz4hc")
-        .arg(image_path)
-        .arg(src_dir)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .context("Failed to execute mkfs.erofs")?;
-
-    let log_lines = |bytes: &[u8]| {
-        let s = String::from_utf8_lossy(bytes);
-        for line in s.lines() {
-            if !line.trim().is_empty() {
-                tracing::debug!("{}", line);
-            }
-        }
-    };
-
-    log_lines(&output.stdout);
-    log_lines(&output.stderr);
-
-    if !output.status.success() {
-        bail!("Failed to create EROFS image");
-    }
-
-    tracing::info!("Build Completed.");
-    let _ = fs::set_permissions(image_path, fs::Permissions::from_mode(0o644));
-    lsetfilecon(image_path, "u:object_r:ksu_file:s0")?;
-    Ok(())
-}
-
 pub fn mount_erofs_image(image_path: &Path, target: &Path) -> Result<()> {
     ensure_dir_exists(target)?;
     lsetfilecon(image_path, "u:object_r:ksu_file:s0").ok();
-    let status = Command::new("mount")
-        .args(["-t", "erofs", "-o", "loop,ro,nodev,noatime"])
-        .arg(image_path)
-        .arg(target)
-        .status()
-        .context("Failed to execute mount command for EROFS")?;
-
-    if !status.success() {
-        bail!("EROFS Mount command failed");
-    }
-    Ok(())
+    loop_device::mount_image(
+        image_path,
+        target,
+        "erofs",
+        MountFlags::RDONLY | MountFlags::NODEV | MountFlags::NOATIME,
+        LoopOptions {
+            read_only: true,
+            direct_io: true,
+        },
+    )
 }
 
-pub fn extract_module_id(path: &Path) -> Option<String> {
-    path.parent()
-        .and_then(|p| p.file_name())
-        .map(|s| s.to_string_lossy().to_string())
+pub fn mount_squashfs_image(image_path: &Path, target: &Path) -> Result<()> {
+    ensure_dir_exists(target)?;
+    lsetfilecon(image_path, "u:object_r:ksu_file:s0").ok();
+    loop_device::mount_image(
+        image_path,
+        target,
+        "squashfs",
+        MountFlags::RDONLY | MountFlags::NODEV | MountFlags::NOATIME,
+        LoopOptions {
+            read_only: true,
+            direct_io: true,
+        },
+    )
 }
@@ -181,6 +181,11 @@ export const MockAPI = {
     await delay(300);
     console.log(`[Mock] Winnow rule set: ${path} -> ${moduleId}`);
   },
+
+  async removeWinnowingRule(path: string): Promise<void> {
+    await delay(300);
+    console.log(`[Mock] Winnow rule removed: ${path}`);
+  },
   openLink(url: string): void {
     console.log('[Mock] Opening link:', url);
     window.open(url, '_blank');
@@ -74,6 +74,7 @@ interface AppAPI {
   deleteSilo: (siloId: string) => Promise<void>;
   restoreSilo: (siloId: string) => Promise<void>;
   setWinnowingRule: (path: string, moduleId: string) => Promise<void>;
+  removeWinnowingRule: (path: string) => Promise<void>;
 }
 
 const RealAPI: AppAPI = {
@@ -273,6 +274,12 @@ const RealAPI: AppAPI = {
     const cmd = `${PATHS.BINARY} system-action --action winnow-set --value "${path}:${moduleId}"`;
     const { errno, stderr } = await ksuExec(cmd);
     if (errno !== 0) throw new Error(stderr);
+  },
+  removeWinnowingRule: async (path: string): Promise<void> => {
+    if (!ksuExec) return;
+    const cmd = `${PATHS.BINARY} system-action --action winnow-remove --value "${path}"`;
+    const { errno, stderr } = await ksuExec(cmd);
+    if (errno !== 0) throw new Error(stderr);
   }
 };
 
@@ -8,7 +8,9 @@ export interface GranaryConfig {
   retention_days: number;
 }
 
-export type OverlayMode = 'tmpfs' | 'ext4' | 'erofs';
+export type OverlayMode = 'tmpfs' | 'ext4' | 'erofs' | 'squashfs' | 'auto';
+
+export type ErofsCompression = 'none' | 'lz4';
 
 export interface AppConfig {
   moduledir: string;
@@ -17,6 +19,8 @@ export interface AppConfig {
   hybrid_mnt_dir: string;
   partitions: string[];
   overlay_mode: OverlayMode;
+  erofs_compression?: ErofsCompression;
+  tmpfs_ram_ratio?: number;
   enable_nuke: boolean;
   disable_umount: boolean;
   allow_umount_coexistence: boolean;
@@ -50,7 +54,7 @@ export interface StorageStatus {
   size: string;
   used: string;
   percent: string;
-  type: 'tmpfs' | 'ext4' | 'erofs' | 'unknown' | null;
+  type: 'tmpfs' | 'ext4' | 'erofs' | 'squashfs' | 'unknown' | null;
   error?: string;
   hymofs_available?: boolean;
 }
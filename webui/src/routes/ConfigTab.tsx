@@ -115,7 +115,9 @@ export default function ConfigTab() {
   const MODE_DESCS: Record<OverlayMode, string> = {
     'tmpfs': 'RAM-based. Fastest I/O, reset on reboot.',
     'ext4': 'Loopback image. Persistent, saves RAM.',
-    'erofs': 'Read-only compressed. High performance, space saving.'
+    'erofs': 'Read-only compressed. High performance, space saving.',
+    'squashfs': 'Read-only compressed. For kernels without EROFS.',
+    'auto': 'Probe the kernel and pick the best backend at boot.'
   };
 
   return (
@@ -60,6 +60,7 @@ export default function StatusTab() {
     if (type === 'tmpfs') return 'type-tmpfs';
     if (type === 'ext4') return 'type-ext4';
     if (type === 'erofs') return 'type-erofs';
+    if (type === 'squashfs') return 'type-erofs';
     return '';
   }
 
@@ -16,6 +16,7 @@ use fs_extra::{
 use tempfile::NamedTempFile;
 use zip::{write::FileOptions, CompressionMethod};
 
+mod sandbox;
 mod zip_ext;
 use crate::zip_ext::zip_create_from_directory_with_options;
 
@@ -82,6 +83,16 @@ enum Commands {
         cert: PathBuf,
     },
     Lint,
+    /// Run the full mount pipeline against a fake root in a private mount namespace
+    Sandbox {
+        /// Keep the sandbox directory for inspection
+        #[arg(long)]
+        keep: bool,
+
+        /// Only run scenarios whose name contains this string
+        #[arg(long)]
+        scenario: Option<String>,
+    },
 }
 
 fn main() -> Result<()> {
@@ -100,6 +111,9 @@ fn main() -> Result<()> {
         Commands::Lint => {
             run_clippy(&root)?;
         }
+        Commands::Sandbox { keep, scenario } => {
+            sandbox::run_sandbox(&root, keep, scenario.as_deref())?;
+        }
     }
     Ok(())
 }
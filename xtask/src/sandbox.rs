@@ -0,0 +1,1271 @@
+// Copyright 2025 Meta-Hybrid Mount Authors
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    env,
+    ffi::CString,
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+// Every scenario script runs with BIN, CONFIG, OUT, ROOT, DATA, MODULES and
+// STORAGE exported, against a fixture no other scenario has touched.
+const PRELUDE: &str = r#"
+set -e
+status=0
+chcon -R u:object_r:system_file:s0 "$ROOT" "$MODULES" 2>/dev/null || true
+"#;
+
+const ALPHA_BIG: &str = "alpha packs well when compressed\n";
+
+const WINNOWING: &str = r#"
+[winnowing]
+"/system/**" = "epsilon"
+"/system/etc/h*" = "alpha"
+"/system/media/**" = "zeta"
+"#;
+
+struct Scenario {
+    name: &'static str,
+    overlay_mode: &'static str,
+    mnt_dir: &'static str,
+    config: &'static str,
+    setup: fn(&Fixture) -> Result<()>,
+    script: &'static str,
+    verify: fn(&Fixture, &mut Checks),
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "mount",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: WINNOWING,
+        setup: no_setup,
+        script: r#"
+chown 1000:1000 "$MODULES/alpha/system/etc/alpha.conf" 2>/dev/null || true
+touch -d @1000000000 "$MODULES/alpha/system/etc/alpha.conf"
+"$BIN" -c "$CONFIG" || status=$?
+stat -c '%u:%g %Y' "$MODULES/alpha/system/etc/alpha.conf" "$STORAGE/alpha/system/etc/alpha.conf" > "$OUT/preserved"
+cat /proc/self/mountinfo > "$OUT/mountinfo"
+cp -R "$ROOT" "$OUT/view"
+cp -R "$DATA/run" "$OUT/run"
+"$BIN" -c "$CONFIG" status > "$OUT/status.json" || true
+"$BIN" -c "$CONFIG" storage > "$OUT/storage.json" || true
+"#,
+        verify: verify_mount,
+    },
+    Scenario {
+        name: "verify",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+chmod 600 "$STORAGE/alpha/system/etc/alpha.conf"
+printf 'tampered\n' > "$STORAGE/beta/vendor/etc/beta.xml"
+touch "$STORAGE/alpha/system/etc/stray.conf"
+"$BIN" -c "$CONFIG" verify > "$OUT/verify.json" || true
+"$BIN" -c "$CONFIG" verify --repair > "$OUT/verify_repair.json" || true
+"$BIN" -c "$CONFIG" verify > "$OUT/verify_clean.json" || true
+"#,
+        verify: verify_repair,
+    },
+    Scenario {
+        name: "contexts",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" contexts --module alpha > "$OUT/contexts.json" || true
+"#,
+        verify: verify_contexts,
+    },
+    Scenario {
+        name: "unmount",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" unmount --module beta > "$OUT/unmount_beta.json" || true
+cat /proc/self/mountinfo > "$OUT/mountinfo_partial"
+"$BIN" -c "$CONFIG" unmount > "$OUT/unmount.json" || true
+cat /proc/self/mountinfo > "$OUT/mountinfo_after"
+"#,
+        verify: verify_unmount,
+    },
+    Scenario {
+        name: "live-modules",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" unmount --module beta > /dev/null || true
+mkdir -p "$MODULES/gamma/vendor/etc"
+printf 'id=gamma\nname=gamma\nversion=v1\nversionCode=1\nauthor=sandbox\n' > "$MODULES/gamma/module.prop"
+printf 'gamma\n' > "$MODULES/gamma/vendor/etc/gamma.xml"
+"$BIN" -c "$CONFIG" remount --partition vendor > "$OUT/remount_add.json" || true
+cp -R "$ROOT/vendor" "$OUT/vendor_add"
+touch "$MODULES/beta/disable"
+"$BIN" -c "$CONFIG" remount --partition vendor > "$OUT/remount_swap.json" || true
+cp -R "$ROOT/vendor" "$OUT/vendor_swap"
+"$BIN" -c "$CONFIG" module disable gamma --now > "$OUT/module_disable.json" || true
+cp -R "$ROOT/vendor" "$OUT/vendor_disable"
+printf 'beta2\n' > "$MODULES/beta/vendor/etc/beta.xml"
+"$BIN" -c "$CONFIG" module enable beta --now > "$OUT/module_enable.json" || true
+cp -R "$ROOT/vendor" "$OUT/vendor_enable"
+"#,
+        verify: verify_live_modules,
+    },
+    Scenario {
+        name: "incremental-sync",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: WINNOWING,
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+printf 'alpha2\n' > "$MODULES/alpha/system/etc/alpha.conf"
+rm "$MODULES/epsilon/system/etc/epsilon.conf"
+chown 2000:2000 "$MODULES/alpha/system/etc/alpha.big"
+touch -d @1100000000 "$MODULES/alpha/system/etc"
+"$BIN" -c "$CONFIG" remount --partition system > "$OUT/remount_system.json" || true
+stat -c %Y "$MODULES/alpha/system/etc" "$STORAGE/alpha/system/etc" > "$OUT/dir_mtimes"
+stat -c %u:%g "$STORAGE/alpha/system/etc/alpha.big" > "$OUT/owner_resync"
+cp -R "$ROOT/system" "$OUT/system_remount"
+"#,
+        verify: verify_incremental_sync,
+    },
+    Scenario {
+        name: "tmpfs-growth",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+head -c 83886080 /dev/zero > "$MODULES/alpha/system/etc/alpha.huge"
+"$BIN" -c "$CONFIG" remount --partition system > "$OUT/remount_system.json" || true
+stat -c %s "$STORAGE/alpha/system/etc/alpha.huge" > "$OUT/huge_resync" || true
+"#,
+        verify: verify_tmpfs_growth,
+    },
+    Scenario {
+        name: "tmpfs-budget",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "tmpfs_ram_ratio = 0.0000001\n",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" storage --probe > "$OUT/probe.json" || true
+"#,
+        verify: verify_tmpfs_budget,
+    },
+    Scenario {
+        name: "erofs",
+        overlay_mode: "erofs",
+        mnt_dir: "mnt",
+        config: "erofs_compression = \"lz4\"\n",
+        setup: write_replacing_module,
+        script: r#"
+mknod "$MODULES/eta/system/etc/stock.conf" c 0 0
+"$BIN" -c "$CONFIG" || status=$?
+cat /proc/self/mountinfo > "$OUT/mountinfo"
+cp -R "$ROOT/system" "$OUT/system"
+"$BIN" -c "$CONFIG" verify > "$OUT/verify.json" || true
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+stat -c %y "$DATA/modules.erofs" > "$OUT/image_times"
+"$BIN" -c "$CONFIG" || status=$?
+stat -c %y "$DATA/modules.erofs" >> "$OUT/image_times"
+cp -R "$ROOT/system" "$OUT/system_reused"
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+printf 'alpha2\n' > "$MODULES/alpha/system/etc/alpha.conf"
+"$BIN" -c "$CONFIG" || status=$?
+stat -c %y "$DATA/modules.erofs" >> "$OUT/image_times"
+cp -R "$ROOT/system" "$OUT/system_changed"
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+"#,
+        verify: verify_erofs,
+    },
+    Scenario {
+        name: "squashfs",
+        overlay_mode: "squashfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: write_replacing_module,
+        script: r#"
+mknod "$MODULES/eta/system/etc/stock.conf" c 0 0
+"$BIN" -c "$CONFIG" || status=$?
+cat /proc/self/mountinfo > "$OUT/mountinfo"
+cp -R "$ROOT/system" "$OUT/system"
+"$BIN" -c "$CONFIG" verify > "$OUT/verify.json" || true
+"$BIN" -c "$CONFIG" storage --probe > "$OUT/probe.json" || true
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+rm -f "$DATA/modules.squashfs"
+mkdir "$DATA/modules.squashfs"
+"$BIN" -c "$CONFIG" || status=$?
+cat /proc/self/mountinfo > "$OUT/mountinfo_fallback"
+cp -R "$ROOT/system" "$OUT/system_fallback"
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+rmdir "$DATA/modules.squashfs"
+"#,
+        verify: verify_squashfs,
+    },
+    Scenario {
+        name: "ext4",
+        overlay_mode: "ext4",
+        mnt_dir: "mnt",
+        config: "",
+        setup: no_setup,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+stat -c %s "$DATA/modules.img" > "$OUT/image_sizes"
+mkdir -p "$MODULES/theta/system/etc"
+printf 'id=theta\nname=theta\nversion=v1\nversionCode=1\nauthor=sandbox\n' > "$MODULES/theta/module.prop"
+head -c 157286400 /dev/urandom > "$MODULES/theta/system/etc/theta.bin"
+"$BIN" -c "$CONFIG" || status=$?
+stat -c %s "$DATA/modules.img" >> "$OUT/image_sizes"
+stat -c %s "$STORAGE/theta/system/etc/theta.bin" > "$OUT/theta_size" || true
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+rm -rf "$MODULES/theta"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" storage compact > "$OUT/compact_busy.json" 2>/dev/null || true
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+"$BIN" -c "$CONFIG" storage compact > "$OUT/compact.json" || true
+stat -c %s "$DATA/modules.img" >> "$OUT/image_sizes"
+"#,
+        verify: verify_ext4,
+    },
+    Scenario {
+        name: "long-lowerdirs",
+        overlay_mode: "auto",
+        mnt_dir: "mnt:lower,dirs",
+        config: "",
+        setup: write_wide_modules,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+"$BIN" -c "$CONFIG" storage --probe > "$OUT/probe.json" || true
+cat /proc/self/mountinfo > "$OUT/mountinfo"
+cp -R "$ROOT/system" "$OUT/system"
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+"#,
+        verify: verify_long_lowerdirs,
+    },
+    Scenario {
+        name: "data-layers",
+        overlay_mode: "tmpfs",
+        mnt_dir: "mnt",
+        config: "",
+        setup: write_data_layer_module,
+        script: r#"
+"$BIN" -c "$CONFIG" || status=$?
+cat /proc/self/mountinfo > "$OUT/mountinfo"
+cat "$ROOT/vendor/etc/iota.bin" > "$OUT/iota.bin" || true
+ls -a "$ROOT/vendor" > "$OUT/vendor_ls"
+"$BIN" -c "$CONFIG" remount --partition vendor > "$OUT/remount.json" || true
+cat "$ROOT/vendor/etc/iota.bin" > "$OUT/iota_remount.bin" || true
+"$BIN" -c "$CONFIG" unmount > /dev/null || true
+"#,
+        verify: verify_data_layers,
+    },
+];
+
+struct Fixture {
+    base: PathBuf,
+    fake_root: PathBuf,
+    data_root: PathBuf,
+    module_dir: PathBuf,
+    mnt_dir: PathBuf,
+    out_dir: PathBuf,
+    config: PathBuf,
+}
+
+impl Fixture {
+    fn read(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.out_dir.join(name)).ok()
+    }
+
+    fn json(&self, name: &str) -> Option<Value> {
+        self.read(name).and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    fn lines(&self, name: &str) -> Vec<String> {
+        self.read(name)
+            .map(|s| s.lines().map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    fn mounts(&self, name: &str) -> Vec<MountEntry> {
+        self.read(name)
+            .map(|s| parse_mountinfo(&s))
+            .unwrap_or_default()
+    }
+}
+
+struct MountEntry {
+    mount_point: String,
+    fs_type: String,
+}
+
+#[derive(Default)]
+struct Checks {
+    failures: Vec<String>,
+}
+
+impl Checks {
+    fn check(&mut self, name: &str, ok: bool) {
+        if ok {
+            println!("   [PASS] {name}");
+        } else {
+            println!("   [FAIL] {name}");
+            self.failures.push(name.to_string());
+        }
+    }
+}
+
+pub fn run_sandbox(root: &Path, keep: bool, filter: Option<&str>) -> Result<()> {
+    let bin = build_host_binary(root)?;
+
+    let scenarios: Vec<&Scenario> = SCENARIOS
+        .iter()
+        .filter(|s| filter.is_none_or(|f| s.name.contains(f)))
+        .collect();
+
+    if scenarios.is_empty() {
+        bail!("No sandbox scenario matches {}", filter.unwrap_or_default());
+    }
+
+    let mut failed = Vec::new();
+    for scenario in scenarios {
+        if let Err(e) = run_scenario(&bin, scenario, keep) {
+            println!("   {e:#}");
+            failed.push(scenario.name);
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!(
+            "{} sandbox scenario(s) failed: {}",
+            failed.len(),
+            failed.join(", ")
+        );
+    }
+
+    println!(":: Sandbox checks passed!");
+    Ok(())
+}
+
+fn run_scenario(bin: &Path, scenario: &Scenario, keep: bool) -> Result<()> {
+    let temp = tempfile::Builder::new()
+        .prefix("meta-hybrid-sandbox-")
+        .tempdir()
+        .context("Failed to create sandbox directory")?;
+    let base = temp
+        .path()
+        .canonicalize()
+        .context("Failed to resolve sandbox directory")?;
+
+    println!(
+        ":: [{}] Preparing fake root in {}",
+        scenario.name,
+        base.display()
+    );
+    let fixture = prepare_fixture(&base, scenario)?;
+    (scenario.setup)(&fixture)?;
+
+    let status = unshare_command()
+        .arg("sh")
+        .arg("-c")
+        .arg(format!("{PRELUDE}{}exit $status\n", scenario.script))
+        .env("BIN", bin)
+        .env("CONFIG", &fixture.config)
+        .env("OUT", &fixture.out_dir)
+        .env("ROOT", &fixture.fake_root)
+        .env("DATA", &fixture.data_root)
+        .env("MODULES", &fixture.module_dir)
+        .env("STORAGE", &fixture.mnt_dir)
+        .status()
+        .context("Failed to execute unshare")?;
+
+    let result = if status.success() {
+        let mut checks = Checks::default();
+        (scenario.verify)(&fixture, &mut checks);
+
+        if checks.failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} check(s) failed in {} (modules: {})",
+                checks.failures.len(),
+                scenario.name,
+                fixture.module_dir.display()
+            ))
+        }
+    } else {
+        Err(anyhow!(
+            "meta-hybrid exited with {} in {}",
+            status,
+            scenario.name
+        ))
+    };
+
+    if result.is_err() {
+        dump_daemon_log(&fixture);
+    }
+
+    if keep {
+        let kept = temp.keep();
+        println!(":: Sandbox kept at {}", kept.display());
+    }
+
+    result
+}
+
+fn build_host_binary(root: &Path) -> Result<PathBuf> {
+    println!(":: Compiling Core for host...");
+
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .current_dir(root)
+        .args(["build", "--package", "meta-hybrid"])
+        .status()
+        .context("Failed to run cargo build")?;
+
+    if !status.success() {
+        bail!("Host build of meta-hybrid failed");
+    }
+
+    Ok(root.join("target").join("debug").join("meta-hybrid"))
+}
+
+fn unshare_command() -> Command {
+    let mut cmd = Command::new("unshare");
+
+    // Real root keeps trusted.* xattrs working on tmpfs, which the storage
+    // backend probes for; everyone else needs a user namespace to mount at all.
+    if !is_root() {
+        cmd.args(["--user", "--map-root-user"]);
+    }
+
+    cmd.args(["--mount", "--propagation", "private"]);
+    cmd
+}
+
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "0")
+        .unwrap_or(false)
+}
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn write_module(module_dir: &Path, id: &str, files: &[(&str, &str)]) -> Result<PathBuf> {
+    let module = module_dir.join(id);
+    write_file(
+        &module.join("module.prop"),
+        &format!("id={id}\nname={id}\nversion=v1\nversionCode=1\nauthor=sandbox\n"),
+    )?;
+    for (relative, content) in files {
+        write_file(&module.join(relative), content)?;
+    }
+    Ok(module)
+}
+
+fn prepare_fixture(base: &Path, scenario: &Scenario) -> Result<Fixture> {
+    let fake_root = base.join("root");
+    let data_root = base.join("data");
+    let module_dir = base.join("modules");
+    let mnt_dir = base.join(scenario.mnt_dir);
+    let out_dir = base.join("out");
+
+    write_file(&fake_root.join("system/etc/hosts"), "stock\n")?;
+    write_file(&fake_root.join("system/etc/stock.conf"), "stock\n")?;
+    write_file(&fake_root.join("vendor/etc/vendor.prop"), "stock\n")?;
+    fs::create_dir_all(fake_root.join("product/app"))?;
+    write_file(&fake_root.join("system/app/Stock/Stock.apk"), "stock\n")?;
+    write_file(
+        &fake_root.join("system/etc/permissions/stock.xml"),
+        "stock\n",
+    )?;
+    write_file(&fake_root.join("system/media/bootanimation.zip"), "stock\n")?;
+    write_file(
+        &fake_root.join("system/etc/selinux/plat_file_contexts"),
+        "/system(/.*)?                u:object_r:system_file:s0\n\
+         /system/etc/alpha\\.conf     u:object_r:alpha_file:s0\n\
+         /system/etc(/.*)?\\.conf  -d u:object_r:wrong_file:s0\n",
+    )?;
+
+    write_module(
+        &module_dir,
+        "alpha",
+        &[
+            ("system/etc/hosts", "alpha\n"),
+            ("system/etc/alpha.conf", "alpha\n"),
+            ("system/etc/alpha.big", ALPHA_BIG.repeat(1024).as_str()),
+        ],
+    )?;
+    write_module(&module_dir, "beta", &[("vendor/etc/beta.xml", "beta\n")])?;
+    write_module(
+        &module_dir,
+        "epsilon",
+        &[
+            ("system/etc/hosts", "epsilon\n"),
+            ("system/etc/epsilon.conf", "epsilon\n"),
+        ],
+    )?;
+    let split = write_module(
+        &module_dir,
+        "zeta",
+        &[
+            ("system/app/Zeta/Zeta.apk", "zeta\n"),
+            ("system/etc/permissions/zeta.xml", "zeta\n"),
+            ("system/etc/zeta.conf", "zeta\n"),
+            ("system/media/bootanimation.zip", "zeta\n"),
+        ],
+    )?;
+    write_file(
+        &split.join("hybrid_rules.json"),
+        r#"{"paths":{"system/app":"overlay","system/etc/permissions":"magic","system/media":"ignore"}}"#,
+    )?;
+    let disabled = write_module(
+        &module_dir,
+        "delta",
+        &[("system/etc/delta.conf", "delta\n")],
+    )?;
+    write_file(&disabled.join("disable"), "")?;
+
+    fs::create_dir_all(&data_root)?;
+    fs::create_dir_all(&mnt_dir)?;
+    fs::create_dir_all(&out_dir)?;
+
+    let config = base.join("config.toml");
+    write_file(
+        &config,
+        &format!(
+            r#"moduledir = "{}"
+mountsource = "KSU"
+verbose = true
+partitions = []
+overlay_mode = "{}"
+disable_umount = true
+hybrid_mnt_dir = "{}"
+data_root = "{}"
+system_root = "{}"
+{}"#,
+            module_dir.display(),
+            scenario.overlay_mode,
+            mnt_dir.display(),
+            data_root.display(),
+            fake_root.display(),
+            scenario.config,
+        ),
+    )?;
+
+    Ok(Fixture {
+        base: base.to_path_buf(),
+        fake_root,
+        data_root,
+        module_dir,
+        mnt_dir,
+        out_dir,
+        config,
+    })
+}
+
+fn no_setup(_: &Fixture) -> Result<()> {
+    Ok(())
+}
+
+// The scenario script turns eta's stock.conf into a whiteout with mknod.
+fn write_replacing_module(fixture: &Fixture) -> Result<()> {
+    let eta = write_module(
+        &fixture.module_dir,
+        "eta",
+        &[("system/app/Stock/Eta.apk", "eta\n")],
+    )?;
+    fs::create_dir_all(eta.join("system/etc"))?;
+    write_file(&eta.join("system/app/Stock/.replace"), "")
+}
+
+fn write_wide_modules(fixture: &Fixture) -> Result<()> {
+    for i in 1..=60 {
+        let relative = format!("system/etc/wide_{i:02}.conf");
+        let content = format!("wide{i:02}\n");
+        write_module(
+            &fixture.module_dir,
+            &format!("lowerdir_stack_module_{i:02}"),
+            &[(relative.as_str(), content.as_str())],
+        )?;
+    }
+    Ok(())
+}
+
+// iota.bin is a metacopy stub whose data lives in the module's data-only
+// layer, reachable only through the absolute redirect.
+fn write_data_layer_module(fixture: &Fixture) -> Result<()> {
+    let module = write_module(
+        &fixture.module_dir,
+        "iota",
+        &[
+            ("vendor/etc/iota.bin", "000000000\n"),
+            (".datadir/vendor/blobs/iota.bin", "iota data\n"),
+        ],
+    )?;
+    let stub = module.join("vendor/etc/iota.bin");
+    set_xattr(&stub, "trusted.overlay.metacopy", b"")?;
+    set_xattr(&stub, "trusted.overlay.redirect", b"/blobs/iota.bin")
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(name)?;
+    let ret = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to set {name} on {}", path.display()));
+    }
+    Ok(())
+}
+
+fn parse_mountinfo(content: &str) -> Vec<MountEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (pre, post) = line.split_once(" - ")?;
+            let mount_point = pre.split_whitespace().nth(4)?.replace("\\040", " ");
+            let fs_type = post.split_whitespace().next()?.to_string();
+            Some(MountEntry {
+                mount_point,
+                fs_type,
+            })
+        })
+        .collect()
+}
+
+fn fs_type_at(mounts: &[MountEntry], path: &Path) -> Option<String> {
+    let target = path.display().to_string();
+    mounts
+        .iter()
+        .rev()
+        .find(|m| m.mount_point == target)
+        .map(|m| m.fs_type.clone())
+}
+
+fn string_list(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn mismatches(fixture: &Fixture, name: &str) -> Vec<(String, String, Value)> {
+    fixture
+        .json(name)
+        .and_then(|v| v.get("modules").and_then(|m| m.as_array()).cloned())
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|m| {
+            let id = m["id"].as_str().unwrap_or_default().to_string();
+            m["mismatches"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |e| {
+                    (
+                        id.clone(),
+                        e["path"].as_str().unwrap_or_default().to_string(),
+                        e,
+                    )
+                })
+        })
+        .collect()
+}
+
+fn image_matches_sources(report: Option<&Value>) -> bool {
+    report.is_some_and(|v| {
+        v.get("read_only") == Some(&Value::Bool(true))
+            && v.get("modules")
+                .and_then(|m| m.as_array())
+                .is_some_and(|modules| {
+                    !modules.is_empty()
+                        && modules.iter().all(|m| {
+                            m.get("mismatches")
+                                .and_then(|x| x.as_array())
+                                .is_some_and(|x| x.is_empty())
+                        })
+                })
+    })
+}
+
+fn verify_mount(fixture: &Fixture, checks: &mut Checks) {
+    let mounts = fixture.mounts("mountinfo");
+    let fs_type = |relative: &str| fs_type_at(&mounts, &fixture.fake_root.join(relative));
+    let read_view = |relative: &str| fixture.read(&format!("view/{relative}"));
+
+    checks.check(
+        "storage tmpfs mounted at hybrid_mnt_dir",
+        fs_type_at(&mounts, &fixture.mnt_dir).as_deref() == Some("tmpfs"),
+    );
+    checks.check(
+        "overlay mounted on /system",
+        fs_type("system").as_deref() == Some("overlay"),
+    );
+    checks.check(
+        "overlay mounted on /vendor",
+        fs_type("vendor").as_deref() == Some("overlay"),
+    );
+    checks.check(
+        "untouched /product is not mounted",
+        fs_type("product").is_none(),
+    );
+    checks.check(
+        "module file shadows stock file",
+        read_view("system/etc/hosts").as_deref() == Some("alpha\n"),
+    );
+    checks.check(
+        "winnowing rule overrides higher-precedence module",
+        read_view("system/etc/hosts").as_deref() == Some("alpha\n")
+            && read_view("system/etc/epsilon.conf").as_deref() == Some("epsilon\n"),
+    );
+    checks.check(
+        "split rule mounts a subtree as its own overlay",
+        fs_type("system/app").as_deref() == Some("overlay")
+            && read_view("system/app/Zeta/Zeta.apk").as_deref() == Some("zeta\n")
+            && read_view("system/app/Stock/Stock.apk").as_deref() == Some("stock\n"),
+    );
+    checks.check(
+        "split rule routes a subtree through magic mount",
+        read_view("system/etc/permissions/zeta.xml").as_deref() == Some("zeta\n")
+            && read_view("system/etc/permissions/stock.xml").as_deref() == Some("stock\n")
+            && read_view("system/etc/zeta.conf").as_deref() == Some("zeta\n"),
+    );
+    checks.check(
+        "split rule ignores a subtree even when winnowing targets it",
+        read_view("system/media/bootanimation.zip").as_deref() == Some("stock\n"),
+    );
+    checks.check(
+        "module adds new file",
+        read_view("system/etc/alpha.conf").as_deref() == Some("alpha\n"),
+    );
+    checks.check(
+        "stock file stays visible",
+        read_view("system/etc/stock.conf").as_deref() == Some("stock\n"),
+    );
+    checks.check(
+        "second partition receives module file",
+        read_view("vendor/etc/beta.xml").as_deref() == Some("beta\n"),
+    );
+    checks.check(
+        "disabled module is not mounted",
+        read_view("system/etc/delta.conf").is_none(),
+    );
+    checks.check(
+        "no overlay lands outside the fake root",
+        mounts
+            .iter()
+            .filter(|m| m.fs_type == "overlay")
+            .all(|m| Path::new(&m.mount_point).starts_with(&fixture.base)),
+    );
+
+    let state = fixture.json("run/daemon_state.json");
+
+    checks.check("runtime state written under data_root", state.is_some());
+    checks.check(
+        "runtime state lists overlay modules",
+        string_list(state.as_ref().and_then(|s| s.get("overlay_modules")))
+            == ["alpha", "beta", "epsilon", "zeta"],
+    );
+    checks.check(
+        "runtime state lists magic modules",
+        string_list(state.as_ref().and_then(|s| s.get("magic_modules"))) == ["zeta"],
+    );
+
+    let journal: Vec<Value> = fixture
+        .lines("run/mount_journal.jsonl")
+        .iter()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect();
+    let journaled = |kind: &str, target: &Path| {
+        journal.iter().any(|e| {
+            e.get("kind").and_then(|v| v.as_str()) == Some(kind)
+                && e.get("target").and_then(|v| v.as_str()) == target.to_str()
+                && e.get("result").and_then(|v| v.as_str()) == Some("ok")
+        })
+    };
+
+    checks.check(
+        "journal records the storage tmpfs",
+        journaled("tmpfs", &fixture.mnt_dir),
+    );
+    checks.check(
+        "journal records both partition overlays",
+        journaled("overlay", &fixture.fake_root.join("system"))
+            && journaled("overlay", &fixture.fake_root.join("vendor")),
+    );
+
+    let report = fixture.json("status.json");
+    let statuses = |key: &str| -> Vec<String> {
+        report
+            .as_ref()
+            .and_then(|r| r.get(key))
+            .and_then(|v| v.as_array())
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.get("status").and_then(|s| s.as_str()))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    checks.check(
+        "status reports storage mounted",
+        report
+            .as_ref()
+            .and_then(|r| r.pointer("/storage/status"))
+            .and_then(|v| v.as_str())
+            == Some("mounted"),
+    );
+    checks.check(
+        "status reports every partition mounted",
+        statuses("partitions") == ["mounted", "mounted", "mounted"],
+    );
+    checks.check(
+        "status reports every module mounted",
+        statuses("modules") == ["mounted", "mounted", "mounted", "mounted"],
+    );
+    checks.check(
+        "status reports no unexpected mounts",
+        report
+            .as_ref()
+            .and_then(|r| r.get("unexpected_mounts"))
+            .and_then(|v| v.as_array())
+            .is_some_and(|a| a.is_empty()),
+    );
+
+    let preserved = fixture.lines("preserved");
+
+    checks.check(
+        "sync preserves ownership and timestamps",
+        preserved.len() == 2
+            && preserved[0] == preserved[1]
+            && preserved[0].ends_with(" 1000000000"),
+    );
+
+    let storage = fixture.json("storage.json");
+
+    checks.check(
+        "tmpfs storage is capped at the reported budget",
+        storage.as_ref().is_some_and(|s| {
+            let limit = s.pointer("/tmpfs_budget/limit").and_then(|v| v.as_u64());
+            let payload = s.pointer("/tmpfs_budget/payload").and_then(|v| v.as_u64());
+
+            s.get("type").and_then(|v| v.as_str()) == Some("tmpfs")
+                && limit.is_some_and(|limit| payload.is_some_and(|p| p > 0 && limit > p))
+                && s.get("total_size").and_then(|v| v.as_u64()) == limit
+        }),
+    );
+}
+
+fn verify_repair(fixture: &Fixture, checks: &mut Checks) {
+    let detected = mismatches(fixture, "verify.json");
+    let has = |id: &str, path: &str, attribute: &str| {
+        detected.iter().any(|(m, p, e)| {
+            m == id
+                && p == path
+                && e["attributes"]
+                    .as_array()
+                    .is_some_and(|a| a.contains(&Value::from(attribute)))
+        })
+    };
+
+    checks.check(
+        "verify reports mode, content and extra entry mismatches",
+        detected.len() == 3
+            && has("alpha", "system/etc/alpha.conf", "mode")
+            && has("alpha", "system/etc/stray.conf", "extra")
+            && has("beta", "vendor/etc/beta.xml", "content"),
+    );
+
+    let repaired = mismatches(fixture, "verify_repair.json");
+
+    checks.check(
+        "verify --repair fixes every mismatch",
+        repaired.len() == 3
+            && repaired
+                .iter()
+                .all(|(_, _, e)| e["repaired"] == Value::Bool(true))
+            && mismatches(fixture, "verify_clean.json").is_empty(),
+    );
+}
+
+fn verify_contexts(fixture: &Fixture, checks: &mut Checks) {
+    let labels: Vec<Value> = fixture
+        .json("contexts.json")
+        .and_then(|v| v.get("decisions").and_then(|d| d.as_array()).cloned())
+        .unwrap_or_default();
+    let label = |path: &str| labels.iter().find(|d| d["path"] == path).cloned();
+
+    checks.check(
+        "file_contexts policy labels synced files",
+        label("/system/etc/alpha.conf").is_some_and(|d| {
+            d["context"] == "u:object_r:alpha_file:s0"
+                && d["source"] == "policy"
+                && d["current"] == d["context"]
+        }) && label("/system/etc/hosts")
+            .is_some_and(|d| d["context"] == "u:object_r:system_file:s0")
+            && label("/module.prop").is_some_and(|d| d["source"] != "policy"),
+    );
+}
+
+fn verify_unmount(fixture: &Fixture, checks: &mut Checks) {
+    let partial = fixture.mounts("mountinfo_partial");
+    let after = fixture.mounts("mountinfo_after");
+
+    checks.check(
+        "unmount --module drops only that module's overlay",
+        fs_type_at(&partial, &fixture.fake_root.join("vendor")).is_none()
+            && fs_type_at(&partial, &fixture.fake_root.join("system")).is_some(),
+    );
+    checks.check(
+        "unmount tears down every mount of the session",
+        !after.is_empty()
+            && after
+                .iter()
+                .all(|m| !Path::new(&m.mount_point).starts_with(&fixture.base)),
+    );
+
+    let state: Option<Value> =
+        fs::read_to_string(fixture.data_root.join("run").join("daemon_state.json"))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok());
+
+    checks.check(
+        "unmount clears modules from runtime state",
+        state
+            .as_ref()
+            .and_then(|s| s.get("overlay_modules"))
+            .and_then(|v| v.as_array())
+            .is_some_and(|a| a.is_empty()),
+    );
+}
+
+fn verify_live_modules(fixture: &Fixture, checks: &mut Checks) {
+    let field = |name: &str, key: &str| -> Option<String> {
+        fixture
+            .json(name)
+            .and_then(|v| v.get(key).and_then(|s| s.as_str().map(str::to_string)))
+    };
+    let vendor_file = |dir: &str, file: &str| fixture.read(&format!("{dir}/etc/{file}"));
+
+    checks.check(
+        "remount mounts a partition with a newly added module",
+        field("remount_add.json", "strategy").as_deref() == Some("mount")
+            && vendor_file("vendor_add", "gamma.xml").as_deref() == Some("gamma\n")
+            && vendor_file("vendor_add", "beta.xml").as_deref() == Some("beta\n"),
+    );
+    checks.check(
+        "remount swaps the live overlay after a module is disabled",
+        field("remount_swap.json", "strategy").as_deref() == Some("swap")
+            && vendor_file("vendor_swap", "gamma.xml").as_deref() == Some("gamma\n")
+            && vendor_file("vendor_swap", "beta.xml").is_none()
+            && vendor_file("vendor_swap", "vendor.prop").as_deref() == Some("stock\n"),
+    );
+    checks.check(
+        "module disable --now removes its files live",
+        field("module_disable.json", "status").as_deref() == Some("applied")
+            && vendor_file("vendor_disable", "gamma.xml").is_none()
+            && vendor_file("vendor_disable", "vendor.prop").as_deref() == Some("stock\n"),
+    );
+    checks.check(
+        "module enable --now adds its files live",
+        field("module_enable.json", "status").as_deref() == Some("applied")
+            && vendor_file("vendor_enable", "beta.xml").as_deref() == Some("beta2\n"),
+    );
+    checks.check(
+        "module enable --now reports what it synced",
+        fixture
+            .json("module_enable.json")
+            .and_then(|v| v.pointer("/synced/0").cloned())
+            .is_some_and(|r| {
+                r.get("id") == Some(&json!("beta"))
+                    && r.get("modified") == Some(&json!(["vendor/etc/beta.xml"]))
+            }),
+    );
+}
+
+fn verify_incremental_sync(fixture: &Fixture, checks: &mut Checks) {
+    let remount = fixture.json("remount_system.json");
+    let system_file = |relative: &str| fixture.read(&format!("system_remount/{relative}"));
+    let synced = |id: &str| {
+        remount
+            .as_ref()
+            .and_then(|r| r.get("synced"))
+            .and_then(|s| s.as_array())
+            .and_then(|a| a.iter().find(|e| e.get("id").is_some_and(|v| v == id)))
+            .cloned()
+    };
+
+    checks.check(
+        "remount restores nested overlays of a partition",
+        remount
+            .as_ref()
+            .and_then(|r| r.get("nested"))
+            .is_some_and(|n| n == &json!(["system/app"]))
+            && system_file("app/Zeta/Zeta.apk").as_deref() == Some("zeta\n")
+            && system_file("etc/hosts").as_deref() == Some("alpha\n"),
+    );
+    checks.check(
+        "remount syncs changed module files incrementally",
+        synced("alpha").is_some_and(|r| {
+            r.get("full") == Some(&Value::Bool(false))
+                && r.get("modified")
+                    == Some(&json!(["system/etc/alpha.big", "system/etc/alpha.conf"]))
+        }) && synced("epsilon")
+            .is_some_and(|r| r.get("removed") == Some(&json!(["system/etc/epsilon.conf"])))
+            && system_file("etc/alpha.conf").as_deref() == Some("alpha2\n")
+            && system_file("etc/epsilon.conf").is_none(),
+    );
+    checks.check(
+        "incremental sync keeps directory timestamps",
+        fixture.lines("dir_mtimes") == ["1100000000", "1100000000"],
+    );
+    checks.check(
+        "incremental sync picks up ownership-only changes",
+        fixture.read("owner_resync").as_deref() == Some("2000:2000\n"),
+    );
+}
+
+fn verify_tmpfs_growth(fixture: &Fixture, checks: &mut Checks) {
+    checks.check(
+        "remount grows tmpfs storage before syncing larger modules",
+        fixture.read("huge_resync").as_deref() == Some("83886080\n"),
+    );
+}
+
+fn verify_tmpfs_budget(fixture: &Fixture, checks: &mut Checks) {
+    let probe = fixture.json("probe.json");
+
+    checks.check(
+        "tmpfs mode defers to ext4 when the payload exceeds the RAM budget",
+        probe.as_ref().is_some_and(|p| {
+            p.pointer("/tmpfs_budget/within_budget") == Some(&Value::Bool(false))
+                && string_list(p.pointer("/ranking")) == ["ext4", "tmpfs"]
+        }),
+    );
+}
+
+fn verify_erofs(fixture: &Fixture, checks: &mut Checks) {
+    let system = |view: &str, relative: &str| fixture.read(&format!("{view}/{relative}"));
+    let exists = |relative: &str| fixture.out_dir.join("system").join(relative).exists();
+
+    checks.check(
+        "native EROFS image mounted at hybrid_mnt_dir",
+        fs_type_at(&fixture.mounts("mountinfo"), &fixture.mnt_dir).as_deref() == Some("erofs"),
+    );
+    checks.check(
+        "EROFS storage serves compressed and inline files",
+        system("system", "etc/alpha.big") == Some(ALPHA_BIG.repeat(1024))
+            && system("system", "etc/alpha.conf").as_deref() == Some("alpha\n")
+            && system("system", "etc/hosts").is_some(),
+    );
+    checks.check(
+        "EROFS storage keeps whiteouts and opaque directories",
+        !exists("etc/stock.conf")
+            && exists("etc/alpha.conf")
+            && system("system", "app/Stock/Eta.apk").as_deref() == Some("eta\n")
+            && !exists("app/Stock/Stock.apk"),
+    );
+    checks.check(
+        "EROFS image matches module sources including labels",
+        image_matches_sources(fixture.json("verify.json").as_ref()),
+    );
+
+    let image_times = fixture.lines("image_times");
+
+    checks.check(
+        "unchanged modules reuse the packed EROFS image",
+        image_times.len() == 3
+            && image_times[0] == image_times[1]
+            && system("system_reused", "etc/alpha.conf").as_deref() == Some("alpha\n"),
+    );
+    checks.check(
+        "changed modules repack the EROFS image",
+        image_times.len() == 3
+            && image_times[1] != image_times[2]
+            && system("system_changed", "etc/alpha.conf").as_deref() == Some("alpha2\n"),
+    );
+}
+
+fn verify_squashfs(fixture: &Fixture, checks: &mut Checks) {
+    let system = |relative: &str| fixture.read(&format!("system/{relative}"));
+    let exists = |relative: &str| fixture.out_dir.join("system").join(relative).exists();
+
+    checks.check(
+        "native squashfs image mounted at hybrid_mnt_dir",
+        fs_type_at(&fixture.mounts("mountinfo"), &fixture.mnt_dir).as_deref() == Some("squashfs"),
+    );
+    checks.check(
+        "failed squashfs commit falls back to the next ranked backend",
+        fs_type_at(&fixture.mounts("mountinfo_fallback"), &fixture.mnt_dir).as_deref()
+            == Some("tmpfs")
+            && fixture.read("system_fallback/etc/alpha.conf").as_deref() == Some("alpha\n"),
+    );
+    checks.check(
+        "squashfs storage serves module content, whiteouts and opaque directories",
+        system("etc/alpha.big") == Some(ALPHA_BIG.repeat(1024))
+            && system("etc/alpha.conf").as_deref() == Some("alpha\n")
+            && !exists("etc/stock.conf")
+            && system("app/Stock/Eta.apk").as_deref() == Some("eta\n")
+            && !exists("app/Stock/Stock.apk"),
+    );
+    checks.check(
+        "squashfs image matches module sources including labels",
+        image_matches_sources(fixture.json("verify.json").as_ref()),
+    );
+
+    let probe = fixture.json("probe.json");
+
+    checks.check(
+        "storage probe records the squashfs boot decision",
+        probe.as_ref().is_some_and(|p| {
+            p.pointer("/selected").and_then(|v| v.as_str()) == Some("squashfs")
+                && p.pointer("/backends")
+                    .and_then(|v| v.as_array())
+                    .is_some_and(|b| b.len() == 4)
+        }),
+    );
+}
+
+fn verify_ext4(fixture: &Fixture, checks: &mut Checks) {
+    let sizes: Vec<u64> = fixture
+        .lines("image_sizes")
+        .iter()
+        .filter_map(|l| l.trim().parse().ok())
+        .collect();
+    let compact = fixture.json("compact.json");
+
+    checks.check(
+        "ext4 image grows to fit new module content",
+        sizes.len() == 3
+            && sizes[1] > sizes[0]
+            && fixture
+                .read("theta_size")
+                .is_some_and(|s| s.trim() == "157286400"),
+    );
+    checks.check(
+        "storage compact refuses a mounted ext4 image",
+        fixture
+            .read("compact_busy.json")
+            .is_some_and(|s| s.trim().is_empty()),
+    );
+    checks.check(
+        "storage compact shrinks the ext4 image",
+        sizes.len() == 3
+            && sizes[2] < sizes[1]
+            && compact.as_ref().is_some_and(|c| {
+                c["before"].as_u64() == Some(sizes[1]) && c["after"].as_u64() == Some(sizes[2])
+            }),
+    );
+}
+
+fn verify_long_lowerdirs(fixture: &Fixture, checks: &mut Checks) {
+    let probe = fixture.json("probe.json");
+
+    checks.check(
+        "auto mode prefers tmpfs when modules fit in RAM",
+        probe.as_ref().is_some_and(|p| {
+            p.pointer("/selected").and_then(|v| v.as_str()) == Some("tmpfs")
+                && p.pointer("/ranking/0").and_then(|v| v.as_str()) == Some("tmpfs")
+                && p.pointer("/modules_size")
+                    .and_then(|v| v.as_u64())
+                    .is_some_and(|size| size > 0)
+                && p.pointer("/overlay_mount_api/lowerdir_append")
+                    .is_some_and(Value::is_boolean)
+        }),
+    );
+    checks.check(
+        "long lowerdir stacks stay on overlayfs",
+        fs_type_at(
+            &fixture.mounts("mountinfo"),
+            &fixture.fake_root.join("system"),
+        )
+        .as_deref()
+            == Some("overlay")
+            && (1..=60).all(|i| {
+                fixture.read(&format!("system/etc/wide_{i:02}.conf"))
+                    == Some(format!("wide{i:02}\n"))
+            }),
+    );
+}
+
+fn verify_data_layers(fixture: &Fixture, checks: &mut Checks) {
+    let vendor = fixture.fake_root.join("vendor").display().to_string();
+
+    checks.check(
+        "data-only layer is passed to the vendor overlay",
+        fixture.lines("mountinfo").iter().any(|line| {
+            line.split_whitespace().nth(4) == Some(vendor.as_str())
+                && line.contains(".datadir/vendor")
+                && line.contains("metacopy=on")
+        }),
+    );
+    checks.check(
+        "metacopy stub reads its data from the data-only layer",
+        fixture.read("iota.bin").as_deref() == Some("iota data\n"),
+    );
+    checks.check(
+        "data-only layer stays out of the merged tree",
+        !fixture
+            .lines("vendor_ls")
+            .iter()
+            .any(|name| name == "blobs"),
+    );
+    checks.check(
+        "remount keeps the data-only layer",
+        fixture.read("iota_remount.bin").as_deref() == Some("iota data\n"),
+    );
+}
+
+fn dump_daemon_log(fixture: &Fixture) {
+    let log = fixture.data_root.join("daemon.log");
+    if let Ok(content) = fs::read_to_string(&log) {
+        println!(":: Daemon log ({}):", log.display());
+        for line in content.lines() {
+            println!("   {line}");
+        }
+    }
+}